@@ -0,0 +1,274 @@
+//! Async GPMDP client for tokio, enabled with the `async` feature.
+//!
+//! ```no_run
+//! # async fn example() -> gpmdp_rc::Result<()> {
+//! use futures_util::StreamExt;
+//!
+//! let client = gpmdp_rc::AsyncGpmdpClient::connect("ws://127.0.0.1:5672").await?;
+//! client.authenticate("<auth_token>").await?;
+//! let volume = client.call("volume", "getVolume", "").await?;
+//! println!("volume: {}", volume["value"]);
+//!
+//! let mut events = Box::pin(client.events());
+//! while let Some(event) = events.next().await {
+//!     println!("{:?}", event);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use futures_util::{SinkExt, Stream, StreamExt};
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio_tungstenite::tungstenite::Message;
+use crate::client::{check_response, connect_request, method_request, Channels, RequestIds,
+                    TIMEOUT_MSECS};
+use crate::error::*;
+use crate::events::*;
+
+#[derive(Default)]
+struct Inner
+{
+    channels: Channels,
+    listeners: Vec<mpsc::UnboundedSender<ChannelEvent>>,
+    requests: RequestIds,
+    closed: bool,
+    error: Option<GpmdpError>,
+}
+
+#[derive(Default)]
+struct Shared
+{
+    inner: Mutex<Inner>,
+    notify: Notify,
+}
+
+impl Shared
+{
+    fn lock(&self) -> MutexGuard<'_, Inner>
+    {
+        return self.inner.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    fn handle_message(&self, text: &str)
+    {
+        let js: serde_json::Value = match serde_json::from_str(text) {
+            Ok(js) => js,
+            Err(_err) => return,
+        };
+
+        let mut inner = self.lock();
+
+        if let Some(channel) = js["channel"].as_str() {
+            if let Some(event) = inner.channels.handle(channel, &js["payload"]) {
+                inner.listeners.retain(|tx| tx.send(event.clone()).is_ok());
+            }
+        } else if let Some(request_id) = js["requestID"].as_u64() {
+            let handler = inner.requests.complete(request_id as u32);
+            drop(inner);
+            if let Some(handler) = handler {
+                handler(js);
+            }
+            return;
+        }
+
+        drop(inner);
+        self.notify.notify_waiters();
+    }
+
+    fn set_closed(&self, error: Option<GpmdpError>)
+    {
+        let mut inner = self.lock();
+        inner.closed = true;
+        if inner.error.is_none() {
+            inner.error = error;
+        }
+        let pending = inner.requests.abandon_all();
+        inner.listeners.clear();
+        drop(inner);
+        drop(pending);
+        self.notify.notify_waiters();
+    }
+
+    fn closed_error(&self) -> GpmdpError
+    {
+        return closed_error(&self.lock());
+    }
+}
+
+fn closed_error(inner: &Inner) -> GpmdpError
+{
+    return inner.error.clone().unwrap_or_else(|| {
+        GpmdpError::Connection("connection closed".to_string())
+    });
+}
+
+/*
+ * A call in flight. Dropping it forgets the response handler, also when
+ * the call's future is dropped before the response came in.
+ */
+struct PendingCall<'a>
+{
+    shared: &'a Shared,
+    request_id: u32,
+}
+
+impl Drop for PendingCall<'_>
+{
+    fn drop(&mut self)
+    {
+        self.shared.lock().requests.complete(self.request_id);
+    }
+}
+
+/// Async client for the GPMDP playback API.
+///
+/// Reading and writing the websocket happens on spawned tokio tasks, so
+/// connect() must be called from within a tokio runtime.
+pub struct AsyncGpmdpClient
+{
+    tx: mpsc::UnboundedSender<Message>,
+    shared: Arc<Shared>,
+    timeout: Duration,
+}
+
+impl AsyncGpmdpClient
+{
+    /// Open a websocket connection to GPMDP at 'url' (e.g. ws://127.0.0.1:5672).
+    pub async fn connect(url: &str) -> Result<AsyncGpmdpClient>
+    {
+        let (stream, _) = tokio_tungstenite::connect_async(url).await
+            .map_err(|e| GpmdpError::Connection(e.to_string()))?;
+        let (mut sink, mut stream) = stream.split();
+
+        let shared = Arc::new(Shared::default());
+        let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                if sink.send(msg).await.is_err() {
+                    break;
+                }
+            }
+            let _ = sink.close().await;
+        });
+
+        let reader_shared = shared.clone();
+        tokio::spawn(async move {
+            let mut error = None;
+            while let Some(msg) = stream.next().await {
+                match msg {
+                    Ok(Message::Text(text)) => reader_shared.handle_message(&text),
+                    Ok(Message::Close(_)) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        error = Some(GpmdpError::Connection(e.to_string()));
+                        break;
+                    }
+                }
+            }
+            reader_shared.set_closed(error);
+        });
+
+        return Ok(AsyncGpmdpClient {
+            tx,
+            shared,
+            timeout: Duration::from_millis(TIMEOUT_MSECS),
+        });
+    }
+
+    /// Change how long calls wait for GPMDP before timing out.
+    pub fn set_timeout(&mut self, timeout: Duration)
+    {
+        self.timeout = timeout;
+    }
+
+    fn send(&self, req: String) -> Result<()>
+    {
+        return self.tx.send(Message::text(req))
+            .map_err(|_| self.shared.closed_error());
+    }
+
+    /// Authenticate this connection with a previously issued token.
+    pub async fn authenticate(&self, token: &str) -> Result<()>
+    {
+        return self.send(connect_request(&[token]));
+    }
+
+    /// Wait until every channel in 'chnls' (CHNL_* bits) has been pushed.
+    pub async fn wait_for_channels(&self, chnls: u64) -> Result<()>
+    {
+        let wait = async {
+            loop {
+                let notified = self.shared.notify.notified();
+                {
+                    let inner = self.shared.lock();
+                    if let Some(res) = inner.channels.check(chnls) {
+                        return res;
+                    }
+                    if inner.closed {
+                        return Err(closed_error(&inner));
+                    }
+                }
+                notified.await;
+            }
+        };
+
+        return match tokio::time::timeout(self.timeout, wait).await {
+            Ok(res) => res,
+            Err(_elapsed) => Err(GpmdpError::Timeout(channel_list(chnls))),
+        };
+    }
+
+    /// Copy the player state, as GPMDP's channel pushes have left it.
+    pub fn state(&self) -> PlayerState
+    {
+        return self.shared.lock().channels.state.clone();
+    }
+
+    /// Stream of every channel event from now on. The stream ends when the
+    /// connection closes.
+    pub fn events(&self) -> impl Stream<Item = ChannelEvent>
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut inner = self.shared.lock();
+        if !inner.closed {
+            inner.listeners.push(tx);
+        }
+
+        return futures_util::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|event| (event, rx))
+        });
+    }
+
+    /// Invoke 'namespace.method' with a JSON array of 'arguments' (or an
+    /// empty string for none) and return the raw response. Any number of
+    /// calls can be in flight at once.
+    pub async fn call(&self,
+                      namespace: &str,
+                      method: &str,
+                      arguments: &str) -> Result<serde_json::Value>
+    {
+        let (tx, rx) = oneshot::channel();
+        let request_id = self.shared.lock().requests.register(Box::new(move |js| {
+            let _ = tx.send(js);
+        }));
+        let _pending = PendingCall { shared: &self.shared, request_id };
+
+        return match self.send(method_request(request_id, namespace, method, arguments)) {
+            Ok(()) => match tokio::time::timeout(self.timeout, rx).await {
+                Ok(Ok(js)) => check_response(js),
+                Ok(Err(_closed)) => Err(self.shared.closed_error()),
+                Err(_elapsed) => Err(GpmdpError::Timeout(format!("{}.{}", namespace, method))),
+            },
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Close the connection.
+    pub async fn close(self)
+    {
+        let _ = self.tx.send(Message::Close(None));
+    }
+}
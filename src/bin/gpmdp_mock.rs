@@ -0,0 +1,68 @@
+#![allow(clippy::needless_return)]
+
+extern crate getopts;
+extern crate gpmdp_rc;
+
+use std::env;
+use std::fs;
+use getopts::Options;
+use gpmdp_rc::error::*;
+use gpmdp_rc::mock::*;
+
+fn get_script(file: &str) -> Result<MockScript>
+{
+    let s = fs::read_to_string(file).map_err(|e| {
+        GpmdpError::Config(format!("failed to read script {}: {}", file, e))
+    })?;
+    return serde_json::from_str(&s).map_err(|e| {
+        GpmdpError::Config(format!("failed to parse script {}: {}", file, e))
+    });
+}
+
+fn usage(cmd: &str)
+{
+    println!("Usage: {} [ -a <addr:port> ] [ -s <script.json> ]", cmd);
+    println!("  -a, --addr    address to listen on (default 127.0.0.1:5672)");
+    println!("  -s, --script  JSON with 'token', 'code', 'channels' and 'responses'");
+    println!("                overriding the built in defaults");
+}
+
+fn main()
+{
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print usage");
+    opts.optopt("a", "addr", "listen address", "ADDR");
+    opts.optopt("s", "script", "script file", "SCRIPT");
+    let options = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(f) => {
+            println!("ERROR: {}", f);
+            usage(&args[0]);
+            std::process::exit(2);
+        }
+    };
+
+    if options.opt_present("h") {
+        usage(&args[0]);
+        std::process::exit(0);
+    }
+
+    let script = match options.opt_str("s") {
+        Some(file) => get_script(&file),
+        None => Ok(MockScript::default()),
+    };
+    let addr = options.opt_str("a").unwrap_or_else(|| "127.0.0.1:5672".to_string());
+
+    let server = match script.and_then(|script| MockServer::bind(&addr, script)) {
+        Ok(server) => server,
+        Err(e) => {
+            println!("ERROR: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+
+    println!("listening on {}", server.url());
+    server.wait();
+}
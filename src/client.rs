@@ -0,0 +1,1141 @@
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::error::*;
+use crate::events::*;
+use crate::models::*;
+use crate::transcript::*;
+
+/// Name this client registers itself under with GPMDP.
+pub const APP_NAME: &str = "gpmdp_rc";
+
+/// How long to wait for channel pushes or a response before giving up.
+pub const TIMEOUT_MSECS: u64 = 4000; // 4secs
+
+/*
+ * x volume.getVolume()               0-100
+ * x volume.setVolume(num)            0-100
+ * x volume.increaseVolume(amount)    default=5
+ * x volume.decreaseVolume(amount)    default=5
+ *
+ *   playback.getCurrentTime()        current track progress in ms
+ * x playback.setCurrentTime(ms)      set track progress to ms
+ *   playback.getTotalTime()          total track time in ms
+ *   playback.isPlaying()             is current track playing
+ *   playback.getCurrentTrack()       get current track metadata
+ * x playback.playPause()             toggle between play/pause state
+ * x playback.getPlaybackState()      0=stopped, 1=paused, 2=playing
+ * x playback.forward()               next track
+ * x playback.rewind()                previous track
+ * x playback.getShuffle()            ALL_SHUFFLE, NO_SHUFFLE
+ * x playback.setShuffle(mode)        ALL_SHUFFLE, NO_SHUFFLE
+ *   playback.toggleShuffle()         toggle shuffle active/inactive
+ * x playback.getRepeat()             LIST_REPEAT, SINGLE_REPEAT, NO_REPEAT
+ * x playback.setRepeat(mode)         LIST_REPEAT, SINGLE_REPEAT, NO_REPEAT
+ *   playback.toggleRepeat()          NO_REPEAT -> LIST_REPEAST -> SINGLE_REPEAT
+ * x playback.isPodcast()             is current track a podcast
+ * x playback.forwardThirty()         podcasts only
+ * x playback.rewindTen()             podcasts only
+ *
+ * x rating.getRating()               current rack rating (0-5)
+ *                                    0 = no rating, 1 = thumbs down, 5 = thumbs up
+ * x rating.toggleThumbsUp()          if Down then it's removed, else toggle Up
+ * x rating.toggleThumbsDown()        if Up then it's removed, else toggle Down
+ * x rating.setRating(rating)         set current track rating (1-5)
+ * x rating.resetRating()             removes current track rating
+ *
+ * x playlists.getAll()               Playlist[]
+ * x playlists.play(Playlist)         Playlist object returned from getAll()
+ * x playlists.playWithTrack(Playlist, Track)  Track from Playlist data
+ *
+ * x queue.clear()
+ * x queue.getTracks()                Track[]
+ * x queue.playTrack(Track)           Track object returned from getTracks()
+ *
+ *   search.getCurrentResults()
+ *   search.getSearchText()
+ *   search.isSearching()
+ * x search.performSearch(text)
+ * x search.playResult(result)        Artist,Album,Track from getCurrentResults()
+ *
+ * Album {
+ *   "id": String,           // Unique ID for this album
+ *   "name": String,         // The name of the album
+ *   "artist": String,       // The name of the artist for the album
+ *   "albumArt": String,     // URL to the albumArt for this album
+ *   "tracks": Track[]
+ * }
+ *
+ * Artist {
+ *   "id": String,           // Unique ID for this artist
+ *   "name": String,         // The name of the artist
+ *   "image": String,        // URL to an image of this artist
+ *   "albums": Album[]
+ * }
+ *
+ * Playlist {
+ *   "id": String,           // Unique ID for this playlist
+ *   "name": String,         // User defined name for this playlist
+ *   "tracks": Track[],      // An array of Track objects that make up the playlist
+ * }
+ *
+ * SearchResults {
+ *   "searchText": String,   // search text used to get these results
+ *   "bestMatch": {
+ *     "type": String,       // Best results, one of Album, Artist, or Track
+ *     "value": Album | Artist | Track
+ *   }
+ *   "albums": Album[],      // An array of albums
+ *   "artists": Artist[],    // An array of artists
+ *   "tracks": Track[],      // An array of tracks
+ * }
+ *
+ * Track {
+ *   "id":
+ *   "title":
+ *   "albumArt":
+ *   "artist":
+ *   "album":
+ *   "albumArtist":
+ *   "index":
+ *   "duration":
+ *   "playCount":
+ *   "albumId":
+ *   "artistId":
+ *   "artistImage":
+ * }
+ */
+
+/// Called with the response to a request, on the websocket thread.
+pub type CompletionHandler = Box<dyn FnOnce(serde_json::Value) + Send>;
+
+/*
+ * Hands out requestIDs, skipping any that are still waiting for a response.
+ */
+#[derive(Default)]
+pub(crate) struct RequestIds
+{
+    next: u32,
+    pending: HashMap<u32, CompletionHandler>,
+}
+
+impl RequestIds
+{
+    pub(crate) fn register(&mut self, handler: CompletionHandler) -> u32
+    {
+        loop {
+            self.next = self.next.wrapping_add(1);
+            if self.next != 0 && !self.pending.contains_key(&self.next) {
+                break;
+            }
+        }
+
+        self.pending.insert(self.next, handler);
+        return self.next;
+    }
+
+    pub(crate) fn complete(&mut self, request_id: u32) -> Option<CompletionHandler>
+    {
+        return self.pending.remove(&request_id);
+    }
+
+    /// Forget every pending request, e.g. when the connection is lost.
+    pub(crate) fn abandon_all(&mut self) -> HashMap<u32, CompletionHandler>
+    {
+        return std::mem::take(&mut self.pending);
+    }
+}
+
+/*
+ * Channel bookkeeping common to the blocking and async clients.
+ */
+#[derive(Default)]
+pub(crate) struct Channels
+{
+    pub(crate) state: PlayerState,
+    pub(crate) auth_token: Option<String>,
+    /// GPMDP answered a connect with CODE_REQUIRED.
+    pub(crate) code_required: bool,
+    /// CHNL_* bits of channels whose last payload failed to decode.
+    chnls_failed: u64,
+    decode_error: Option<GpmdpError>,
+}
+
+impl Channels
+{
+    /// Decode a channel push and fold it into the state.
+    pub(crate) fn handle(&mut self,
+                         channel: &str,
+                         payload: &serde_json::Value) -> Option<ChannelEvent>
+    {
+        let event = match ChannelEvent::decode(channel, payload) {
+            Ok(event) => event,
+            Err(e) => {
+                self.chnls_failed |= channel_bit(channel);
+                self.decode_error = Some(e);
+                return None;
+            }
+        };
+
+        if let ChannelEvent::Connect(ref token) = event {
+            if token == "CODE_REQUIRED" {
+                self.code_required = true;
+            } else {
+                self.auth_token = Some(token.clone());
+            }
+        }
+
+        self.chnls_failed &= !event.channel_bit();
+        self.state.apply(&event);
+        return Some(event);
+    }
+
+    /// None until every channel in 'chnls' has been received or one of
+    /// them failed to decode.
+    pub(crate) fn check(&self, chnls: u64) -> Option<Result<()>>
+    {
+        if self.code_required {
+            return Some(Err(GpmdpError::Auth(
+                "auth token rejected, run 'auth' to get a new one".to_string())));
+        }
+        if (self.chnls_failed & chnls) != 0 {
+            return self.decode_error.clone().map(Err);
+        }
+        if (self.state.chnls_rcvd & chnls) == chnls { Some(Ok(())) } else { None }
+    }
+}
+
+#[derive(Default)]
+struct Inner
+{
+    channels: Channels,
+    listeners: Vec<mpsc::Sender<ChannelEvent>>,
+    requests: RequestIds,
+    opened: bool,
+    closed: bool,
+    error: Option<GpmdpError>,
+    /// The isPodcast answer and the id of the track it's for.
+    podcast: Option<(Option<String>, Result<bool>)>,
+}
+
+impl Inner
+{
+    fn closed_error(&self) -> GpmdpError
+    {
+        return self.error.clone().unwrap_or_else(|| {
+            GpmdpError::Connection("connection closed".to_string())
+        });
+    }
+}
+
+/// Connection bookkeeping shared between the websocket thread and the
+/// GpmdpClient handle.
+#[derive(Default)]
+struct Shared
+{
+    inner: Mutex<Inner>,
+    cond: Condvar,
+    recorder: Option<Recorder>,
+}
+
+impl Shared
+{
+    fn lock(&self) -> MutexGuard<'_, Inner>
+    {
+        return self.inner.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    fn handle_message(&self, text: &str)
+    {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::In, text);
+        }
+
+        let js: serde_json::Value = match serde_json::from_str(text) {
+            Ok(js) => js,
+            Err(_err) => return,
+        };
+
+        let mut inner = self.lock();
+
+        if let Some(channel) = js["channel"].as_str() {
+            if let Some(event) = inner.channels.handle(channel, &js["payload"]) {
+                inner.listeners.retain(|tx| tx.send(event.clone()).is_ok());
+            }
+        } else if let Some(request_id) = js["requestID"].as_u64() {
+            let handler = inner.requests.complete(request_id as u32);
+            drop(inner); // the handler may call back into the client
+            if let Some(handler) = handler {
+                handler(js);
+            }
+            return;
+        }
+
+        self.cond.notify_all();
+    }
+
+    fn set_closed(&self, error: Option<GpmdpError>)
+    {
+        let mut inner = self.lock();
+        inner.closed = true;
+        if inner.error.is_none() {
+            inner.error = error;
+        }
+        // dropping the handlers wakes up everyone waiting on a response
+        // and dropping the listeners ends their subscriptions
+        let pending = inner.requests.abandon_all();
+        inner.listeners.clear();
+        drop(inner);
+        drop(pending);
+        self.cond.notify_all();
+    }
+
+    /*
+     * Block until 'ready' returns a value or the timeout expires. The
+     * condition is re-evaluated every time a message arrives.
+     */
+    fn wait_for<T, F>(&self,
+                      timeout: Duration,
+                      what: &str,
+                      mut ready: F) -> Result<T>
+        where F: FnMut(&mut Inner) -> Option<T>
+    {
+        let deadline = Instant::now() + timeout;
+        let mut inner = self.lock();
+
+        loop {
+            if let Some(value) = ready(&mut inner) {
+                return Ok(value);
+            }
+
+            if inner.closed {
+                return Err(inner.closed_error());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(GpmdpError::Timeout(what.to_string()));
+            }
+
+            inner = match self.cond.wait_timeout(inner, deadline - now) {
+                Ok((inner, _)) => inner,
+                Err(e) => e.into_inner().0,
+            };
+        }
+    }
+}
+
+/*
+ * ws::Error's Display goes through the deprecated description() so build
+ * the message from its parts instead.
+ */
+pub(crate) fn ws_error_str(err: &ws::Error) -> String
+{
+    return match err.kind {
+        ws::ErrorKind::Io(ref e) if err.details.is_empty() => e.to_string(),
+        ws::ErrorKind::Io(ref e) => format!("{}: {}", err.details, e),
+        _ => err.details.to_string(),
+    };
+}
+
+/*
+ * Build a 'connect.connect' message. GPMDP takes the app name followed by
+ * either an auth token, a pairing code or nothing to request a code.
+ */
+pub(crate) fn connect_request(arguments: &[&str]) -> String
+{
+    let mut args = vec![APP_NAME];
+    args.extend_from_slice(arguments);
+    let req = serde_json::json!({
+        "namespace": "connect",
+        "method": "connect",
+        "arguments": args,
+    });
+    return req.to_string();
+}
+
+/*
+ * Build a 'namespace.method' call. 'arguments' is a JSON array or an empty
+ * string for none.
+ */
+pub(crate) fn method_request(request_id: u32,
+                             namespace: &str,
+                             method: &str,
+                             arguments: &str) -> String
+{
+    let mut req = String::new();
+    if arguments.is_empty() {
+        req.push_str(&format!(r#"{{ "{}":"{}", "{}":"{}", "{}":{} }}"#,
+                              "namespace", namespace,
+                              "method", method,
+                              "requestID", request_id));
+    } else {
+        req.push_str(&format!(r#"{{ "{}":"{}", "{}":"{}", "{}":{}, "{}":{} }}"#,
+                              "namespace", namespace,
+                              "method", method,
+                              "requestID", request_id,
+                              "arguments", arguments));
+    }
+    return req;
+}
+
+/*
+ * Encode method arguments as the JSON array GPMDP expects.
+ */
+pub(crate) fn json_args<T: Serialize>(args: &[T]) -> Result<String>
+{
+    return serde_json::to_string(args)
+        .map_err(|e| GpmdpError::InvalidArgument(e.to_string()));
+}
+
+/*
+ * Pass a raw response through unless GPMDP reported an error.
+ */
+pub(crate) fn check_response(js: serde_json::Value) -> Result<serde_json::Value>
+{
+    if js["type"] == "error" {
+        let msg = match js["value"].as_str() {
+            Some(msg) => msg.to_string(),
+            None => js["value"].to_string(),
+        };
+        return Err(GpmdpError::Server(msg));
+    }
+
+    return Ok(js);
+}
+
+/*
+ * The "value" of a response.
+ */
+pub(crate) fn response_value(js: &serde_json::Value) -> Result<serde_json::Value>
+{
+    return match js.get("value") {
+        Some(value) => Ok(value.clone()),
+        None => Err(GpmdpError::Decode(
+            format!("no value in {}.{} response",
+                    js["namespace"].as_str().unwrap_or(""),
+                    js["method"].as_str().unwrap_or("")))),
+    };
+}
+
+struct Handler
+{
+    shared: Arc<Shared>,
+}
+
+impl ws::Handler for Handler
+{
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()>
+    {
+        let mut inner = self.shared.lock();
+        inner.opened = true;
+        self.shared.cond.notify_all();
+        return Ok(());
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()>
+    {
+        if let Ok(text) = msg.as_text() {
+            self.shared.handle_message(text);
+        }
+        return Ok(());
+    }
+
+    fn on_close(&mut self, _code: ws::CloseCode, _reason: &str)
+    {
+        self.shared.set_closed(None);
+    }
+
+    fn on_error(&mut self, err: ws::Error)
+    {
+        self.shared.set_closed(Some(GpmdpError::Connection(ws_error_str(&err))));
+    }
+}
+
+/*
+ * Where requests go: the websocket, or the thread playing back a
+ * transcript (None tells it to stop).
+ */
+enum Transport
+{
+    Ws(ws::Sender),
+    Replay(mpsc::Sender<Option<String>>),
+}
+
+/// A request that has been sent and not yet answered.
+pub struct PendingRequest
+{
+    shared: Arc<Shared>,
+    request_id: u32,
+    what: String,
+    rx: mpsc::Receiver<serde_json::Value>,
+    timeout: Duration,
+}
+
+impl PendingRequest
+{
+    pub fn request_id(&self) -> u32
+    {
+        return self.request_id;
+    }
+
+    /// Block until the raw response arrives.
+    pub fn wait(self) -> Result<serde_json::Value>
+    {
+        return match self.rx.recv_timeout(self.timeout) {
+            Ok(js) => check_response(js),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                Err(GpmdpError::Timeout(self.what.clone()))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(self.shared.lock().closed_error())
+            }
+        };
+    }
+
+    /// Block until the response arrives and return its "value".
+    pub fn wait_value(self) -> Result<serde_json::Value>
+    {
+        let js = self.wait()?;
+        return response_value(&js);
+    }
+}
+
+impl Drop for PendingRequest
+{
+    fn drop(&mut self)
+    {
+        // forget the handler if we gave up before the response came in
+        self.shared.lock().requests.complete(self.request_id);
+    }
+}
+
+/// Blocking client for the GPMDP playback API.
+///
+/// The websocket runs on a background thread. Channel pushes are folded
+/// into the player state state() returns, and every method blocks until
+/// GPMDP answers.
+pub struct GpmdpClient
+{
+    out: Transport,
+    shared: Arc<Shared>,
+    thread: Option<thread::JoinHandle<()>>,
+    timeout: Duration,
+}
+
+impl GpmdpClient
+{
+    /// Open a websocket connection to GPMDP at 'url' (e.g. ws://127.0.0.1:5672).
+    pub fn connect(url: &str) -> Result<GpmdpClient>
+    {
+        return GpmdpClient::open(url, None);
+    }
+
+    /// Like connect() but every frame sent and received is written to
+    /// 'recorder'.
+    pub fn connect_recorded(url: &str, recorder: Recorder) -> Result<GpmdpClient>
+    {
+        return GpmdpClient::open(url, Some(recorder));
+    }
+
+    /// A client that talks to a recorded transcript instead of GPMDP. The
+    /// requests made must match the ones in the transcript, otherwise the
+    /// connection is closed with an error.
+    pub fn replay(frames: Vec<Frame>) -> GpmdpClient
+    {
+        let shared = Arc::new(Shared::default());
+        let (tx, rx) = mpsc::channel::<Option<String>>();
+
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || {
+            let mut replay = Replay::new(frames);
+            thread_shared.lock().opened = true;
+
+            loop {
+                for msg in replay.inbound() {
+                    thread_shared.handle_message(&msg);
+                }
+                let sent = match rx.recv() {
+                    Ok(Some(sent)) => sent,
+                    _ => break,
+                };
+                if let Err(e) = replay.outbound(&sent) {
+                    thread_shared.set_closed(Some(e));
+                    return;
+                }
+            }
+            thread_shared.set_closed(None);
+        });
+
+        return GpmdpClient {
+            out: Transport::Replay(tx),
+            shared,
+            thread: Some(thread),
+            timeout: Duration::from_millis(TIMEOUT_MSECS),
+        };
+    }
+
+    fn open(url: &str, recorder: Option<Recorder>) -> Result<GpmdpClient>
+    {
+        let shared = Arc::new(Shared { recorder, ..Shared::default() });
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let url = url.to_string();
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || {
+            let handler_shared = thread_shared.clone();
+            let res = ws::connect(url, |out| {
+                let _ = tx.send(out);
+                Handler { shared: handler_shared.clone() }
+            });
+            thread_shared.set_closed(res.err().map(|e| {
+                GpmdpError::Connection(ws_error_str(&e))
+            }));
+        });
+
+        let timeout = Duration::from_millis(TIMEOUT_MSECS);
+        let opened = shared.wait_for(timeout, "connection", |inner| {
+            if inner.opened { Some(()) } else { None }
+        });
+
+        let out = match rx.recv_timeout(Duration::from_millis(0)) {
+            Ok(out) => out,
+            Err(_err) => {
+                let _ = thread.join();
+                return Err(opened.err().unwrap_or_else(|| {
+                    GpmdpError::Connection("failed to connect".to_string())
+                }));
+            }
+        };
+
+        let client = GpmdpClient {
+            out: Transport::Ws(out),
+            shared,
+            thread: Some(thread),
+            timeout,
+        };
+
+        opened?;
+        return Ok(client);
+    }
+
+    /// Change how long calls wait for GPMDP before timing out.
+    pub fn set_timeout(&mut self, timeout: Duration)
+    {
+        self.timeout = timeout;
+    }
+
+    fn send(&self, req: String) -> Result<()>
+    {
+        if let Some(recorder) = &self.shared.recorder {
+            recorder.record(Direction::Out, &req);
+        }
+
+        return match &self.out {
+            Transport::Ws(out) => {
+                out.send(req).map_err(|e| GpmdpError::Connection(ws_error_str(&e)))
+            }
+            Transport::Replay(tx) => {
+                tx.send(Some(req)).map_err(|_| self.shared.lock().closed_error())
+            }
+        };
+    }
+
+    fn send_connect(&self, arguments: &[&str]) -> Result<()>
+    {
+        return self.send(connect_request(arguments));
+    }
+
+    /// Authenticate this connection with a previously issued token.
+    pub fn authenticate(&self, token: &str) -> Result<()>
+    {
+        return self.send_connect(&[token]);
+    }
+
+    /// Ask GPMDP to display a 4-digit pairing code.
+    pub fn request_auth_code(&self) -> Result<()>
+    {
+        self.shared.lock().channels.auth_token = None;
+        self.send_connect(&[])?;
+
+        // GPMDP answers with CODE_REQUIRED once the code is on screen
+        return self.shared.wait_for(self.timeout, "pairing code", |inner| {
+            if inner.channels.code_required {
+                inner.channels.code_required = false;
+                Some(())
+            } else {
+                None
+            }
+        });
+    }
+
+    /// Send the pairing code shown by GPMDP and return the permanent token.
+    pub fn submit_auth_code(&self, code: &str) -> Result<String>
+    {
+        {
+            let mut inner = self.shared.lock();
+            inner.channels.auth_token = None;
+            inner.channels.code_required = false;
+        }
+        self.send_connect(&[code])?;
+        return self.shared.wait_for(self.timeout, "auth token", |inner| {
+            if inner.channels.code_required {
+                inner.channels.code_required = false;
+                return Some(Err(GpmdpError::Auth("invalid pairing code".to_string())));
+            }
+            inner.channels.auth_token.take().map(Ok)
+        })?;
+    }
+
+    /// Wait until every channel in 'chnls' (CHNL_* bits) has been pushed.
+    pub fn wait_for_channels(&self, chnls: u64) -> Result<()>
+    {
+        return self.shared.wait_for(self.timeout, &channel_list(chnls), |inner| {
+            inner.channels.check(chnls)
+        })?;
+    }
+
+    /// Why the connection closed, None while it's open.
+    pub fn close_reason(&self) -> Option<GpmdpError>
+    {
+        let inner = self.shared.lock();
+        return if inner.closed { Some(inner.closed_error()) } else { None };
+    }
+
+    /// Copy the player state, as GPMDP's channel pushes have left it.
+    pub fn state(&self) -> PlayerState
+    {
+        return self.shared.lock().channels.state.clone();
+    }
+
+    /// Send 'namespace.method' with a JSON array of 'arguments' (or an
+    /// empty string for none) under a fresh requestID. 'handler' is called
+    /// with the raw response once it arrives.
+    pub fn call_with(&self,
+                     namespace: &str,
+                     method: &str,
+                     arguments: &str,
+                     handler: CompletionHandler) -> Result<u32>
+    {
+        let request_id = self.shared.lock().requests.register(handler);
+        let req = method_request(request_id, namespace, method, arguments);
+
+        if let Err(e) = self.send(req) {
+            self.shared.lock().requests.complete(request_id);
+            return Err(e);
+        }
+
+        return Ok(request_id);
+    }
+
+    /// Start 'namespace.method' without waiting for the response. Any
+    /// number of requests can be in flight at once.
+    pub fn send_request(&self,
+                        namespace: &str,
+                        method: &str,
+                        arguments: &str) -> Result<PendingRequest>
+    {
+        let (tx, rx) = mpsc::channel();
+        let request_id = self.call_with(namespace, method, arguments,
+                                        Box::new(move |js| {
+                                            let _ = tx.send(js);
+                                        }))?;
+        return Ok(PendingRequest {
+            shared: self.shared.clone(),
+            request_id,
+            what: format!("{}.{}", namespace, method),
+            rx,
+            timeout: self.timeout,
+        });
+    }
+
+    /// Receive every channel event from now on. The receiver is dropped
+    /// from the listener list once it goes away and disconnects when the
+    /// connection closes.
+    pub fn subscribe(&self) -> mpsc::Receiver<ChannelEvent>
+    {
+        let (tx, rx) = mpsc::channel();
+        self.shared.lock().listeners.push(tx);
+        return rx;
+    }
+
+    /// Invoke 'namespace.method' and wait for the raw response.
+    pub fn call(&self,
+                namespace: &str,
+                method: &str,
+                arguments: &str) -> Result<serde_json::Value>
+    {
+        return self.send_request(namespace, method, arguments)?.wait();
+    }
+
+    fn call_value(&self,
+                  namespace: &str,
+                  method: &str,
+                  arguments: &str) -> Result<serde_json::Value>
+    {
+        return self.send_request(namespace, method, arguments)?.wait_value();
+    }
+
+    pub fn get_playback_state(&self) -> Result<PlaybackState>
+    {
+        let value = self.call_value("playback", "getPlaybackState", "")?;
+        return match value.as_u64() {
+            Some(n) => Ok(PlaybackState::from_u64(n)),
+            None => Err(GpmdpError::Decode(format!("invalid playback state {}", value))),
+        };
+    }
+
+    pub fn play_pause(&self) -> Result<()>
+    {
+        return self.call("playback", "playPause", "").map(|_| ());
+    }
+
+    pub fn forward(&self) -> Result<()>
+    {
+        return self.call("playback", "forward", "").map(|_| ());
+    }
+
+    pub fn rewind(&self) -> Result<()>
+    {
+        return self.call("playback", "rewind", "").map(|_| ());
+    }
+
+    /// Set the progress of the current track in ms.
+    pub fn set_current_time(&self, ms: u64) -> Result<()>
+    {
+        return self.call("playback", "setCurrentTime",
+                         &format!("[{}]", ms)).map(|_| ());
+    }
+
+    /// Move the current track forward (or backward if negative) by
+    /// 'offset_ms', clamped to the track boundaries.
+    pub fn seek(&self, offset_ms: i64) -> Result<()>
+    {
+        self.wait_for_channels(CHNL_TIME)?;
+        let state = self.state();
+
+        let mut seek_to = state.cur_track_progress as i64 + offset_ms;
+        if seek_to > state.cur_track_total as i64 {
+            seek_to = state.cur_track_total as i64;
+        } else if seek_to < 0 {
+            seek_to = 0;
+        }
+
+        return self.set_current_time(seek_to as u64);
+    }
+
+    /// Whether the current track is a podcast episode. GPMDP is asked once
+    /// per track, its answer or failure is kept until the track changes.
+    pub fn is_podcast(&self) -> Result<bool>
+    {
+        let track_id = self.state().cur_track.id;
+        if let Some((id, podcast)) = &self.shared.lock().podcast {
+            if *id == track_id {
+                return podcast.clone();
+            }
+        }
+
+        let podcast = self.call_value("playback", "isPodcast", "").and_then(|value| {
+            return match value.as_bool() {
+                Some(podcast) => Ok(podcast),
+                None => Err(GpmdpError::Decode(format!("invalid isPodcast value {}", value))),
+            };
+        });
+        self.shared.lock().podcast = Some((track_id, podcast.clone()));
+        return podcast;
+    }
+
+    /// Skip 30 secs ahead in a podcast episode.
+    pub fn forward_thirty(&self) -> Result<()>
+    {
+        return self.call("playback", "forwardThirty", "").map(|_| ());
+    }
+
+    /// Go 10 secs back in a podcast episode.
+    pub fn rewind_ten(&self) -> Result<()>
+    {
+        return self.call("playback", "rewindTen", "").map(|_| ());
+    }
+
+    pub fn toggle_thumbs_up(&self) -> Result<()>
+    {
+        return self.call("rating", "toggleThumbsUp", "").map(|_| ());
+    }
+
+    pub fn toggle_thumbs_down(&self) -> Result<()>
+    {
+        return self.call("rating", "toggleThumbsDown", "").map(|_| ());
+    }
+
+    /// Give the current track a thumbs up, leaving it alone if it already
+    /// has one.
+    pub fn thumbs_up(&self) -> Result<()>
+    {
+        self.wait_for_channels(CHNL_RATING)?;
+        let state = self.state();
+        if state.cur_track_liked {
+            return Ok(());
+        }
+        if state.cur_track_disliked {
+            // the first toggle only removes the thumbs down
+            self.toggle_thumbs_up()?;
+        }
+        return self.toggle_thumbs_up();
+    }
+
+    /// Give the current track a thumbs down, leaving it alone if it
+    /// already has one.
+    pub fn thumbs_down(&self) -> Result<()>
+    {
+        self.wait_for_channels(CHNL_RATING)?;
+        let state = self.state();
+        if state.cur_track_disliked {
+            return Ok(());
+        }
+        if state.cur_track_liked {
+            // the first toggle only removes the thumbs up
+            self.toggle_thumbs_down()?;
+        }
+        return self.toggle_thumbs_down();
+    }
+
+    /// Rating of the current track: 0 for none, 1 (thumbs down) to 5
+    /// (thumbs up).
+    pub fn get_rating(&self) -> Result<u32>
+    {
+        let value = self.call_value("rating", "getRating", "")?;
+        let rating = match &value {
+            serde_json::Value::String(s) => s.parse::<u32>().ok(),
+            value => value.as_u64().map(|n| n as u32),
+        };
+        return match rating {
+            Some(n) if n <= 5 => Ok(n),
+            _ => Err(GpmdpError::Decode(format!("invalid rating {}", value))),
+        };
+    }
+
+    /// Rate the current track 1-5.
+    pub fn set_rating(&self, rating: u32) -> Result<()>
+    {
+        if !(1..=5).contains(&rating) {
+            return Err(GpmdpError::InvalidArgument(
+                format!("invalid rating {}, must be 1-5", rating)));
+        }
+        return self.call("rating", "setRating",
+                         &format!("[{}]", rating)).map(|_| ());
+    }
+
+    /// Remove the rating of the current track.
+    pub fn reset_rating(&self) -> Result<()>
+    {
+        return self.call("rating", "resetRating", "").map(|_| ());
+    }
+
+    /// ALL_SHUFFLE or NO_SHUFFLE.
+    pub fn get_shuffle(&self) -> Result<String>
+    {
+        let value = self.call_value("playback", "getShuffle", "")?;
+        return Ok(String::deserialize(&value)?);
+    }
+
+    /// 'mode' is ALL_SHUFFLE or NO_SHUFFLE.
+    pub fn set_shuffle(&self, mode: &str) -> Result<()>
+    {
+        return self.call("playback", "setShuffle",
+                         &json_args(&[mode])?).map(|_| ());
+    }
+
+    /// LIST_REPEAT, SINGLE_REPEAT or NO_REPEAT.
+    pub fn get_repeat(&self) -> Result<String>
+    {
+        let value = self.call_value("playback", "getRepeat", "")?;
+        return Ok(String::deserialize(&value)?);
+    }
+
+    /// 'mode' is LIST_REPEAT, SINGLE_REPEAT or NO_REPEAT.
+    pub fn set_repeat(&self, mode: &str) -> Result<()>
+    {
+        return self.call("playback", "setRepeat",
+                         &json_args(&[mode])?).map(|_| ());
+    }
+
+    pub fn get_volume(&self) -> Result<u32>
+    {
+        let value = self.call_value("volume", "getVolume", "")?;
+        return match value.as_u64() {
+            Some(n) => Ok(n as u32),
+            None => Err(GpmdpError::Decode(format!("invalid volume level {}", value))),
+        };
+    }
+
+    /// Set the volume (0-100, larger values are clamped).
+    pub fn set_volume(&self, level: u32) -> Result<()>
+    {
+        return self.call("volume", "setVolume",
+                         &format!("[{}]", level.min(100))).map(|_| ());
+    }
+
+    pub fn increase_volume(&self, amount: u32) -> Result<()>
+    {
+        return self.call("volume", "increaseVolume",
+                         &format!("[{}]", amount)).map(|_| ());
+    }
+
+    pub fn decrease_volume(&self, amount: u32) -> Result<()>
+    {
+        return self.call("volume", "decreaseVolume",
+                         &format!("[{}]", amount)).map(|_| ());
+    }
+
+    /// Lyrics of the current track, empty if GPMDP has none.
+    pub fn lyrics(&self) -> Result<String>
+    {
+        self.wait_for_channels(CHNL_LYRICS)?;
+        return Ok(self.state().cur_track_lyrics);
+    }
+
+    /// Tracks currently in the queue.
+    pub fn queue_tracks(&self) -> Result<Vec<Track>>
+    {
+        self.wait_for_channels(CHNL_QUEUE)?;
+        return Ok(self.state().cur_queue);
+    }
+
+    pub fn clear_queue(&self) -> Result<()>
+    {
+        return self.call("queue", "clear", "").map(|_| ());
+    }
+
+    /// Play track number 'track_num' (1-based) of the queue.
+    pub fn play_queue_track(&self, track_num: usize) -> Result<()>
+    {
+        let tracks = self.queue_tracks()?;
+        let track = match track_num.checked_sub(1).and_then(|i| tracks.get(i)) {
+            Some(track) => track,
+            None => {
+                return Err(GpmdpError::InvalidArgument(
+                    format!("invalid track number {}", track_num)));
+            }
+        };
+        return self.call("queue", "playTrack",
+                         &json_args(&[track])?).map(|_| ());
+    }
+
+    /// All of the user's playlists.
+    pub fn playlists(&self) -> Result<Vec<Playlist>>
+    {
+        self.wait_for_channels(CHNL_PLAYLISTS)?;
+        return Ok(self.state().cur_playlists);
+    }
+
+    /// Playlist number 'playlist_num' (1-based).
+    pub fn playlist(&self, playlist_num: usize) -> Result<Playlist>
+    {
+        let playlists = self.playlists()?;
+        return match playlist_num.checked_sub(1).and_then(|i| playlists.get(i)) {
+            Some(playlist) => Ok(playlist.clone()),
+            None => {
+                Err(GpmdpError::InvalidArgument(
+                    format!("invalid playlist number {}", playlist_num)))
+            }
+        };
+    }
+
+    /// Play playlist number 'playlist_num' (1-based).
+    pub fn play_playlist(&self, playlist_num: usize) -> Result<()>
+    {
+        let playlist = self.playlist(playlist_num)?;
+        return self.call("playlists", "play",
+                         &json_args(&[playlist])?).map(|_| ());
+    }
+
+    /// Play playlist number 'playlist_num' starting at its track number
+    /// 'track_num' (both 1-based).
+    pub fn play_playlist_track(&self, playlist_num: usize, track_num: usize) -> Result<()>
+    {
+        let playlist = self.playlist(playlist_num)?;
+        let track = match track_num.checked_sub(1).and_then(|i| playlist.tracks.get(i)) {
+            Some(track) => track.clone(),
+            None => {
+                return Err(GpmdpError::InvalidArgument(
+                    format!("invalid track number {} for playlist '{}'",
+                            track_num, playlist.name())));
+            }
+        };
+        let args = format!("[{},{}]",
+                           serde_json::to_string(&playlist)?,
+                           serde_json::to_string(&track)?);
+        return self.call("playlists", "playWithTrack", &args).map(|_| ());
+    }
+
+    /// Run a search and return its results.
+    pub fn search(&self, text: &str) -> Result<SearchResults>
+    {
+        let value = self.call_value("search", "performSearch",
+                                    &json_args(&[text])?)?;
+        return SearchResults::deserialize(&value)
+            .map_err(|e| GpmdpError::Decode(format!("invalid search results: {}", e)));
+    }
+
+    /// Results of the most recent search.
+    pub fn search_results(&self) -> Result<SearchResults>
+    {
+        self.wait_for_channels(CHNL_SEARCH_RESULTS)?;
+        return Ok(self.state().cur_search);
+    }
+
+    /// Play search result number 'result_num' (1-based). Results are
+    /// numbered artists first, then albums, then tracks.
+    pub fn play_search_result(&self, result_num: usize) -> Result<()>
+    {
+        let args = match self.search_results()?.get(result_num) {
+            Some(SearchResult::Artist(artist)) => json_args(&[artist])?,
+            Some(SearchResult::Album(album)) => json_args(&[album])?,
+            Some(SearchResult::Track(track)) => json_args(&[track])?,
+            None => {
+                return Err(GpmdpError::InvalidArgument(
+                    format!("invalid result number {}", result_num)));
+            }
+        };
+        return self.call("search", "playResult", &args).map(|_| ());
+    }
+
+    /// Close the connection and wait for the websocket thread to exit.
+    pub fn close(mut self)
+    {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self)
+    {
+        if let Some(thread) = self.thread.take() {
+            let (opened, closed) = {
+                let inner = self.shared.lock();
+                (inner.opened, inner.closed)
+            };
+            match &self.out {
+                Transport::Ws(out) if !opened => {
+                    let _ = out.shutdown();
+                }
+                Transport::Ws(out) if !closed => {
+                    let _ = out.close(ws::CloseCode::Normal);
+                }
+                Transport::Ws(_) => {}
+                Transport::Replay(tx) => {
+                    let _ = tx.send(None);
+                }
+            }
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for GpmdpClient
+{
+    fn drop(&mut self)
+    {
+        self.shutdown();
+    }
+}
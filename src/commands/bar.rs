@@ -0,0 +1,273 @@
+//! `bar`: a long running status bar block fed by GPMDP's channel pushes.
+//!
+//! Three protocols are spoken:
+//!
+//! * `polybar`: the label as a plain line per change, for polybar's
+//!   `tail = true` script modules and anything else that reads lines.
+//! * `i3bar`: the i3bar JSON protocol, with click events read from stdin.
+//! * `waybar`: one JSON object per change for a waybar custom module with
+//!   `"return-type": "json"`.
+
+use std::io;
+use std::io::BufRead;
+use std::sync::mpsc;
+use std::thread;
+use serde_json::json;
+use crate::client::GpmdpClient;
+use crate::commands::builtin::{invalid, parse_options, status_json, STATUS_FIELDS};
+use crate::commands::{Command, Registry};
+use crate::error::*;
+use crate::events::*;
+use crate::format::Template;
+
+/// Block name used for i3bar and matched against click events.
+const BLOCK_NAME: &str = "gpmdp_rc";
+
+const DEFAULT_LABEL: &str = "[%artist% - ]%title%";
+
+/// Percent the volume moves per scroll wheel click.
+const VOLUME_STEP: u32 = 5;
+
+/*
+ * What wakes the bar up: a channel push, an i3bar click (the button
+ * number) or the connection closing.
+ */
+enum Input
+{
+    Changed,
+    Click(u64),
+    Closed,
+}
+
+/*
+ * The status for the label. Only pushed state is used so updates never
+ * wait on a request.
+ */
+fn bar_status(state: &PlayerState) -> serde_json::Value
+{
+    let playback_state = if state.cur_track.title().is_empty() {
+        "stopped"
+    } else if state.cur_playing {
+        "playing"
+    } else {
+        "paused"
+    };
+    return status_json(state, playback_state, None);
+}
+
+/*
+ * Pango markup escapes for waybar.
+ */
+fn escape_markup(text: &str) -> String
+{
+    return text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+}
+
+/*
+ * The line to print for a status in 'protocol'. i3bar updates after the
+ * first one start with a comma since they're elements of an endless array.
+ */
+fn bar_line(protocol: &str, label: &str, status: &serde_json::Value, first: bool) -> String
+{
+    return match protocol {
+        "i3bar" => {
+            let block = json!([{
+                "name": BLOCK_NAME,
+                "full_text": label,
+                "short_text": status["title"],
+            }]);
+            format!("{}{}", if first { "" } else { "," }, block)
+        }
+        "waybar" => {
+            let tooltip = format!("{}\n{}\n{}",
+                                  status["title"].as_str().unwrap_or(""),
+                                  status["artist"].as_str().unwrap_or(""),
+                                  status["album"].as_str().unwrap_or(""));
+            let elapsed = status["time_elapsed_secs"].as_u64().unwrap_or(0);
+            let percentage = (elapsed * 100)
+                .checked_div(status["time_total_secs"].as_u64().unwrap_or(0))
+                .unwrap_or(0);
+            json!({
+                "text": escape_markup(label),
+                "tooltip": escape_markup(tooltip.trim()),
+                "class": status["state"],
+                "alt": status["state"],
+                "percentage": percentage,
+            }).to_string()
+        }
+        _ => label.to_string(),
+    };
+}
+
+/*
+ * The button number of an i3bar click event line, None for the opening
+ * '[' and clicks on other blocks.
+ */
+fn parse_click(line: &str) -> Option<u64>
+{
+    let line = line.trim().trim_start_matches(',');
+    let click: serde_json::Value = serde_json::from_str(line).ok()?;
+    if !click["name"].is_null() && click["name"] != BLOCK_NAME {
+        return None;
+    }
+    return click["button"].as_u64();
+}
+
+/*
+ * Left click plays/pauses, middle goes back, right skips and the wheel
+ * changes the volume.
+ */
+fn click(client: &GpmdpClient, button: u64) -> Result<()>
+{
+    return match button {
+        1 => client.play_pause(),
+        2 => client.rewind(),
+        3 => client.forward(),
+        4 => client.increase_volume(VOLUME_STEP),
+        5 => client.decrease_volume(VOLUME_STEP),
+        _ => Ok(()),
+    };
+}
+
+pub(crate) struct Bar;
+
+impl Bar
+{
+    /*
+     * Emit a line each time the bar changes until the connection closes.
+     */
+    fn bar(&self,
+           client: &GpmdpClient,
+           args: &serde_json::Value,
+           emit: &mut dyn FnMut(serde_json::Value)) -> Result<()>
+    {
+        let protocol = args["protocol"].as_str().unwrap_or("polybar");
+        let label = Template::parse(args["label"].as_str().unwrap_or(DEFAULT_LABEL), &STATUS_FIELDS)?;
+
+        let (tx, inputs) = mpsc::channel();
+
+        let events = client.subscribe();
+        let events_tx = tx.clone();
+        thread::spawn(move || {
+            for _event in events.iter() {
+                if events_tx.send(Input::Changed).is_err() {
+                    return;
+                }
+            }
+            let _ = events_tx.send(Input::Closed);
+        });
+
+        if protocol == "i3bar" {
+            emit(json!(json!({ "version": 1, "click_events": true }).to_string()));
+            emit(json!("["));
+
+            /* left blocked on stdin when the bar ends, the process exits anyway */
+            thread::spawn(move || {
+                for line in io::stdin().lock().lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_err) => return,
+                    };
+                    if let Some(button) = parse_click(&line) {
+                        if tx.send(Input::Click(button)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        } else {
+            drop(tx);
+        }
+
+        let mut last: Option<String> = None;
+        let mut input = Input::Changed;
+        loop {
+            match input {
+                Input::Changed => {
+                    let status = bar_status(&client.state());
+                    let text = label.render(&status);
+                    let line = bar_line(protocol, &text, &status, last.is_none());
+                    /* ignore pushes that don't change what's shown */
+                    if last.as_deref().map(|last| last.trim_start_matches(',')) !=
+                       Some(line.trim_start_matches(',')) {
+                        emit(json!(line));
+                        last = Some(line);
+                    }
+                }
+                Input::Click(button) => {
+                    if let Err(e) = click(client, button) {
+                        eprintln!("ERROR: {}", e);
+                    }
+                }
+                Input::Closed => return Ok(()),
+            }
+            input = match inputs.recv() {
+                Ok(input) => input,
+                Err(_err) => return Ok(()),
+            };
+        }
+    }
+}
+
+impl Command for Bar
+{
+    fn name(&self) -> &'static str { "bar" }
+    fn synopsis(&self) -> &'static str
+    {
+        return "[ --protocol i3bar | waybar | polybar ] [ --label <template> ]";
+    }
+    fn long_running(&self) -> bool { true }
+
+    /// {"protocol": .., "label": template}.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        let options = parse_options(self.name(), args, &["--protocol", "--label"])?;
+
+        let protocol = options.get("--protocol").copied().unwrap_or("polybar");
+        if !["i3bar", "waybar", "polybar"].contains(&protocol) {
+            return Err(invalid(&format!("invalid bar protocol '{}'", protocol)));
+        }
+        let label = options.get("--label").copied().unwrap_or(DEFAULT_LABEL);
+        Template::parse(label, &STATUS_FIELDS)?;
+
+        return Ok(json!({ "protocol": protocol, "label": label }));
+    }
+
+    fn channels(&self, _args: &serde_json::Value) -> u64
+    {
+        return CHNL_PLAYSTATE |
+               CHNL_TRACK |
+               CHNL_TIME |
+               CHNL_RATING |
+               CHNL_SHUFFLE |
+               CHNL_REPEAT |
+               CHNL_VOLUME |
+               CHNL_LYRICS;
+    }
+
+    /// Every line until the connection closes.
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        let mut lines = Vec::new();
+        self.bar(client, args, &mut |line| lines.push(line))?;
+        return Ok(serde_json::Value::Array(lines));
+    }
+
+    /// Emits lines as the bar changes and fails with why the connection
+    /// closed.
+    fn stream(&self,
+              client: &GpmdpClient,
+              _registry: &Registry,
+              args: &serde_json::Value,
+              emit: &mut dyn FnMut(serde_json::Value)) -> Result<()>
+    {
+        self.bar(client, args, emit)?;
+        return match client.close_reason() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        };
+    }
+}
@@ -0,0 +1,1224 @@
+//! The commands gpmdp_rc ships with.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+use serde_json::json;
+use crate::client::GpmdpClient;
+use crate::commands::bar::Bar;
+use crate::commands::http::Http;
+use crate::commands::mpd::MpdBridge;
+#[cfg(feature = "mpris")]
+use crate::commands::mpris::Mpris;
+#[cfg(feature = "mqtt")]
+use crate::commands::mqtt::Mqtt;
+use crate::commands::osc::Osc;
+use crate::commands::{render_value, Command, Registry};
+use crate::error::*;
+use crate::events::*;
+use crate::models::{Playlist, SearchResults, Track};
+
+/// Register every built in command, in usage order.
+pub fn register(registry: &mut Registry)
+{
+    registry.register(Box::new(Auth));
+    registry.register(Box::new(Status));
+    registry.register(Box::new(Play));
+    registry.register(Box::new(Pause));
+    registry.register(Box::new(Next));
+    registry.register(Box::new(Prev));
+    registry.register(Box::new(Replay));
+    registry.register(Box::new(Seek));
+    registry.register(Box::new(Skip30));
+    registry.register(Box::new(Back10));
+    registry.register(Box::new(Lyrics));
+    registry.register(Box::new(Thumbs));
+    registry.register(Box::new(Rating));
+    registry.register(Box::new(Shuffle));
+    registry.register(Box::new(Repeat));
+    registry.register(Box::new(Queue));
+    registry.register(Box::new(Clear));
+    registry.register(Box::new(Playlists));
+    registry.register(Box::new(PlaylistCmd));
+    registry.register(Box::new(Search));
+    registry.register(Box::new(Results));
+    registry.register(Box::new(Volume));
+    registry.register(Box::new(Watch));
+    registry.register(Box::new(Bar));
+    registry.register(Box::new(MpdBridge));
+    registry.register(Box::new(Http));
+    registry.register(Box::new(Osc));
+    #[cfg(feature = "mpris")]
+    registry.register(Box::new(Mpris));
+    #[cfg(feature = "mqtt")]
+    registry.register(Box::new(Mqtt));
+}
+
+pub(crate) fn invalid(msg: &str) -> GpmdpError
+{
+    return GpmdpError::InvalidArgument(msg.to_string());
+}
+
+/*
+ * The single argument of a command, 'missing' is the error without one.
+ */
+fn one_arg<'a>(args: &'a [String], missing: &str) -> Result<&'a str>
+{
+    return match args {
+        [arg] => Ok(arg),
+        [] => Err(invalid(missing)),
+        _ => Err(invalid("too many arguments")),
+    };
+}
+
+fn parse_num<T: FromStr>(s: &str, what: &str) -> Result<T>
+{
+    return s.parse::<T>().map_err(|_| invalid(&format!("failed to parse {}", what)));
+}
+
+/*
+ * "--name value" options of a command, 'names' being the ones it takes.
+ */
+pub(crate) fn parse_options<'a>(command: &str,
+                                args: &'a [String],
+                                names: &[&str]) -> Result<HashMap<&'a str, &'a str>>
+{
+    let mut options = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !names.contains(&arg.as_str()) {
+            return Err(invalid(&format!("invalid {} option '{}'", command, arg)));
+        }
+        match args.next() {
+            Some(value) => options.insert(arg.as_str(), value.as_str()),
+            None => return Err(invalid(&format!("{} needs a value", arg))),
+        };
+    }
+    return Ok(options);
+}
+
+/*
+ * Map a keyword argument onto the value GPMDP takes for it.
+ */
+fn keyword(arg: &str, choices: &[(&str, &str)], what: &str) -> Result<serde_json::Value>
+{
+    return match choices.iter().find(|(word, _)| *word == arg) {
+        Some((_, value)) => Ok(json!(value)),
+        None => Err(invalid(&format!("invalid {}", what))),
+    };
+}
+
+fn str_field<'a>(value: &'a serde_json::Value, field: &str) -> &'a str
+{
+    return value[field].as_str().unwrap_or("");
+}
+
+fn track_line(track: &serde_json::Value) -> String
+{
+    return format!("{}: {} | {} | {}", track["num"],
+                   str_field(track, "artist"), str_field(track, "album"),
+                   str_field(track, "title"));
+}
+
+fn track_lines(tracks: &serde_json::Value) -> String
+{
+    return tracks.as_array().map_or(String::new(), |tracks| {
+        tracks.iter().map(track_line).collect::<Vec<String>>().join("\n")
+    });
+}
+
+/// The Track fields only `--format` templates see, public() leaves them
+/// out of the other outputs.
+const TEMPLATE_TRACK_FIELDS: [&str; 8] = [
+    "album_artist", "id", "index", "play_count", "album_id", "artist_id", "album_art",
+    "artist_image",
+];
+
+/// The keys of a track record, as `--format` templates see them.
+const TRACK_FIELDS: [&str; 13] = [
+    "num", "title", "artist", "album", "duration_secs", "album_artist", "id", "index",
+    "play_count", "album_id", "artist_id", "album_art", "artist_image",
+];
+
+/// The keys of the status object, as `--format` templates see them.
+pub(crate) const STATUS_FIELDS: [&str; 24] = [
+    "state", "artist", "album", "title", "time_elapsed_fmt", "time_elapsed_secs",
+    "time_total_fmt", "time_total_secs", "rating", "volume", "shuffle", "repeat", "queue_track",
+    "queue_length", "podcast", "lyrics", "album_artist", "id", "index", "play_count", "album_id",
+    "artist_id", "album_art", "artist_image",
+];
+
+/// The keys of a search hit record, those of the three kinds.
+const SEARCH_FIELDS: [&str; 15] = [
+    "type", "num", "name", "artist", "title", "album", "duration_secs", "album_artist", "id",
+    "index", "play_count", "album_id", "artist_id", "album_art", "artist_image",
+];
+
+/// The keys of a watch event, those of every channel.
+const EVENT_FIELDS: [&str; 25] = [
+    "channel", "api_version", "playing", "num", "title", "artist", "album", "duration_secs",
+    "lyrics", "time_elapsed_fmt", "time_elapsed_secs", "time_total_fmt", "time_total_secs",
+    "rating", "shuffle", "repeat", "playlists", "queue", "results", "library", "volume",
+    "theme_color", "theme", "theme_type", "payload",
+];
+
+/*
+ * The JSON for a track: num, title, artist, album and duration_secs.
+ */
+fn track_json(num: usize, track: &Track) -> serde_json::Value
+{
+    return json!({
+        "num": num,
+        "title": track.title(),
+        "artist": track.artist(),
+        "album": track.album(),
+        "duration_secs": track.duration.map(|ms| ms / 1000),
+    });
+}
+
+fn tracks_json(tracks: &[Track], first: usize) -> serde_json::Value
+{
+    return tracks.iter().enumerate().map(|(i, track)| track_json(i + first, track)).collect();
+}
+
+/*
+ * The rest of a track's fields, snake_cased, for templates.
+ */
+fn template_track_fields(track: &Track) -> serde_json::Map<String, serde_json::Value>
+{
+    let fields = json!({
+        "album_artist": track.album_artist,
+        "id": track.id,
+        "index": track.index,
+        "play_count": track.play_count,
+        "album_id": track.album_id,
+        "artist_id": track.artist_id,
+        "album_art": track.album_art,
+        "artist_image": track.artist_image,
+    });
+    return match fields {
+        serde_json::Value::Object(fields) => fields,
+        _ => serde_json::Map::new(),
+    };
+}
+
+/*
+ * track_json() plus the fields only templates see.
+ */
+fn track_record(num: usize, track: &Track) -> serde_json::Value
+{
+    let mut record = track_json(num, track);
+    if let Some(record) = record.as_object_mut() {
+        record.extend(template_track_fields(track));
+    }
+    return record;
+}
+
+fn track_records(tracks: &[Track], first: usize) -> serde_json::Value
+{
+    return tracks.iter().enumerate().map(|(i, track)| track_record(i + first, track)).collect();
+}
+
+/*
+ * 'output' without the fields only templates see, in it, its elements and
+ * its "tracks".
+ */
+fn without_template_fields(output: &serde_json::Value) -> serde_json::Value
+{
+    return match output {
+        serde_json::Value::Array(values) => values.iter().map(without_template_fields).collect(),
+        serde_json::Value::Object(map) => {
+            map.iter()
+                .filter(|(key, _)| !TEMPLATE_TRACK_FIELDS.contains(&key.as_str()))
+                .map(|(key, value)| {
+                    let value = if key == "tracks" { without_template_fields(value) } else { value.clone() };
+                    return (key.clone(), value);
+                })
+                .collect()
+        }
+        value => value.clone(),
+    };
+}
+
+/*
+ * Search results numbered artists first, then albums, then tracks, the
+ * numbers 'results <result#>' takes.
+ */
+fn search_json(results: &SearchResults) -> serde_json::Value
+{
+    let artists: Vec<serde_json::Value> = results.artists.iter().enumerate()
+        .map(|(i, artist)| json!({ "num": i + 1, "name": artist.name() }))
+        .collect();
+    let first = artists.len() + 1;
+    let albums: Vec<serde_json::Value> = results.albums.iter().enumerate()
+        .map(|(i, album)| {
+            json!({ "num": i + first, "name": album.name(), "artist": album.artist() })
+        })
+        .collect();
+    let first = first + albums.len();
+    return json!({
+        "artists": artists,
+        "albums": albums,
+        "tracks": track_records(&results.tracks, first),
+    });
+}
+
+fn render_search(results: &serde_json::Value) -> String
+{
+    let mut lines = Vec::new();
+
+    let empty = Vec::new();
+    for artist in results["artists"].as_array().unwrap_or(&empty) {
+        lines.push(format!("{}: {}", artist["num"], str_field(artist, "name")));
+    }
+    for album in results["albums"].as_array().unwrap_or(&empty) {
+        lines.push(format!("{}: {} | {}", album["num"],
+                           str_field(album, "artist"), str_field(album, "name")));
+    }
+    for track in results["tracks"].as_array().unwrap_or(&empty) {
+        lines.push(track_line(track));
+    }
+
+    return lines.join("\n");
+}
+
+/*
+ * Search results one record per hit, with "type" saying which kind.
+ */
+fn search_records(results: &serde_json::Value) -> Vec<serde_json::Value>
+{
+    let mut records = Vec::new();
+    for (key, kind) in &[("artists", "artist"), ("albums", "album"), ("tracks", "track")] {
+        for hit in results[*key].as_array().into_iter().flatten() {
+            let mut record = json!({ "type": kind });
+            if let (Some(record), Some(hit)) = (record.as_object_mut(), hit.as_object()) {
+                record.extend(hit.clone());
+            }
+            records.push(record);
+        }
+    }
+    return records;
+}
+
+fn playlists_json(playlists: &[Playlist]) -> serde_json::Value
+{
+    return playlists.iter().enumerate()
+        .map(|(i, playlist)| {
+            json!({ "num": i + 1, "name": playlist.name(), "tracks": playlist.tracks.len() })
+        })
+        .collect();
+}
+
+pub(crate) fn rating_str(liked: bool, disliked: bool) -> &'static str
+{
+    return if liked { "up" } else if disliked { "down" } else { "none" };
+}
+
+pub(crate) fn shuffle_str(shuffle: &str) -> &'static str
+{
+    return if shuffle == "NO_SHUFFLE" { "off" } else { "on" };
+}
+
+pub(crate) fn repeat_str(repeat: &str) -> &'static str
+{
+    return match repeat {
+        "LIST_REPEAT" => "all",
+        "SINGLE_REPEAT" => "single",
+        _ => "off",
+    };
+}
+
+/// The status object of 'state', see the README for its keys, plus the
+/// lyrics and the current track's fields only templates see. "podcast" is
+/// left out when it isn't known.
+pub(crate) fn status_json(state: &PlayerState,
+                          playback_state: &str,
+                          podcast: Option<bool>) -> serde_json::Value
+{
+    let mut status = json!({
+        "state": playback_state,
+        "artist": state.cur_track.artist(),
+        "album": state.cur_track.album(),
+        "title": state.cur_track.title(),
+        "time_elapsed_fmt": fmt_time(state.cur_track_progress),
+        "time_elapsed_secs": state.cur_track_progress / 1000,
+        "time_total_fmt": fmt_time(state.cur_track_total),
+        "time_total_secs": state.cur_track_total / 1000,
+        "rating": rating_str(state.cur_track_liked, state.cur_track_disliked),
+        "volume": state.cur_volume,
+        "shuffle": shuffle_str(&state.cur_shuffle),
+        "repeat": repeat_str(&state.cur_repeat),
+        "queue_track": state.queue_position(),
+        "queue_length": state.queue_length(),
+    });
+
+    if let Some(status) = status.as_object_mut() {
+        if let Some(podcast) = podcast {
+            status.insert("podcast".to_string(), json!(podcast));
+        }
+        let lyrics = Some(&state.cur_track_lyrics).filter(|lyrics| !lyrics.is_empty());
+        status.insert("lyrics".to_string(), json!(lyrics));
+        status.extend(template_track_fields(&state.cur_track));
+    }
+    return status;
+}
+
+/// Track times as h:mm:ss, m:ss or 0:ss.
+pub fn fmt_time(ms: u64) -> String
+{
+    let t = ms / 1000; /* convert ms to secs */
+    let h = 3600; /* secs in an hour */
+    let m = 60; /* secs in a minute */
+    if t >= h { /* >= 1 hour */
+        format!("{}:{}:{:02}", (t / h), ((t % h) / m), ((t % h) % m))
+    } else if t >= m { /* >= 1 minute */
+        format!("{}:{:02}", (t / m), (t % m))
+    } else { /* < 1 minute */
+        format!("0:{:02}", t)
+    }
+}
+
+struct Auth;
+
+impl Command for Auth
+{
+    fn name(&self) -> &'static str { "auth" }
+    fn fields(&self) -> &'static [&'static str] { &["token"] }
+
+    fn needs_auth(&self) -> bool
+    {
+        return false;
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.request_auth_code()?;
+
+        let mut code = String::new();
+        print!("Enter the 4-digit code from GPMDP: ");
+        let _ = io::stdout().flush();
+        io::stdin().read_line(&mut code)
+            .map_err(|e| invalid(&format!("invalid code: {}", e)))?;
+
+        let token = client.submit_auth_code(code.trim_end_matches(&['\r', '\n'][..]))?;
+        return Ok(json!({ "token": token }));
+    }
+
+    fn render(&self, output: &serde_json::Value) -> String
+    {
+        return format!("Token: {}", str_field(output, "token"));
+    }
+}
+
+struct Status;
+
+impl Command for Status
+{
+    fn name(&self) -> &'static str { "status" }
+    fn fields(&self) -> &'static [&'static str] { &STATUS_FIELDS }
+
+    fn channels(&self, _args: &serde_json::Value) -> u64
+    {
+        return CHNL_PLAYSTATE |
+               CHNL_TRACK |
+               CHNL_TIME |
+               CHNL_RATING |
+               CHNL_SHUFFLE |
+               CHNL_REPEAT |
+               CHNL_QUEUE |
+               CHNL_VOLUME |
+               CHNL_LYRICS;
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        let playback_state = client.get_playback_state()?;
+        /* a GPMDP without isPodcast still has a status, just no "podcast" */
+        let podcast = client.is_podcast().ok();
+        return Ok(status_json(&client.state(), playback_state.as_str(), podcast));
+    }
+
+    fn public(&self, output: &serde_json::Value) -> serde_json::Value
+    {
+        /* the lyrics are for templates too, they'd swamp the status */
+        let mut status = without_template_fields(output);
+        if let Some(status) = status.as_object_mut() {
+            status.remove("lyrics");
+        }
+        return status;
+    }
+}
+
+struct Play;
+
+impl Command for Play
+{
+    fn name(&self) -> &'static str { "play" }
+    fn synopsis(&self) -> &'static str { "[ <track#> ]" }
+
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        if args.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        let track_num: usize = parse_num(one_arg(args, "")?, "track number")?;
+        return Ok(json!(track_num));
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        match args.as_u64() {
+            Some(n) => client.play_queue_track(n as usize)?,
+            None => client.play_pause()?,
+        }
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Pause;
+
+impl Command for Pause
+{
+    fn name(&self) -> &'static str { "pause" }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.play_pause()?;
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Next;
+
+impl Command for Next
+{
+    fn name(&self) -> &'static str { "next" }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.forward()?;
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Prev;
+
+impl Command for Prev
+{
+    fn name(&self) -> &'static str { "prev" }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.rewind()?;
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Replay;
+
+impl Command for Replay
+{
+    fn name(&self) -> &'static str { "replay" }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.set_current_time(0)?;
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Seek;
+
+impl Command for Seek
+{
+    fn name(&self) -> &'static str { "seek" }
+    fn synopsis(&self) -> &'static str { "[ +<secs> | -<secs> | forward | backward ]" }
+
+    /// The offset in ms, or "forward"/"backward" for a step whose size
+    /// depends on what's playing.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        return match one_arg(args, "must provide seek value")? {
+            "forward" => Ok(json!("forward")),
+            "backward" => Ok(json!("backward")),
+            secs => Ok(json!(parse_num::<i64>(secs, "seek value")? * 1000)), /* convert to ms */
+        };
+    }
+
+    /// Podcasts step with GPMDP's own +30s/-10s, everything else 10s, as
+    /// does a GPMDP that can't tell.
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        if args == "forward" || args == "backward" {
+            let podcast = client.is_podcast().unwrap_or(false);
+            match (args == "forward", podcast) {
+                (true, true) => client.forward_thirty()?,
+                (false, true) => client.rewind_ten()?,
+                (true, false) => client.seek(10000)?, /* 10s */
+                (false, false) => client.seek(-10000)?, /* 10s */
+            }
+        } else {
+            client.seek(args.as_i64().unwrap_or(0))?;
+        }
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Skip30;
+
+impl Command for Skip30
+{
+    fn name(&self) -> &'static str { "skip30" }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.forward_thirty()?;
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Back10;
+
+impl Command for Back10
+{
+    fn name(&self) -> &'static str { "back10" }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.rewind_ten()?;
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Lyrics;
+
+impl Command for Lyrics
+{
+    fn name(&self) -> &'static str { "lyrics" }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        let lyrics = client.lyrics()?;
+        if lyrics.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        return Ok(json!(lyrics));
+    }
+
+    fn render(&self, output: &serde_json::Value) -> String
+    {
+        return match output.as_str() {
+            Some(lyrics) if !lyrics.is_empty() => lyrics.to_string(),
+            _ => "Lyrics not available!".to_string(),
+        };
+    }
+}
+
+struct Thumbs;
+
+impl Command for Thumbs
+{
+    fn name(&self) -> &'static str { "thumbs" }
+    fn synopsis(&self) -> &'static str { "< up | down >" }
+
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        return keyword(one_arg(args, "must provide thumbs rating")?,
+                       &[("up", "up"), ("down", "down")], "thumbs rating");
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        if args == "up" {
+            client.thumbs_up()?;
+        } else {
+            client.thumbs_down()?;
+        }
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Rating;
+
+impl Command for Rating
+{
+    fn name(&self) -> &'static str { "rating" }
+    fn synopsis(&self) -> &'static str { "< get | set <1-5> | reset >" }
+
+    /// "get", "reset" or the rating to set.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        return match args {
+            [op] if op == "get" || op == "reset" => Ok(json!(op)),
+            [op, rating] if op == "set" => {
+                match parse_num::<u32>(rating, "rating")? {
+                    n @ 1..=5 => Ok(json!(n)),
+                    _ => Err(invalid("rating must be 1-5")),
+                }
+            }
+            [op] if op == "set" => Err(invalid("must provide a rating")),
+            [] => Err(invalid("must provide get, set or reset")),
+            _ => Err(invalid("invalid rating command")),
+        };
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        if args == "get" {
+            return Ok(json!(client.get_rating()?));
+        }
+        if args == "reset" {
+            client.reset_rating()?;
+        } else {
+            client.set_rating(args.as_u64().unwrap_or(0) as u32)?;
+        }
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Shuffle;
+
+impl Command for Shuffle
+{
+    fn name(&self) -> &'static str { "shuffle" }
+    fn synopsis(&self) -> &'static str { "< on | off >" }
+
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        return keyword(one_arg(args, "must provide shuffle mode")?,
+                       &[("on", "ALL_SHUFFLE"), ("off", "NO_SHUFFLE")], "shuffle mode");
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.set_shuffle(args.as_str().unwrap_or(""))?;
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Repeat;
+
+impl Command for Repeat
+{
+    fn name(&self) -> &'static str { "repeat" }
+    fn synopsis(&self) -> &'static str { "< all | single | off >" }
+
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        return keyword(one_arg(args, "must provide repeat mode")?,
+                       &[("all", "LIST_REPEAT"),
+                         ("single", "SINGLE_REPEAT"),
+                         ("off", "NO_REPEAT")], "repeat mode");
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.set_repeat(args.as_str().unwrap_or(""))?;
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Queue;
+
+impl Command for Queue
+{
+    fn name(&self) -> &'static str { "queue" }
+    fn fields(&self) -> &'static [&'static str] { &TRACK_FIELDS }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        return Ok(track_records(&client.queue_tracks()?, 1));
+    }
+
+    fn public(&self, output: &serde_json::Value) -> serde_json::Value
+    {
+        return without_template_fields(output);
+    }
+
+    fn render(&self, output: &serde_json::Value) -> String
+    {
+        return track_lines(output);
+    }
+}
+
+struct Clear;
+
+impl Command for Clear
+{
+    fn name(&self) -> &'static str { "clear" }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        client.clear_queue()?;
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+struct Playlists;
+
+impl Command for Playlists
+{
+    fn name(&self) -> &'static str { "playlists" }
+    fn fields(&self) -> &'static [&'static str] { &["num", "name", "tracks"] }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        return Ok(playlists_json(&client.playlists()?));
+    }
+
+    fn render(&self, output: &serde_json::Value) -> String
+    {
+        return output.as_array().map_or(String::new(), |playlists| {
+            playlists.iter()
+                .map(|playlist| format!("{}: {}", playlist["num"], str_field(playlist, "name")))
+                .collect::<Vec<String>>()
+                .join("\n")
+        });
+    }
+}
+
+struct PlaylistCmd;
+
+impl Command for PlaylistCmd
+{
+    fn name(&self) -> &'static str { "playlist" }
+    fn synopsis(&self) -> &'static str { "<playlist#> [ <track#> | --tracks ]" }
+    fn fields(&self) -> &'static [&'static str] { &TRACK_FIELDS }
+
+    /// {"playlist": n} plus "track": n to start at a track or "tracks":
+    /// true to list them.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        let playlist_num: usize = match args.first() {
+            Some(arg) => parse_num(arg, "playlist number")?,
+            None => return Err(invalid("must provide a playlist number")),
+        };
+        return match &args[1..] {
+            [] => Ok(json!({ "playlist": playlist_num })),
+            [arg] if arg == "--tracks" => Ok(json!({ "playlist": playlist_num, "tracks": true })),
+            [arg] => {
+                let track_num: usize = parse_num(arg, "track number")?;
+                Ok(json!({ "playlist": playlist_num, "track": track_num }))
+            }
+            _ => Err(invalid("too many arguments")),
+        };
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        let playlist_num = args["playlist"].as_u64().unwrap_or(0) as usize;
+
+        if args["tracks"] == true {
+            return Ok(track_records(&client.playlist(playlist_num)?.tracks, 1));
+        }
+        match args["track"].as_u64() {
+            Some(track_num) => client.play_playlist_track(playlist_num, track_num as usize)?,
+            None => client.play_playlist(playlist_num)?,
+        }
+        return Ok(serde_json::Value::Null);
+    }
+
+    fn public(&self, output: &serde_json::Value) -> serde_json::Value
+    {
+        return without_template_fields(output);
+    }
+
+    fn render(&self, output: &serde_json::Value) -> String
+    {
+        return track_lines(output);
+    }
+}
+
+struct Search;
+
+impl Command for Search
+{
+    fn name(&self) -> &'static str { "search" }
+    fn synopsis(&self) -> &'static str { "\"<text>\"" }
+    fn fields(&self) -> &'static [&'static str] { &SEARCH_FIELDS }
+
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        return Ok(json!(one_arg(args, "must provide search string")?));
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        return Ok(search_json(&client.search(args.as_str().unwrap_or(""))?));
+    }
+
+    fn public(&self, output: &serde_json::Value) -> serde_json::Value
+    {
+        return without_template_fields(output);
+    }
+
+    fn render(&self, output: &serde_json::Value) -> String
+    {
+        return render_search(output);
+    }
+
+    fn records(&self, output: &serde_json::Value) -> Vec<serde_json::Value>
+    {
+        return search_records(output);
+    }
+}
+
+struct Results;
+
+impl Command for Results
+{
+    fn name(&self) -> &'static str { "results" }
+    fn synopsis(&self) -> &'static str { "[ <result#> ]" }
+    fn fields(&self) -> &'static [&'static str] { &SEARCH_FIELDS }
+
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        if args.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        let result_num: usize = parse_num(one_arg(args, "")?, "result number")?;
+        return Ok(json!(result_num));
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        return match args.as_u64() {
+            Some(n) => {
+                client.play_search_result(n as usize)?;
+                Ok(serde_json::Value::Null)
+            }
+            None => Ok(search_json(&client.search_results()?)),
+        };
+    }
+
+    fn public(&self, output: &serde_json::Value) -> serde_json::Value
+    {
+        return without_template_fields(output);
+    }
+
+    fn render(&self, output: &serde_json::Value) -> String
+    {
+        return render_search(output);
+    }
+
+    fn records(&self, output: &serde_json::Value) -> Vec<serde_json::Value>
+    {
+        return search_records(output);
+    }
+}
+
+struct Volume;
+
+impl Command for Volume
+{
+    fn name(&self) -> &'static str { "volume" }
+    fn synopsis(&self) -> &'static str { "[ <0-100> | up | down ]" }
+
+    /// null to read the volume, "up", "down" or the level to set.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        if args.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        return match one_arg(args, "")? {
+            "up" => Ok(json!("up")),
+            "down" => Ok(json!("down")),
+            level => Ok(json!(parse_num::<u32>(level, "volume level")?.min(100))),
+        };
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        if args == "up" {
+            client.increase_volume(10)?;
+        } else if args == "down" {
+            client.decrease_volume(10)?;
+        } else if let Some(level) = args.as_u64() {
+            client.set_volume(level as u32)?;
+        } else {
+            return Ok(json!(client.get_volume()?));
+        }
+        return Ok(serde_json::Value::Null);
+    }
+}
+
+/*
+ * The JSON for a channel event: {"channel": <name>} plus the fields that
+ * channel has, named as in the status. None for auth pushes.
+ */
+pub(crate) fn event_json(event: &ChannelEvent, state: &PlayerState) -> Option<serde_json::Value>
+{
+    let fields = match event {
+        ChannelEvent::Connect(_) => return None,
+        ChannelEvent::ApiVersion(version) => json!({ "api_version": version }),
+        ChannelEvent::PlayState(playing) => json!({ "playing": playing }),
+        ChannelEvent::Track(track) => {
+            let num = state.cur_queue.iter()
+                .position(|t| t.same_song(track))
+                .map_or(0, |i| i + 1);
+            track_json(num, track)
+        }
+        ChannelEvent::Lyrics(lyrics) => json!({ "lyrics": lyrics }),
+        ChannelEvent::Time { current, total } => {
+            json!({
+                "time_elapsed_fmt": fmt_time(*current),
+                "time_elapsed_secs": current / 1000,
+                "time_total_fmt": fmt_time(*total),
+                "time_total_secs": total / 1000,
+            })
+        }
+        ChannelEvent::Rating { liked, disliked } => {
+            json!({ "rating": rating_str(*liked, *disliked) })
+        }
+        ChannelEvent::Shuffle(shuffle) => json!({ "shuffle": shuffle_str(shuffle) }),
+        ChannelEvent::Repeat(repeat) => json!({ "repeat": repeat_str(repeat) }),
+        ChannelEvent::Playlists(playlists) => json!({ "playlists": playlists_json(playlists) }),
+        ChannelEvent::Queue(queue) => json!({ "queue": tracks_json(queue, 1) }),
+        ChannelEvent::SearchResults(results) => {
+            json!({ "results": without_template_fields(&search_json(results)) })
+        }
+        ChannelEvent::Library(library) => json!({ "library": library }),
+        ChannelEvent::Volume(volume) => json!({ "volume": volume }),
+        ChannelEvent::ThemeColor(color) => json!({ "theme_color": color }),
+        ChannelEvent::Theme(theme) => json!({ "theme": theme }),
+        ChannelEvent::ThemeType(theme_type) => json!({ "theme_type": theme_type }),
+        ChannelEvent::Other(_, payload) => json!({ "payload": payload }),
+    };
+
+    let mut event_js = json!({ "channel": event.channel() });
+    if let (Some(event_js), serde_json::Value::Object(fields)) = (event_js.as_object_mut(), fields) {
+        event_js.extend(fields);
+    }
+    return Some(event_js);
+}
+
+fn render_event(event: &serde_json::Value) -> String
+{
+    let channel = str_field(event, "channel");
+    let count = |key: &str| event[key].as_array().map_or(0, |values| values.len());
+    let summary = match channel {
+        "track" => format!("{} - {}", str_field(event, "artist"), str_field(event, "title")),
+        "time" => {
+            format!("{}/{}", str_field(event, "time_elapsed_fmt"),
+                    str_field(event, "time_total_fmt"))
+        }
+        "playState" => (if event["playing"] == true { "playing" } else { "paused" }).to_string(),
+        "lyrics" => (if event["lyrics"].is_string() { "available" } else { "none" }).to_string(),
+        "queue" => format!("{} tracks", count("queue")),
+        "playlists" => format!("{} playlists", count("playlists")),
+        "search-results" => {
+            let results = &event["results"];
+            let len = ["artists", "albums", "tracks"].iter()
+                .map(|key| results[*key].as_array().map_or(0, |values| values.len()))
+                .sum::<usize>();
+            format!("{} results", len)
+        }
+        _ => {
+            event.as_object()
+                .and_then(|fields| fields.iter().find(|(key, _)| *key != "channel"))
+                .map_or(String::new(), |(_, value)| render_value(value))
+        }
+    };
+    return format!("{}: {}", channel, summary);
+}
+
+/*
+ * Drops repeated events: those equal to the last one printed on their
+ * channel and time ticks within 'interval' secs of the last one printed.
+ */
+pub(crate) struct Dedupe
+{
+    interval: u64,
+    last: HashMap<String, serde_json::Value>,
+}
+
+impl Dedupe
+{
+    pub(crate) fn new(interval: u64) -> Dedupe
+    {
+        return Dedupe { interval, last: HashMap::new() };
+    }
+
+    pub(crate) fn is_new(&mut self, event: &serde_json::Value) -> bool
+    {
+        let channel = str_field(event, "channel").to_string();
+        if let Some(last) = self.last.get(&channel) {
+            if last == event {
+                return false;
+            }
+            if channel == "time" && last["time_total_secs"] == event["time_total_secs"] {
+                let secs = |event: &serde_json::Value| event["time_elapsed_secs"].as_u64().unwrap_or(0);
+                let (last_secs, secs) = (secs(last), secs(event));
+                /* a step back is a seek or a restart, always print it */
+                if secs >= last_secs && secs - last_secs < self.interval {
+                    return false;
+                }
+            }
+        }
+        self.last.insert(channel, event.clone());
+        return true;
+    }
+}
+
+struct Watch;
+
+impl Watch
+{
+    /*
+     * Hand each new event on the watched channels to 'emit' until the
+     * connection closes: first the state so far, then the changes.
+     */
+    fn watch(&self,
+             client: &GpmdpClient,
+             args: &serde_json::Value,
+             emit: &mut dyn FnMut(serde_json::Value))
+    {
+        let chnls = args["channels"].as_u64().unwrap_or(CHNLS_ALL);
+        let mut dedupe = Dedupe::new(args["interval"].as_u64().unwrap_or(1));
+
+        let events = client.subscribe();
+        let state = client.state();
+        let current = state.events(chnls).into_iter().map(|event| (event, state.clone()));
+        let changes = events.iter()
+            .filter(|event| event.channel_bit() & chnls != 0)
+            .map(|event| (event, client.state()));
+
+        for (event, state) in current.chain(changes) {
+            if let Some(event_js) = event_json(&event, &state) {
+                if dedupe.is_new(&event_js) {
+                    emit(event_js);
+                }
+            }
+        }
+    }
+}
+
+impl Command for Watch
+{
+    fn name(&self) -> &'static str { "watch" }
+    fn synopsis(&self) -> &'static str { "[ --channels <channel>,... ] [ --interval <secs> ]" }
+    fn fields(&self) -> &'static [&'static str] { &EVENT_FIELDS }
+    fn long_running(&self) -> bool { true }
+
+    /// {"channels": CHNL_* bits, "interval": secs between time ticks}.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        let options = parse_options(self.name(), args, &["--channels", "--interval"])?;
+
+        let mut chnls = CHNLS_ALL;
+        if let Some(channels) = options.get("--channels") {
+            chnls = 0;
+            for channel in channels.split(',').map(str::trim) {
+                match channel_bit(channel) {
+                    0 => return Err(invalid(&format!("unknown channel '{}'", channel))),
+                    chnl => chnls |= chnl,
+                }
+            }
+        }
+        let interval = match options.get("--interval") {
+            Some(interval) => parse_num::<u64>(interval, "interval")?,
+            None => 1,
+        };
+        return Ok(json!({ "channels": chnls, "interval": interval }));
+    }
+
+    /// Every event until the connection closes.
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        let mut events = Vec::new();
+        self.watch(client, args, &mut |event| events.push(event));
+        return Ok(serde_json::Value::Array(events));
+    }
+
+    /// Emits events as they come and fails with why the connection closed.
+    fn stream(&self,
+              client: &GpmdpClient,
+              _registry: &Registry,
+              args: &serde_json::Value,
+              emit: &mut dyn FnMut(serde_json::Value)) -> Result<()>
+    {
+        self.watch(client, args, emit);
+        return match client.close_reason() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        };
+    }
+
+    fn render(&self, output: &serde_json::Value) -> String
+    {
+        return match output.as_array() {
+            Some(events) => events.iter().map(render_event).collect::<Vec<String>>().join("\n"),
+            None => render_event(output),
+        };
+    }
+}
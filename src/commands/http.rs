@@ -0,0 +1,577 @@
+//! `http`: a local REST API and Server-Sent Events stream in front of GPMDP.
+//!
+//! Each endpoint runs one of the command line commands and answers with its
+//! `--json` result, so the JSON is the same as `gpmdp_rc --json <command>`.
+//! A command without a result answers 204. Arguments, like the volume to
+//! set, are the request body: a JSON string or number, or the bare text.
+//!
+//! `GET /events` is a `text/event-stream` of the `watch` events, each sent
+//! as `event: <channel>` with the event as its `data`.
+
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::json;
+use crate::client::GpmdpClient;
+use crate::commands::builtin::{event_json, parse_options, repeat_str, shuffle_str, Dedupe};
+use crate::commands::{execute, Command, Registry};
+use crate::error::*;
+use crate::events::*;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+/// How long a client has to send its request, all of it.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest request line or header line taken.
+const MAX_LINE: usize = 8 * 1024;
+
+/// The most headers taken.
+const MAX_HEADERS: usize = 100;
+
+/// How long the rest of a refused request is waited for, see linger().
+const LINGER: Duration = Duration::from_millis(200);
+
+/// How often an idle event stream is sent a comment, to notice clients
+/// that have gone.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// The largest request body taken.
+const MAX_BODY: usize = 64 * 1024;
+
+/// Methods some endpoint takes, to tell 405 from 404.
+const METHODS: [&str; 4] = ["GET", "POST", "PUT", "DELETE"];
+
+/*
+ * A request that failed, answered with 'status' and the error as
+ * {"error": {"kind": .., "message": ..}}.
+ */
+struct Failure
+{
+    status: u16,
+    error: GpmdpError,
+}
+
+fn failure(status: u16, message: &str) -> Failure
+{
+    return Failure { status, error: GpmdpError::InvalidArgument(message.to_string()) };
+}
+
+impl From<GpmdpError> for Failure
+{
+    fn from(e: GpmdpError) -> Failure
+    {
+        let status = match e {
+            GpmdpError::InvalidArgument(_) => 400,
+            GpmdpError::Config(_)          => 500,
+            GpmdpError::Timeout(_)         => 504,
+            _                              => 502,
+        };
+        return Failure { status, error: e };
+    }
+}
+
+struct Request
+{
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request
+{
+    fn query(&self, name: &str) -> Option<&str>
+    {
+        return self.query.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str());
+    }
+
+    /*
+     * The body as a command argument, None if there's none.
+     */
+    fn arg(&self) -> Option<String>
+    {
+        let body = self.body.trim();
+        if body.is_empty() {
+            return None;
+        }
+        return match serde_json::from_str(body) {
+            Ok(serde_json::Value::String(s)) => Some(s),
+            Ok(serde_json::Value::Number(n)) => Some(n.to_string()),
+            _ => Some(body.to_string()),
+        };
+    }
+}
+
+/*
+ * What an endpoint does.
+ */
+enum Route
+{
+    /// Run the command line command 'argv'.
+    Run(Vec<String>),
+    Shuffle,
+    Repeat,
+    /// Stream the events of `watch` run with these arguments.
+    Events(Vec<String>),
+}
+
+fn reason(status: u16) -> &'static str
+{
+    return match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        _   => "Gateway Timeout",
+    };
+}
+
+/*
+ * Undo the percent encoding of a query string part, '+' being a space.
+ */
+fn decode(s: &str) -> String
+{
+    let mut bytes = Vec::new();
+    let mut chars = s.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = chars.by_ref().take(2).collect();
+                match std::str::from_utf8(&hex).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) if hex.len() == 2 => bytes.push(b),
+                    _ => {
+                        bytes.push(b'%');
+                        bytes.extend(hex);
+                    }
+                }
+            }
+            b => bytes.push(b),
+        }
+    }
+    return String::from_utf8_lossy(&bytes).into_owned();
+}
+
+/*
+ * Whether 'host', a Host header, names this server listening on 'listen'
+ * and 'port': the listen address, or localhost or an IP address with the
+ * port. Any other name is some site's domain pointed at us (DNS rebinding).
+ */
+fn own_host(host: &str, listen: &str, port: u16) -> bool
+{
+    if host.eq_ignore_ascii_case(listen) {
+        return true;
+    }
+    let (name, host_port) = match host.rsplit_once(':') {
+        Some((name, host_port)) if !host.ends_with(']') => (name, host_port.parse().ok()),
+        _ => (host, Some(80)),
+    };
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    return host_port == Some(port) &&
+           (name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok());
+}
+
+/*
+ * Read the request line, headers and body of a request to the server
+ * listening on 'listen' and 'port'. Requests from a web page on another
+ * site are refused, so it can't drive the player.
+ */
+fn read_request(reader: &mut impl BufRead,
+                listen: &str,
+                port: u16) -> std::result::Result<Request, Failure>
+{
+    let malformed = || failure(400, "malformed request");
+
+    let line = read_line(reader, failure(414, "request line too long"))?;
+    let mut words = line.split_whitespace();
+    let (method, target) = match (words.next(), words.next(), words.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/") => {
+            (method.to_string(), target.to_string())
+        }
+        _ => return Err(malformed()),
+    };
+
+    let mut length = 0;
+    let mut host = None;
+    let mut origin = None;
+    for count in 0.. {
+        let header = read_line(reader, failure(431, "request header too long"))?;
+        if header.is_empty() {
+            return Err(malformed());
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(failure(431, "too many request headers"));
+        }
+        let (name, value) = header.split_once(':').ok_or_else(malformed)?;
+        let value = value.trim().to_string();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => length = value.parse::<usize>().map_err(|_| malformed())?,
+            "host" => host = Some(value),
+            "origin" => origin = Some(value),
+            _ => {}
+        }
+    }
+    let host = match host {
+        Some(host) if own_host(&host, listen, port) => host,
+        host => {
+            return Err(failure(403, &format!("requests for host {} aren't allowed",
+                                             host.as_deref().unwrap_or("(none)"))));
+        }
+    };
+    if let Some(origin) = origin {
+        if origin != format!("http://{}", host) {
+            return Err(failure(403, &format!("requests from {} aren't allowed", origin)));
+        }
+    }
+    if length > MAX_BODY {
+        return Err(failure(413, "request body too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(read_failure)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            return (decode(key), decode(value));
+        })
+        .collect();
+    return Ok(Request {
+        method,
+        path: path.to_string(),
+        query,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    });
+}
+
+/*
+ * Reads 'stream' until the instant 'until', so a client sending its
+ * request a byte at a time can't keep the connection open.
+ */
+struct Deadline<'a>
+{
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+impl Read for Deadline<'_>
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        let mut stream = self.stream;
+        stream.set_read_timeout(Some(left))?;
+        return stream.read(buf);
+    }
+}
+
+fn read_failure(err: std::io::Error) -> Failure
+{
+    return match err.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => failure(408, "request timed out"),
+        _ => failure(400, "malformed request"),
+    };
+}
+
+/*
+ * A line of the request head, failing with 'too_long' past MAX_LINE. An
+ * empty string is the end of the input.
+ */
+fn read_line(reader: &mut impl BufRead, too_long: Failure) -> std::result::Result<String, Failure>
+{
+    let mut line = String::new();
+    reader.take(MAX_LINE as u64).read_line(&mut line).map_err(read_failure)?;
+    if line.len() == MAX_LINE && !line.ends_with('\n') {
+        return Err(too_long);
+    }
+    return Ok(line);
+}
+
+/*
+ * What 'method' does on the path 'segments', None if it isn't an endpoint.
+ */
+fn endpoint(method: &str, segments: &[&str], request: &Request) -> Option<Route>
+{
+    let run = |argv: &[&str]| Route::Run(argv.iter().map(|arg| arg.to_string()).collect());
+    let with_arg = |argv: &[&str]| {
+        let mut argv: Vec<String> = argv.iter().map(|arg| arg.to_string()).collect();
+        argv.extend(request.arg());
+        return Route::Run(argv);
+    };
+
+    let route = match (segments, method) {
+        (["status"], "GET")                => run(&["status"]),
+        (["queue"], "GET")                 => run(&["queue"]),
+        (["queue"], "DELETE")              => run(&["clear"]),
+        (["play"], "POST")                 => with_arg(&["play"]),
+        (["pause"], "POST")                => run(&["pause"]),
+        (["next"], "POST")                 => run(&["next"]),
+        (["prev"], "POST")                 => run(&["prev"]),
+        (["seek"], "POST")                 => with_arg(&["seek"]),
+        (["playlists"], "GET")             => run(&["playlists"]),
+        (["playlists", n], "GET")          => run(&["playlist", n, "--tracks"]),
+        (["playlists", n, "play"], "POST") => with_arg(&["playlist", n]),
+        (["search"], "GET")                => {
+            let mut argv = vec!["search".to_string()];
+            argv.extend(request.query("q").map(str::to_string));
+            Route::Run(argv)
+        }
+        (["results"], "GET")               => run(&["results"]),
+        (["results", n, "play"], "POST")   => run(&["results", n]),
+        (["lyrics"], "GET")                => run(&["lyrics"]),
+        (["volume"], "GET")                => run(&["volume"]),
+        (["volume"], "PUT")                => with_arg(&["volume"]),
+        (["rating"], "GET")                => run(&["rating", "get"]),
+        (["rating"], "PUT")                => {
+            match request.arg().as_deref() {
+                Some(thumbs @ ("up" | "down")) => run(&["thumbs", thumbs]),
+                Some("reset") => run(&["rating", "reset"]),
+                _ => with_arg(&["rating", "set"]),
+            }
+        }
+        (["shuffle"], "GET")               => Route::Shuffle,
+        (["shuffle"], "PUT")               => with_arg(&["shuffle"]),
+        (["repeat"], "GET")                => Route::Repeat,
+        (["repeat"], "PUT")                => with_arg(&["repeat"]),
+        (["events"], "GET")                => {
+            let mut argv = vec!["watch".to_string()];
+            for name in ["channels", "interval"] {
+                if let Some(value) = request.query(name) {
+                    argv.push(format!("--{}", name));
+                    argv.push(value.to_string());
+                }
+            }
+            Route::Events(argv)
+        }
+        _ => return None,
+    };
+    return Some(route);
+}
+
+/*
+ * The endpoint 'request' is for.
+ */
+fn route(request: &Request) -> std::result::Result<Route, Failure>
+{
+    let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    if let Some(route) = endpoint(&request.method, &segments, request) {
+        return Ok(route);
+    }
+    if METHODS.iter().any(|method| endpoint(method, &segments, request).is_some()) {
+        return Err(failure(405, &format!("{} isn't allowed on {}", request.method, request.path)));
+    }
+    return Err(failure(404, &format!("no endpoint {}", request.path)));
+}
+
+/*
+ * Write a whole response at once, JSON unless there's no content.
+ */
+fn respond(mut stream: &TcpStream, status: u16, body: &serde_json::Value) -> std::io::Result<()>
+{
+    let mut response = format!("HTTP/1.1 {} {}\r\nConnection: close\r\n", status, reason(status));
+    if status == 204 {
+        response.push_str("\r\n");
+    } else {
+        let body = format!("{}\n", body);
+        response.push_str(&format!("Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                                   body.len(), body));
+    }
+    return stream.write_all(response.as_bytes());
+}
+
+/*
+ * Send the events `watch` would print as Server-Sent Events until the
+ * client or GPMDP goes away.
+ */
+fn events(client: &GpmdpClient, mut stream: &TcpStream, args: &serde_json::Value) -> std::io::Result<()>
+{
+    let chnls = args["channels"].as_u64().unwrap_or(CHNLS_ALL);
+    let mut dedupe = Dedupe::new(args["interval"].as_u64().unwrap_or(1));
+
+    let events = client.subscribe();
+    let state = client.state();
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                       Cache-Control: no-cache\r\nConnection: close\r\n\r\n")?;
+
+    /* the event as a message, None if it's a repeat */
+    let mut message = |event: ChannelEvent, state: &PlayerState| -> Option<String> {
+        return event_json(&event, state)
+            .filter(|event_js| dedupe.is_new(event_js))
+            .map(|event_js| format!("event: {}\ndata: {}\n\n", event.channel(), event_js));
+    };
+    for event in state.events(chnls) {
+        if let Some(message) = message(event, &state) {
+            stream.write_all(message.as_bytes())?;
+        }
+    }
+    loop {
+        let message = match events.recv_timeout(KEEPALIVE) {
+            Ok(event) if event.channel_bit() & chnls != 0 => message(event, &client.state()),
+            Ok(_) => None,
+            Err(RecvTimeoutError::Timeout) => Some(": keepalive\n\n".to_string()),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        if let Some(message) = message {
+            stream.write_all(message.as_bytes())?;
+        }
+    }
+}
+
+/*
+ * Read and drop the rest of a request refused part way through, up to
+ * MAX_BODY or until the client pauses. Closing with it unread would reset
+ * the connection, and the client could lose the answer.
+ */
+fn linger(stream: &TcpStream)
+{
+    let _ = stream.shutdown(Shutdown::Write);
+    if stream.set_read_timeout(Some(LINGER)).is_ok() {
+        let _ = std::io::copy(&mut stream.take(MAX_BODY as u64), &mut std::io::sink());
+    }
+}
+
+/*
+ * Answer one request to the server listening on 'listen' and 'port'.
+ */
+fn connection(client: &GpmdpClient,
+              registry: &Registry,
+              listen: &str,
+              port: u16,
+              stream: &TcpStream) -> std::io::Result<()>
+{
+    let deadline = Deadline { stream, until: Instant::now() + READ_TIMEOUT };
+    let res = read_request(&mut BufReader::new(deadline), listen, port)
+        .and_then(|request| route(&request))
+        .and_then(|route| {
+            return match route {
+                Route::Run(argv) => {
+                    let (command, args) = registry.parse(&argv)?;
+                    Ok(Some(command.public(&execute(command, client, registry, &args)?)))
+                }
+                Route::Shuffle => Ok(Some(json!(shuffle_str(&client.state().cur_shuffle)))),
+                Route::Repeat => Ok(Some(json!(repeat_str(&client.state().cur_repeat)))),
+                Route::Events(argv) => {
+                    let (_, args) = registry.parse(&argv)?;
+                    /* the stream ends when the client goes away, no error */
+                    let _ = events(client, stream, &args);
+                    Ok(None)
+                }
+            };
+        });
+
+    return match res {
+        Ok(Some(serde_json::Value::Null)) => respond(stream, 204, &serde_json::Value::Null),
+        Ok(Some(output)) => respond(stream, 200, &output),
+        Ok(None) => Ok(()),
+        Err(failure) => {
+            respond(stream, failure.status, &json!({ "error": failure.error }))?;
+            if [413, 414, 431].contains(&failure.status) {
+                linger(stream);
+            }
+            Ok(())
+        }
+    };
+}
+
+pub(crate) struct Http;
+
+impl Http
+{
+    /*
+     * Accept HTTP clients on 'listen' until the connection closes, running
+     * the commands in 'registry' for them.
+     */
+    fn serve(&self, client: &GpmdpClient, registry: &Registry, listen: &str) -> Result<()>
+    {
+        let listener = TcpListener::bind(listen).map_err(|err| {
+            return GpmdpError::Connection(format!("failed to listen on {}: {}", listen, err));
+        })?;
+        let addr = listener.local_addr().map_err(|err| GpmdpError::Connection(err.to_string()))?;
+
+        /* wake up accept() when the connection closes */
+        let closed = client.subscribe();
+        thread::spawn(move || {
+            for _ in closed {}
+            let _ = TcpStream::connect(addr);
+        });
+        if let Some(e) = client.close_reason() {
+            return Err(e);
+        }
+
+        return thread::scope(|scope| {
+            for stream in listener.incoming() {
+                if let Some(e) = client.close_reason() {
+                    return Err(e);
+                }
+                match stream {
+                    Ok(stream) => {
+                        scope.spawn(move || {
+                            /* a client that goes away is no error */
+                            let _ = connection(client, registry, listen, addr.port(), &stream);
+                            let _ = stream.shutdown(Shutdown::Both);
+                        });
+                    }
+                    Err(err) => {
+                        return Err(GpmdpError::Connection(
+                            format!("failed to accept on {}: {}", listen, err)));
+                    }
+                }
+            }
+            return Ok(());
+        });
+    }
+}
+
+impl Command for Http
+{
+    fn name(&self) -> &'static str { "http" }
+    fn synopsis(&self) -> &'static str { "[ --listen <addr>:<port> ]" }
+    fn long_running(&self) -> bool { true }
+
+    /// {"listen": address}.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        let options = parse_options(self.name(), args, &["--listen"])?;
+        return Ok(json!({ "listen": options.get("--listen").copied().unwrap_or(DEFAULT_LISTEN) }));
+    }
+
+    fn channels(&self, _args: &serde_json::Value) -> u64
+    {
+        return CHNL_SHUFFLE | CHNL_REPEAT;
+    }
+
+    /// Serves HTTP clients until the connection closes, then fails with
+    /// why it did.
+    fn run(&self,
+           client: &GpmdpClient,
+           registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        self.serve(client, registry, args["listen"].as_str().unwrap_or(DEFAULT_LISTEN))?;
+        return match client.close_reason() {
+            Some(e) => Err(e),
+            None => Ok(serde_json::Value::Null),
+        };
+    }
+}
@@ -0,0 +1,350 @@
+//! Command line commands and the registry they're looked up in.
+//!
+//! Each command is a `Command`: it parses its own arguments, says which
+//! channels it needs, makes its requests and renders its result. A result
+//! is plain JSON so front ends other than the terminal can use it as is.
+//! Adding a command is a matter of implementing the trait and registering
+//! it:
+//!
+//! ```no_run
+//! use gpmdp_rc::commands::{Command, Registry};
+//! use gpmdp_rc::{GpmdpClient, Result};
+//!
+//! struct Title;
+//!
+//! impl Command for Title
+//! {
+//!     fn name(&self) -> &'static str { "title" }
+//!     fn channels(&self, _args: &serde_json::Value) -> u64 { gpmdp_rc::events::CHNL_TRACK }
+//!     fn run(&self,
+//!            client: &GpmdpClient,
+//!            _registry: &Registry,
+//!            _args: &serde_json::Value) -> Result<serde_json::Value>
+//!     {
+//!         Ok(client.state().cur_track.title().into())
+//!     }
+//! }
+//!
+//! let mut registry = Registry::default();
+//! registry.register(Box::new(Title));
+//! ```
+
+use crate::client::GpmdpClient;
+use crate::error::*;
+use crate::format::Template;
+
+mod bar;
+pub mod builtin;
+mod http;
+mod mpd;
+#[cfg(feature = "mpris")]
+mod mpris;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod osc;
+
+pub trait Command: Send + Sync
+{
+    /// Name typed on the command line.
+    fn name(&self) -> &'static str;
+
+    /// Arguments as shown in the usage, e.g. "[ <track#> ]".
+    fn synopsis(&self) -> &'static str
+    {
+        return "";
+    }
+
+    /// Keys of the result's records `--format` templates can use. Bare
+    /// numbers and strings are `%value%`.
+    fn fields(&self) -> &'static [&'static str]
+    {
+        return &["value"];
+    }
+
+    /// Check the command line arguments and turn them into the JSON the
+    /// other methods work from. Called before connecting, so bad arguments
+    /// fail without a GPMDP round trip.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        if !args.is_empty() {
+            return Err(GpmdpError::InvalidArgument(
+                format!("'{}' takes no arguments", self.name())));
+        }
+        return Ok(serde_json::Value::Null);
+    }
+
+    /// CHNL_* bits that have to be pushed before run() is called.
+    fn channels(&self, _args: &serde_json::Value) -> u64
+    {
+        return 0;
+    }
+
+    /// Whether the command runs until the connection closes, like a watch
+    /// or a server. The shell and the daemon don't run those.
+    fn long_running(&self) -> bool
+    {
+        return false;
+    }
+
+    /// Whether the connection has to be authenticated first.
+    fn needs_auth(&self) -> bool
+    {
+        return true;
+    }
+
+    /// Make the requests and return the result, null if there is none.
+    /// 'registry' is the one the command was looked up in, for commands
+    /// that run others.
+    fn run(&self,
+           client: &GpmdpClient,
+           registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>;
+
+    /// Run a command whose results keep coming, handing each to 'emit' as
+    /// it arrives. By default the one result of run() is emitted.
+    fn stream(&self,
+              client: &GpmdpClient,
+              registry: &Registry,
+              args: &serde_json::Value,
+              emit: &mut dyn FnMut(serde_json::Value)) -> Result<()>
+    {
+        emit(self.run(client, registry, args)?);
+        return Ok(());
+    }
+
+    /// The result as printed as text or JSON and handed to other programs,
+    /// without the fields only `--format` templates use. By default the
+    /// result itself.
+    fn public(&self, output: &serde_json::Value) -> serde_json::Value
+    {
+        return output.clone();
+    }
+
+    /// Text to print for a result, empty for nothing.
+    fn render(&self, output: &serde_json::Value) -> String
+    {
+        return render_value(output);
+    }
+
+    /// The result as separate records for `--ndjson`: the elements of a
+    /// list, nothing for null, otherwise the result itself.
+    fn records(&self, output: &serde_json::Value) -> Vec<serde_json::Value>
+    {
+        return match output {
+            serde_json::Value::Null => Vec::new(),
+            serde_json::Value::Array(values) => values.clone(),
+            value => vec![value.clone()],
+        };
+    }
+}
+
+/// How the command line tool prints results and errors.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Output
+{
+    /// The command's own rendering, errors as "ERROR: <message>".
+    Text,
+    /// One JSON document, null for commands without a result.
+    Json,
+    /// One JSON document per line, one per record.
+    Ndjson,
+    /// A template filled in from each record, errors as for Text.
+    Template(Template),
+}
+
+/// A command's result as printed in 'format', empty for nothing.
+pub fn format_output(command: &dyn Command,
+                     output: &serde_json::Value,
+                     format: &Output) -> String
+{
+    return match format {
+        Output::Text => command.render(&command.public(output)),
+        Output::Json => command.public(output).to_string(),
+        Output::Ndjson => {
+            command.records(&command.public(output)).iter()
+                .map(|record| record.to_string())
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+        Output::Template(template) => {
+            command.records(output).iter()
+                .map(|record| template.render(record))
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+    };
+}
+
+/// An error as printed in 'format'.
+pub fn format_error(e: &GpmdpError, format: &Output) -> String
+{
+    return match format {
+        Output::Text | Output::Template(_) => format!("ERROR: {}", e),
+        Output::Json | Output::Ndjson => e.to_json().to_string(),
+    };
+}
+
+/*
+ * Strings bare, objects as "key: value" lines, arrays one element a line.
+ */
+pub(crate) fn render_value(value: &serde_json::Value) -> String
+{
+    return match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Object(map) => {
+            map.iter()
+                .map(|(k, v)| format!("{}: {}", k, render_value(v)))
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+        serde_json::Value::Array(values) => {
+            values.iter().map(render_value).collect::<Vec<String>>().join("\n")
+        }
+        value => value.to_string(),
+    };
+}
+
+/// Runs a front end with the registry it's in and the arguments after its
+/// name.
+pub type FrontEndFn = dyn Fn(&Registry, &[String]) -> Result<()> + Send + Sync;
+
+/*
+ * Something run instead of a command, like the shell: it opens its own
+ * connection, if any, and runs commands itself.
+ */
+struct FrontEnd
+{
+    name: &'static str,
+    synopsis: &'static str,
+    run: Box<FrontEndFn>,
+}
+
+/// The commands known to the command line tool, in usage order, and the
+/// front ends it runs instead of a command.
+pub struct Registry
+{
+    commands: Vec<Box<dyn Command>>,
+    front_ends: Vec<FrontEnd>,
+}
+
+impl Registry
+{
+    /// A registry without any commands.
+    pub fn new() -> Registry
+    {
+        return Registry { commands: Vec::new(), front_ends: Vec::new() };
+    }
+
+    /// Add a command, replacing any with the same name.
+    pub fn register(&mut self, command: Box<dyn Command>)
+    {
+        match self.commands.iter().position(|c| c.name() == command.name()) {
+            Some(i) => self.commands[i] = command,
+            None => self.commands.push(command),
+        }
+    }
+
+    /// Add a front end, replacing any with the same name. It's listed
+    /// after the commands.
+    pub fn register_front_end(&mut self,
+                              name: &'static str,
+                              synopsis: &'static str,
+                              run: Box<FrontEndFn>)
+    {
+        let front_end = FrontEnd { name, synopsis, run };
+        match self.front_ends.iter().position(|f| f.name == name) {
+            Some(i) => self.front_ends[i] = front_end,
+            None => self.front_ends.push(front_end),
+        }
+    }
+
+    /// Run the front end named by argv[0] with the rest of it. None if
+    /// there's no such front end.
+    pub fn run_front_end(&self, argv: &[String]) -> Option<Result<()>>
+    {
+        let name = argv.first()?;
+        let front_end = self.front_ends.iter().find(|f| f.name == name)?;
+        return Some((front_end.run)(self, &argv[1..]));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Command>
+    {
+        return self.commands.iter()
+            .find(|c| c.name() == name)
+            .map(|c| c.as_ref());
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &dyn Command>
+    {
+        return self.commands.iter().map(|c| c.as_ref());
+    }
+
+    /// Look up the command named by argv[0] and parse the rest as its
+    /// arguments.
+    pub fn parse(&self, argv: &[String]) -> Result<(&dyn Command, serde_json::Value)>
+    {
+        let name = match argv.first() {
+            Some(name) => name,
+            None => return Err(GpmdpError::InvalidArgument("no command given".to_string())),
+        };
+        let command = match self.get(name) {
+            Some(command) => command,
+            None => {
+                return Err(GpmdpError::InvalidArgument(
+                    format!("unknown command '{}'", name)));
+            }
+        };
+        return Ok((command, command.parse(&argv[1..])?));
+    }
+
+    /// One "  <name> <synopsis>" line per command, then per front end.
+    pub fn usage(&self) -> String
+    {
+        return self.commands()
+            .map(|c| (c.name(), c.synopsis()))
+            .chain(self.front_ends.iter().map(|f| (f.name, f.synopsis)))
+            .map(|(name, synopsis)| format!("  {} {}", name, synopsis).trim_end().to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+    }
+}
+
+impl Default for Registry
+{
+    /// The built in commands.
+    fn default() -> Registry
+    {
+        let mut registry = Registry::new();
+        builtin::register(&mut registry);
+        return registry;
+    }
+}
+
+/// Wait for the channels a command needs, then run it with the registry
+/// it was looked up in.
+pub fn execute(command: &dyn Command,
+               client: &GpmdpClient,
+               registry: &Registry,
+               args: &serde_json::Value) -> Result<serde_json::Value>
+{
+    let chnls = command.channels(args);
+    if chnls != 0 {
+        client.wait_for_channels(chnls)?;
+    }
+    return command.run(client, registry, args);
+}
+
+/// Wait for the channels a command needs, then stream its results.
+pub fn execute_stream(command: &dyn Command,
+                      client: &GpmdpClient,
+                      registry: &Registry,
+                      args: &serde_json::Value,
+                      emit: &mut dyn FnMut(serde_json::Value)) -> Result<()>
+{
+    let chnls = command.channels(args);
+    if chnls != 0 {
+        client.wait_for_channels(chnls)?;
+    }
+    return command.stream(client, registry, args, emit);
+}
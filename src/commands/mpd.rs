@@ -0,0 +1,698 @@
+//! `mpd-bridge`: an MPD protocol server in front of GPMDP.
+//!
+//! MPD clients (mpc, ncmpcpp, phone apps, status bar widgets) connect to the
+//! bridge as they would to MPD. It answers the part of the protocol that
+//! maps onto GPMDP: playback, volume, shuffle and repeat, the queue, stored
+//! playlists and search. Status is answered from the pushed player state
+//! and `idle` is woken up by channel pushes:
+//!
+//! * `player`: playState and track
+//! * `mixer`: volume
+//! * `options`: shuffle and repeat
+//! * `playlist`: queue
+//! * `stored_playlist`: playlists
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use serde_json::json;
+use crate::client::GpmdpClient;
+use crate::commands::builtin::{parse_options, repeat_str};
+use crate::commands::{Command, Registry};
+use crate::error::*;
+use crate::events::*;
+use crate::models::Track;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:6600";
+
+/// How long a connection's event thread takes to notice it has ended.
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+/// The protocol version clients are greeted with.
+const GREETING: &str = "OK MPD 0.23.0\n";
+
+/* MPD's ACK error codes */
+const ACK_ERROR_ARG: u32 = 2;
+const ACK_ERROR_UNKNOWN: u32 = 5;
+const ACK_ERROR_NO_EXIST: u32 = 50;
+const ACK_ERROR_SYSTEM: u32 = 52;
+
+/// What `commands` lists.
+const COMMANDS: [&str; 22] = [
+    "close", "commands", "currentsong", "idle", "listplaylists", "load", "next",
+    "noidle", "notcommands", "pause", "ping", "play", "playlistinfo", "previous",
+    "random", "repeat", "search", "seekcur", "setvol", "single", "status", "tagtypes",
+];
+
+const SUBSYSTEMS: [&str; 5] = ["player", "mixer", "options", "playlist", "stored_playlist"];
+
+/// Tags songs have and `search` matches on.
+const TAGS: [(&str, &str); 4] = [
+    ("Artist", "artist"),
+    ("Album", "album"),
+    ("AlbumArtist", "albumartist"),
+    ("Title", "title"),
+];
+
+/*
+ * A command's failure, written as "ACK [<code>@<list index>] {<command>}
+ * <message>".
+ */
+struct Ack
+{
+    code: u32,
+    message: String,
+}
+
+fn ack(code: u32, message: &str) -> Ack
+{
+    return Ack { code, message: message.to_string() };
+}
+
+impl From<GpmdpError> for Ack
+{
+    fn from(e: GpmdpError) -> Ack
+    {
+        let code = match e {
+            GpmdpError::InvalidArgument(_) => ACK_ERROR_ARG,
+            _ => ACK_ERROR_SYSTEM,
+        };
+        return Ack { code, message: e.to_string() };
+    }
+}
+
+type Response = std::result::Result<String, Ack>;
+
+/*
+ * Split a command line into words. Words are separated by whitespace and
+ * "..." quotes, in which a backslash escapes the next character.
+ */
+fn split_args(line: &str) -> std::result::Result<Vec<String>, Ack>
+{
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => word.extend(chars.next()),
+                    Some(c) => word.push(c),
+                    None => return Err(ack(ACK_ERROR_ARG, "Missing closing '\"'")),
+                }
+            }
+        } else {
+            word.push(c);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+    return Ok(words);
+}
+
+fn pair(out: &mut String, key: &str, value: impl Display)
+{
+    out.push_str(&format!("{}: {}\n", key, value));
+}
+
+fn tag<'a>(track: &'a Track, name: &str) -> &'a str
+{
+    return match name {
+        "Artist" => track.artist(),
+        "Album" => track.album(),
+        "AlbumArtist" => track.album_artist.as_deref().unwrap_or(""),
+        _ => track.title(),
+    };
+}
+
+/*
+ * A song's lines. 'pos' is its 0-based queue position, queue songs also
+ * get that plus one as their id.
+ */
+fn song(track: &Track, pos: Option<usize>) -> String
+{
+    let mut out = String::new();
+    let file = track.id.clone()
+        .unwrap_or_else(|| format!("{} - {}", track.artist(), track.title()));
+    pair(&mut out, "file", file);
+    for (name, _) in TAGS.iter() {
+        let value = tag(track, name);
+        if !value.is_empty() {
+            pair(&mut out, name, value);
+        }
+    }
+    if let Some(ms) = track.duration {
+        pair(&mut out, "Time", ms / 1000);
+        pair(&mut out, "duration", format!("{:.3}", ms as f64 / 1000.0));
+    }
+    if let Some(pos) = pos {
+        pair(&mut out, "Pos", pos);
+        pair(&mut out, "Id", pos + 1);
+    }
+    return out;
+}
+
+/*
+ * The queue's version for `status`, a number that changes when the queue
+ * does.
+ */
+fn queue_version(queue: &[Track]) -> u32
+{
+    let mut hasher = DefaultHasher::new();
+    for track in queue {
+        track.id.hash(&mut hasher);
+        track.title().hash(&mut hasher);
+    }
+    return hasher.finish() as u32;
+}
+
+fn status(state: &PlayerState) -> String
+{
+    let stopped = state.cur_track.title().is_empty();
+    let repeat = repeat_str(&state.cur_repeat);
+
+    let mut out = String::new();
+    pair(&mut out, "volume", state.cur_volume);
+    pair(&mut out, "repeat", (repeat != "off") as u8);
+    pair(&mut out, "random", (state.cur_shuffle != "NO_SHUFFLE") as u8);
+    pair(&mut out, "single", (repeat == "single") as u8);
+    pair(&mut out, "consume", 0);
+    pair(&mut out, "playlist", queue_version(&state.cur_queue));
+    pair(&mut out, "playlistlength", state.queue_length());
+    pair(&mut out, "state", if stopped { "stop" } else if state.cur_playing { "play" } else { "pause" });
+    if stopped {
+        return out;
+    }
+    if let Some(pos) = state.queue_position().checked_sub(1) {
+        pair(&mut out, "song", pos);
+        pair(&mut out, "songid", pos + 1);
+    }
+    pair(&mut out, "time", format!("{}:{}", state.cur_track_progress / 1000,
+                                   state.cur_track_total / 1000));
+    pair(&mut out, "elapsed", format!("{:.3}", state.cur_track_progress as f64 / 1000.0));
+    pair(&mut out, "duration", format!("{:.3}", state.cur_track_total as f64 / 1000.0));
+    return out;
+}
+
+fn num_arg<T: FromStr>(arg: &str) -> std::result::Result<T, Ack>
+{
+    return arg.parse().map_err(|_| ack(ACK_ERROR_ARG, &format!("Integer expected: {}", arg)));
+}
+
+fn bool_arg(arg: &str) -> std::result::Result<bool, Ack>
+{
+    return match arg {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(ack(ACK_ERROR_ARG, &format!("Boolean (0/1) expected: {}", arg))),
+    };
+}
+
+/*
+ * The queue positions of "<pos>", "<start>:<end>" or "<start>:".
+ */
+fn range_arg(arg: &str, len: usize) -> std::result::Result<std::ops::Range<usize>, Ack>
+{
+    let range = match arg.split_once(':') {
+        Some((start, "")) => num_arg(start)?..len,
+        Some((start, end)) => num_arg(start)?..num_arg::<usize>(end)?.min(len),
+        None => {
+            let pos: usize = num_arg(arg)?;
+            pos..pos + 1
+        }
+    };
+    if range.start > range.end || range.end > len {
+        return Err(ack(ACK_ERROR_ARG, "Bad song index"));
+    }
+    return Ok(range);
+}
+
+/*
+ * `search <tag> <what> [<tag> <what> ...]`: GPMDP searches for all the
+ * 'what's together, the tracks found are kept if each tag contains its
+ * 'what', ignoring case. `any` matches any tag.
+ */
+fn search(client: &GpmdpClient, args: &[String]) -> Response
+{
+    if args.len() == 1 && args[0].starts_with('(') {
+        return Err(ack(ACK_ERROR_ARG, "filter expressions are not supported"));
+    }
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(ack(ACK_ERROR_ARG, "wrong number of arguments for \"search\""));
+    }
+
+    let mut filters = Vec::new();
+    for pair in args.chunks(2) {
+        let name = pair[0].to_lowercase();
+        let tag = match TAGS.iter().find(|(_, lower)| *lower == name) {
+            Some((tag, _)) => Some(*tag),
+            None if name == "any" => None,
+            None => return Err(ack(ACK_ERROR_ARG, &format!("Unknown tag type: {}", pair[0]))),
+        };
+        filters.push((tag, pair[1].to_lowercase()));
+    }
+
+    let text: Vec<&str> = args.iter().skip(1).step_by(2).map(String::as_str).collect();
+    let results = client.search(&text.join(" "))?;
+    return Ok(results.tracks.iter()
+              .filter(|track| {
+                  filters.iter().all(|(name, what)| match name {
+                      Some(name) => tag(track, name).to_lowercase().contains(what),
+                      None => true,
+                  })
+              })
+              .map(|track| song(track, None))
+              .collect());
+}
+
+/*
+ * Run one command, other than the idle and command list ones, and return
+ * its response lines.
+ */
+fn command(client: &GpmdpClient, name: &str, args: &[String]) -> Response
+{
+    let state = client.state();
+    let count = |min: usize, max: usize| -> std::result::Result<(), Ack> {
+        if args.len() < min || args.len() > max {
+            return Err(ack(ACK_ERROR_ARG, &format!("wrong number of arguments for \"{}\"", name)));
+        }
+        return Ok(());
+    };
+    let empty = |res: Result<()>| -> Response { return res.map(|_| String::new()).map_err(Ack::from) };
+
+    return match name {
+        "ping" => count(0, 0).map(|_| String::new()),
+        "commands" => Ok(COMMANDS.iter().map(|command| format!("command: {}\n", command)).collect()),
+        "notcommands" => Ok(String::new()),
+        "tagtypes" => {
+            /* "tagtypes enable ..." and the like are accepted, all tags are sent anyway */
+            if !args.is_empty() {
+                return Ok(String::new());
+            }
+            Ok(TAGS.iter().map(|(tag, _)| format!("tagtype: {}\n", tag)).collect())
+        }
+        "status" => count(0, 0).map(|_| status(&state)),
+        "currentsong" => {
+            count(0, 0)?;
+            if state.cur_track.title().is_empty() {
+                return Ok(String::new());
+            }
+            Ok(song(&state.cur_track, state.queue_position().checked_sub(1)))
+        }
+        "playlistinfo" => {
+            count(0, 1)?;
+            let range = match args.first() {
+                Some(arg) => range_arg(arg, state.queue_length())?,
+                None => 0..state.queue_length(),
+            };
+            Ok(range.map(|pos| song(&state.cur_queue[pos], Some(pos))).collect())
+        }
+        "play" => {
+            count(0, 1)?;
+            match args.first() {
+                Some(arg) => {
+                    let pos = num_arg::<usize>(arg)?;
+                    if pos >= state.queue_length() {
+                        return Err(ack(ACK_ERROR_ARG, "Bad song index"));
+                    }
+                    empty(client.play_queue_track(pos + 1))
+                }
+                None if state.cur_playing => Ok(String::new()),
+                None => empty(client.play_pause()),
+            }
+        }
+        "pause" => {
+            count(0, 1)?;
+            /* GPMDP only toggles, which is right unless already in the state asked for */
+            match args.first() {
+                Some(arg) if bool_arg(arg)? != state.cur_playing => Ok(String::new()),
+                _ => empty(client.play_pause()),
+            }
+        }
+        "next" => count(0, 0).and_then(|_| empty(client.forward())),
+        "previous" => count(0, 0).and_then(|_| empty(client.rewind())),
+        "seekcur" => {
+            count(1, 1)?;
+            let secs: f64 = args[0].parse()
+                .map_err(|_| ack(ACK_ERROR_ARG, &format!("Number expected: {}", args[0])))?;
+            if args[0].starts_with('+') || args[0].starts_with('-') {
+                empty(client.seek((secs * 1000.0) as i64))
+            } else if secs < 0.0 {
+                Err(ack(ACK_ERROR_ARG, "Bad time"))
+            } else {
+                empty(client.set_current_time((secs * 1000.0) as u64))
+            }
+        }
+        "setvol" => {
+            count(1, 1)?;
+            let level: u32 = num_arg(&args[0])?;
+            if level > 100 {
+                return Err(ack(ACK_ERROR_ARG, "Invalid volume value"));
+            }
+            empty(client.set_volume(level))
+        }
+        "random" => {
+            count(1, 1)?;
+            empty(client.set_shuffle(if bool_arg(&args[0])? { "ALL_SHUFFLE" } else { "NO_SHUFFLE" }))
+        }
+        /* GPMDP's single repeat is MPD's repeat and single together */
+        "repeat" => {
+            count(1, 1)?;
+            let mode = match (bool_arg(&args[0])?, repeat_str(&state.cur_repeat)) {
+                (false, _) => "NO_REPEAT",
+                (true, "single") => "SINGLE_REPEAT",
+                (true, _) => "LIST_REPEAT",
+            };
+            empty(client.set_repeat(mode))
+        }
+        "single" => {
+            count(1, 1)?;
+            match (bool_arg(&args[0])?, repeat_str(&state.cur_repeat)) {
+                (true, _) => empty(client.set_repeat("SINGLE_REPEAT")),
+                (false, "single") => empty(client.set_repeat("LIST_REPEAT")),
+                (false, _) => Ok(String::new()),
+            }
+        }
+        "listplaylists" => {
+            count(0, 0)?;
+            let mut out = String::new();
+            for playlist in state.cur_playlists.iter() {
+                pair(&mut out, "playlist", playlist.name());
+                pair(&mut out, "Last-Modified", "1970-01-01T00:00:00Z");
+            }
+            Ok(out)
+        }
+        "load" => {
+            count(1, 1)?;
+            match state.cur_playlists.iter().position(|playlist| playlist.name() == args[0]) {
+                Some(i) => empty(client.play_playlist(i + 1)),
+                None => Err(ack(ACK_ERROR_NO_EXIST, "No such playlist")),
+            }
+        }
+        "search" => search(client, args),
+        _ => Err(ack(ACK_ERROR_UNKNOWN, &format!("unknown command \"{}\"", name))),
+    };
+}
+
+/*
+ * Run the lines of a command list, or a single command as a list of one.
+ * Stops at the first failure.
+ */
+fn command_list(client: &GpmdpClient, lines: &[String], list_ok: bool) -> String
+{
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let (name, res) = match split_args(line) {
+            Ok(words) if words.is_empty() => {
+                (String::new(), Err(ack(ACK_ERROR_UNKNOWN, "No command given")))
+            }
+            Ok(words) => (words[0].clone(), command(client, &words[0], &words[1..])),
+            Err(e) => (String::new(), Err(e)),
+        };
+        match res {
+            Ok(response) => out.push_str(&response),
+            Err(e) => {
+                out.push_str(&format!("ACK [{}@{}] {{{}}} {}\n", e.code, i, name, e.message));
+                return out;
+            }
+        }
+        if list_ok {
+            out.push_str("list_OK\n");
+        }
+    }
+    out.push_str("OK\n");
+    return out;
+}
+
+fn subsystem(event: &ChannelEvent) -> Option<&'static str>
+{
+    return match event {
+        ChannelEvent::PlayState(_) | ChannelEvent::Track(_) => Some("player"),
+        ChannelEvent::Volume(_)                             => Some("mixer"),
+        ChannelEvent::Shuffle(_) | ChannelEvent::Repeat(_)  => Some("options"),
+        ChannelEvent::Queue(_)                              => Some("playlist"),
+        ChannelEvent::Playlists(_)                          => Some("stored_playlist"),
+        _                                                   => None,
+    };
+}
+
+/*
+ * What wakes a connection up: a line from the client, a change in a
+ * subsystem, the client going away or GPMDP closing the connection.
+ */
+enum Input
+{
+    Line(String),
+    Changed(&'static str),
+    Eof,
+    Closed,
+}
+
+/*
+ * Take the changes 'filter' waits for, all of them if it's empty, and
+ * return their idle response.
+ */
+fn take_changes(changed: &mut Vec<&'static str>, filter: &[String]) -> Option<String>
+{
+    let (taken, kept): (Vec<&str>, Vec<&str>) = changed.iter()
+        .partition(|name| filter.is_empty() || filter.iter().any(|f| f == *name));
+    if taken.is_empty() {
+        return None;
+    }
+    *changed = kept;
+    let mut out = String::new();
+    for name in taken {
+        pair(&mut out, "changed", name);
+    }
+    out.push_str("OK\n");
+    return Some(out);
+}
+
+/*
+ * Talk to one client until it closes the connection or GPMDP does. The
+ * connection's subscription goes with it, time ticks alone don't end it.
+ */
+fn connection(client: &GpmdpClient, stream: &TcpStream) -> std::io::Result<()>
+{
+    let (tx, inputs) = mpsc::channel();
+    let done = AtomicBool::new(false);
+
+    return thread::scope(|scope| {
+        let events = client.subscribe();
+        let events_tx = tx.clone();
+        let done = &done;
+        scope.spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                match events.recv_timeout(STOP_POLL) {
+                    Ok(event) => {
+                        if let Some(name) = subsystem(&event) {
+                            let _ = events_tx.send(Input::Changed(name));
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        let _ = events_tx.send(Input::Closed);
+                        return;
+                    }
+                }
+            }
+        });
+
+        let reader = stream.try_clone()?;
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                match line {
+                    Ok(line) => {
+                        if tx.send(Input::Line(line)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_err) => break,
+                }
+            }
+            let _ = tx.send(Input::Eof);
+        });
+
+        let res = session(client, stream, &inputs);
+        done.store(true, Ordering::Relaxed);
+        return res;
+    });
+}
+
+/*
+ * Answer the client's commands, and its idles from the changes, as they
+ * come in on 'inputs'.
+ */
+fn session(client: &GpmdpClient,
+           stream: &TcpStream,
+           inputs: &mpsc::Receiver<Input>) -> std::io::Result<()>
+{
+    let mut out = stream;
+    out.write_all(GREETING.as_bytes())?;
+
+    /* subsystems changed since the last idle, in order */
+    let mut changed: Vec<&'static str> = Vec::new();
+    let mut idle: Option<Vec<String>> = None;
+    /* lines of the command list being read and whether it's a list_OK one */
+    let mut list: Option<(Vec<String>, bool)> = None;
+
+    loop {
+        let line = match inputs.recv() {
+            Ok(Input::Line(line)) => line,
+            Ok(Input::Changed(name)) => {
+                if !changed.contains(&name) {
+                    changed.push(name);
+                }
+                if let Some(response) = idle.as_deref().and_then(|f| take_changes(&mut changed, f)) {
+                    out.write_all(response.as_bytes())?;
+                    idle = None;
+                }
+                continue;
+            }
+            Ok(Input::Eof) | Ok(Input::Closed) | Err(_) => return Ok(()),
+        };
+
+        if idle.is_some() {
+            /* only noidle may be sent while idle */
+            if line.trim() != "noidle" {
+                return Ok(());
+            }
+            out.write_all(b"OK\n")?;
+            idle = None;
+            continue;
+        }
+
+        if let Some((lines, list_ok)) = &mut list {
+            if line.trim() == "command_list_end" {
+                out.write_all(command_list(client, lines, *list_ok).as_bytes())?;
+                list = None;
+            } else {
+                lines.push(line);
+            }
+            continue;
+        }
+
+        let words = split_args(&line).unwrap_or_default();
+        match words.first().map(String::as_str) {
+            Some("command_list_begin") => list = Some((Vec::new(), false)),
+            Some("command_list_ok_begin") => list = Some((Vec::new(), true)),
+            Some("close") => return Ok(()),
+            Some("noidle") => {}
+            Some("idle") => {
+                let filter = words[1..].to_vec();
+                if let Some(unknown) = filter.iter().find(|f| !SUBSYSTEMS.contains(&f.as_str())) {
+                    let response = format!("ACK [{}@0] {{idle}} Unrecognized idle event: {}\n",
+                                           ACK_ERROR_ARG, unknown);
+                    out.write_all(response.as_bytes())?;
+                    continue;
+                }
+                match take_changes(&mut changed, &filter) {
+                    Some(response) => out.write_all(response.as_bytes())?,
+                    None => idle = Some(filter),
+                }
+            }
+            _ => out.write_all(command_list(client, &[line], false).as_bytes())?,
+        }
+    }
+}
+
+pub(crate) struct MpdBridge;
+
+impl MpdBridge
+{
+    /*
+     * Accept MPD clients until GPMDP closes the connection.
+     */
+    fn serve(&self, client: &GpmdpClient, listen: &str) -> Result<()>
+    {
+        let listener = TcpListener::bind(listen).map_err(|err| {
+            return GpmdpError::Connection(format!("failed to listen on {}: {}", listen, err));
+        })?;
+        let addr = listener.local_addr().map_err(|err| GpmdpError::Connection(err.to_string()))?;
+
+        /* wake up accept() when the connection closes */
+        let closed = client.subscribe();
+        thread::spawn(move || {
+            for _ in closed {}
+            let _ = TcpStream::connect(addr);
+        });
+        if let Some(e) = client.close_reason() {
+            return Err(e);
+        }
+
+        return thread::scope(|scope| {
+            for stream in listener.incoming() {
+                if let Some(e) = client.close_reason() {
+                    return Err(e);
+                }
+                match stream {
+                    Ok(stream) => {
+                        scope.spawn(move || {
+                            /* a client that goes away is no error */
+                            let _ = connection(client, &stream);
+                            let _ = stream.shutdown(Shutdown::Both);
+                        });
+                    }
+                    Err(err) => {
+                        return Err(GpmdpError::Connection(
+                            format!("failed to accept on {}: {}", listen, err)));
+                    }
+                }
+            }
+            return Ok(());
+        });
+    }
+}
+
+impl Command for MpdBridge
+{
+    fn name(&self) -> &'static str { "mpd-bridge" }
+    fn synopsis(&self) -> &'static str { "[ --listen <addr>:<port> ]" }
+    fn long_running(&self) -> bool { true }
+
+    /// {"listen": address}.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        let options = parse_options(self.name(), args, &["--listen"])?;
+        return Ok(json!({ "listen": options.get("--listen").copied().unwrap_or(DEFAULT_LISTEN) }));
+    }
+
+    fn channels(&self, _args: &serde_json::Value) -> u64
+    {
+        return CHNL_PLAYSTATE |
+               CHNL_TRACK |
+               CHNL_TIME |
+               CHNL_SHUFFLE |
+               CHNL_REPEAT |
+               CHNL_PLAYLISTS |
+               CHNL_QUEUE |
+               CHNL_VOLUME;
+    }
+
+    /// Serves MPD clients until the connection closes, then fails with
+    /// why it did.
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        self.serve(client, args["listen"].as_str().unwrap_or(DEFAULT_LISTEN))?;
+        return match client.close_reason() {
+            Some(e) => Err(e),
+            None => Ok(serde_json::Value::Null),
+        };
+    }
+}
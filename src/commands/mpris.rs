@@ -0,0 +1,449 @@
+//! `mpris`: GPMDP on the D-Bus session bus as an MPRIS2 media player.
+//!
+//! Media keys, desktop widgets and `playerctl` find it as
+//! `org.mpris.MediaPlayer2.gpmdp_rc`. Methods and property writes are run
+//! on the websocket connection, properties are read from the pushed player
+//! state and `PropertiesChanged` is emitted as channel pushes change them.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use zbus::zvariant::{ObjectPath, Value};
+use zbus::{fdo, interface};
+use crate::client::GpmdpClient;
+use crate::commands::builtin::repeat_str;
+use crate::commands::{Command, Registry};
+use crate::error::*;
+use crate::events::*;
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.gpmdp_rc";
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// The track id for no track, from the MPRIS spec.
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// A time push this far from the last one, in ms, is a seek.
+const SEEK_MSECS: i64 = 3000;
+
+/*
+ * What the D-Bus methods and property writes ask for.
+ */
+enum Action
+{
+    PlayPause,
+    /// Play/pause if the playing state isn't the one given already.
+    SetPlaying(bool),
+    Next,
+    Previous,
+    /// Seek by ms.
+    Seek(i64),
+    /// Seek to ms.
+    SetPosition(u64),
+    Volume(u32),
+    Shuffle(&'static str),
+    Repeat(&'static str),
+}
+
+/*
+ * What wakes the service up: a channel push, an action from D-Bus with
+ * where to send its result, or the connection closing.
+ */
+enum Input
+{
+    Changed(Box<ChannelEvent>),
+    Call(Action, mpsc::Sender<Result<()>>),
+    Closed,
+}
+
+fn dbus_error(err: zbus::Error) -> GpmdpError
+{
+    return GpmdpError::Connection(format!("D-Bus: {}", err));
+}
+
+fn playback_status(state: &PlayerState) -> &'static str
+{
+    return if state.cur_track.title().is_empty() {
+        "Stopped"
+    } else if state.cur_playing {
+        "Playing"
+    } else {
+        "Paused"
+    };
+}
+
+fn loop_status(state: &PlayerState) -> &'static str
+{
+    return match repeat_str(&state.cur_repeat) {
+        "all" => "Playlist",
+        "single" => "Track",
+        _ => "None",
+    };
+}
+
+/*
+ * The track id, an object path made of the GPMDP track id.
+ */
+fn current_track_id(state: &PlayerState) -> ObjectPath<'static>
+{
+    let path = match state.cur_track.id.as_deref() {
+        Some(id) if !state.cur_track.title().is_empty() => {
+            let id: String = id.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("/org/mpris/MediaPlayer2/gpmdp_rc/track/t{}", id)
+        }
+        _ => NO_TRACK.to_string(),
+    };
+    return ObjectPath::try_from(path)
+        .unwrap_or_else(|_| ObjectPath::from_static_str_unchecked(NO_TRACK));
+}
+
+/*
+ * The Metadata property: the current track's xesam and mpris fields.
+ */
+fn metadata(state: &PlayerState) -> HashMap<String, Value<'static>>
+{
+    let track = &state.cur_track;
+    let mut map = HashMap::new();
+    map.insert("mpris:trackid".to_string(), Value::from(current_track_id(state)));
+    if track.title().is_empty() {
+        return map;
+    }
+
+    let mut text = |key: &str, value: &str| {
+        if !value.is_empty() {
+            map.insert(key.to_string(), Value::from(value.to_string()));
+        }
+    };
+    text("xesam:title", track.title());
+    text("xesam:album", track.album());
+    text("mpris:artUrl", track.album_art.as_deref().unwrap_or(""));
+    if !track.artist().is_empty() {
+        map.insert("xesam:artist".to_string(), Value::from(vec![track.artist().to_string()]));
+    }
+    if let Some(album_artist) = track.album_artist.as_deref().filter(|a| !a.is_empty()) {
+        map.insert("xesam:albumArtist".to_string(), Value::from(vec![album_artist.to_string()]));
+    }
+    let length = track.duration.unwrap_or(state.cur_track_total);
+    if length > 0 {
+        map.insert("mpris:length".to_string(), Value::from(length as i64 * 1000));
+    }
+    if let Some(count) = track.play_count {
+        map.insert("xesam:useCount".to_string(), Value::from(count as i32));
+    }
+    return map;
+}
+
+/*
+ * The Player properties a channel push may change, with their values.
+ */
+fn changed_properties(event: &ChannelEvent, state: &PlayerState) -> Vec<(&'static str, Value<'static>)>
+{
+    return match event {
+        ChannelEvent::PlayState(_) => vec![("PlaybackStatus", Value::from(playback_status(state)))],
+        ChannelEvent::Track(_) | ChannelEvent::Time { .. } => {
+            vec![("PlaybackStatus", Value::from(playback_status(state))),
+                 ("Metadata", Value::from(metadata(state)))]
+        }
+        ChannelEvent::Volume(volume) => vec![("Volume", Value::from(*volume as f64 / 100.0))],
+        ChannelEvent::Shuffle(_) => vec![("Shuffle", Value::from(state.cur_shuffle != "NO_SHUFFLE"))],
+        ChannelEvent::Repeat(_) => vec![("LoopStatus", Value::from(loop_status(state)))],
+        _ => Vec::new(),
+    };
+}
+
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root
+{
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool { false }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool { false }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool { false }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str { "Google Play Music Desktop Player" }
+
+    #[zbus(property)]
+    fn desktop_entry(&self) -> &str { "google-play-music-desktop-player" }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> { Vec::new() }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> { Vec::new() }
+}
+
+struct Player
+{
+    state: Arc<Mutex<PlayerState>>,
+    calls: mpsc::Sender<Input>,
+}
+
+impl Player
+{
+    fn state(&self) -> MutexGuard<'_, PlayerState>
+    {
+        return self.state.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /*
+     * Have the service run 'action' on the connection and wait for it.
+     */
+    fn call(&self, action: Action) -> fdo::Result<()>
+    {
+        let (tx, result) = mpsc::channel();
+        let closed = || fdo::Error::Failed("the connection to GPMDP is closed".to_string());
+        self.calls.send(Input::Call(action, tx)).map_err(|_| closed())?;
+        return result.recv().map_err(|_| closed())?
+            .map_err(|e| fdo::Error::Failed(e.to_string()));
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player
+{
+    fn play_pause(&self) -> fdo::Result<()> { self.call(Action::PlayPause) }
+
+    fn play(&self) -> fdo::Result<()> { self.call(Action::SetPlaying(true)) }
+
+    fn pause(&self) -> fdo::Result<()> { self.call(Action::SetPlaying(false)) }
+
+    /// GPMDP can't stop, so this pauses.
+    fn stop(&self) -> fdo::Result<()> { self.call(Action::SetPlaying(false)) }
+
+    fn next(&self) -> fdo::Result<()> { self.call(Action::Next) }
+
+    fn previous(&self) -> fdo::Result<()> { self.call(Action::Previous) }
+
+    /// Seek by 'offset' microseconds.
+    fn seek(&self, offset: i64) -> fdo::Result<()>
+    {
+        return self.call(Action::Seek(offset / 1000));
+    }
+
+    /// Seek to 'position' microseconds, if 'track_id' is still playing.
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()>
+    {
+        if track_id != current_track_id(&self.state()) || position < 0 {
+            return Ok(());
+        }
+        return self.call(Action::SetPosition(position as u64 / 1000));
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &zbus::object_server::SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &'static str { playback_status(&self.state()) }
+
+    /*
+     * zbus signals a writable property once its setter returns, GPMDP's push
+     * of the new value is signaled again. Clients take that as a no-op.
+     */
+
+    #[zbus(property)]
+    fn loop_status(&self) -> &'static str { loop_status(&self.state()) }
+
+    #[zbus(property)]
+    fn set_loop_status(&mut self, value: String) -> fdo::Result<()>
+    {
+        let mode = match value.as_str() {
+            "None" => "NO_REPEAT",
+            "Track" => "SINGLE_REPEAT",
+            "Playlist" => "LIST_REPEAT",
+            _ => return Err(fdo::Error::InvalidArgs(format!("invalid loop status '{}'", value))),
+        };
+        return self.call(Action::Repeat(mode));
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 { 1.0 }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 { 1.0 }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 { 1.0 }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool { self.state().cur_shuffle != "NO_SHUFFLE" }
+
+    #[zbus(property)]
+    fn set_shuffle(&mut self, value: bool) -> fdo::Result<()>
+    {
+        return self.call(Action::Shuffle(if value { "ALL_SHUFFLE" } else { "NO_SHUFFLE" }));
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> { metadata(&self.state()) }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 { self.state().cur_volume as f64 / 100.0 }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, value: f64) -> fdo::Result<()>
+    {
+        return self.call(Action::Volume((value.clamp(0.0, 1.0) * 100.0).round() as u32));
+    }
+
+    /// In microseconds. Clients poll it, changes aren't signaled.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 { self.state().cur_track_progress as i64 * 1000 }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool { true }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool { true }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool { true }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool { true }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool { true }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool { true }
+}
+
+fn perform(client: &GpmdpClient, action: Action) -> Result<()>
+{
+    return match action {
+        Action::PlayPause => client.play_pause(),
+        Action::SetPlaying(playing) if playing == client.state().cur_playing => Ok(()),
+        Action::SetPlaying(_) => client.play_pause(),
+        Action::Next => client.forward(),
+        Action::Previous => client.rewind(),
+        Action::Seek(offset_ms) => client.seek(offset_ms),
+        Action::SetPosition(ms) => client.set_current_time(ms),
+        Action::Volume(level) => client.set_volume(level),
+        Action::Shuffle(mode) => client.set_shuffle(mode),
+        Action::Repeat(mode) => client.set_repeat(mode),
+    };
+}
+
+pub(crate) struct Mpris;
+
+impl Mpris
+{
+    /*
+     * Serve the bus name until the connection closes.
+     */
+    fn serve(&self, client: &GpmdpClient) -> Result<()>
+    {
+        let state = Arc::new(Mutex::new(client.state()));
+        let (tx, inputs) = mpsc::channel();
+
+        let player = Player { state: state.clone(), calls: tx.clone() };
+        let bus = zbus::blocking::connection::Builder::session()
+            .and_then(|builder| builder.name(BUS_NAME))
+            .and_then(|builder| builder.serve_at(PATH, Root))
+            .and_then(|builder| builder.serve_at(PATH, player))
+            .and_then(|builder| builder.build())
+            .map_err(dbus_error)?;
+
+        let events = client.subscribe();
+        thread::spawn(move || {
+            for event in events.iter() {
+                if tx.send(Input::Changed(Box::new(event))).is_err() {
+                    return;
+                }
+            }
+            let _ = tx.send(Input::Closed);
+        });
+
+        /* the values last signaled, so repeated pushes aren't */
+        let mut signaled: HashMap<&'static str, Value<'static>> = HashMap::new();
+        let mut last_time: Option<(u64, u64)> = None;
+
+        loop {
+            match inputs.recv() {
+                Ok(Input::Call(action, result)) => {
+                    let _ = result.send(perform(client, action));
+                }
+                Ok(Input::Changed(event)) => {
+                    let now = client.state();
+                    *state.lock().unwrap_or_else(|e| e.into_inner()) = now.clone();
+
+                    if let ChannelEvent::Time { current, total } = *event {
+                        /* a jump in the same track, rather than the next tick, is a seek */
+                        let seeked = match last_time {
+                            Some((last, last_total)) => {
+                                last_total == total &&
+                                (current as i64 - last as i64).abs() > SEEK_MSECS
+                            }
+                            None => false,
+                        };
+                        last_time = Some((current, total));
+                        if seeked {
+                            bus.emit_signal(None::<&str>, PATH, PLAYER_INTERFACE, "Seeked",
+                                            &(current as i64 * 1000))
+                                .map_err(dbus_error)?;
+                        }
+                    }
+
+                    let changed: HashMap<&str, Value> = changed_properties(&event, &now).into_iter()
+                        .filter(|(name, value)| signaled.get(name) != Some(value))
+                        .collect();
+                    if changed.is_empty() {
+                        continue;
+                    }
+                    for (name, value) in changed.iter() {
+                        signaled.insert(name, value.try_clone().map_err(|e| dbus_error(e.into()))?);
+                    }
+                    bus.emit_signal(None::<&str>, PATH, "org.freedesktop.DBus.Properties",
+                                    "PropertiesChanged",
+                                    &(PLAYER_INTERFACE, changed, Vec::<&str>::new()))
+                        .map_err(dbus_error)?;
+                }
+                Ok(Input::Closed) | Err(_) => return Ok(()),
+            }
+        }
+    }
+}
+
+impl Command for Mpris
+{
+    fn name(&self) -> &'static str { "mpris" }
+    fn long_running(&self) -> bool { true }
+
+    fn channels(&self, _args: &serde_json::Value) -> u64
+    {
+        return CHNL_PLAYSTATE |
+               CHNL_TRACK |
+               CHNL_TIME |
+               CHNL_SHUFFLE |
+               CHNL_REPEAT |
+               CHNL_VOLUME;
+    }
+
+    /// Serves MPRIS until the connection closes, then fails with why it
+    /// did.
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        self.serve(client)?;
+        return match client.close_reason() {
+            Some(e) => Err(e),
+            None => Ok(serde_json::Value::Null),
+        };
+    }
+}
@@ -0,0 +1,332 @@
+//! `mqtt`: GPMDP's player state on an MQTT broker, for home automation.
+//!
+//! The playState, track, time, volume, shuffle, repeat and rating channels
+//! are published, retained, to `<prefix>/<channel>` as the JSON `watch`
+//! prints for them. Commands are taken on `<prefix>/set/<command>`, the
+//! payload being the command's arguments, and their errors published to
+//! `<prefix>/error`. `<prefix>/availability` is `online` while the bridge
+//! is connected to GPMDP and `offline` otherwise.
+//!
+//! With `--discovery` the entities are announced to Home Assistant under
+//! its discovery prefix.
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde_json::json;
+use crate::client::GpmdpClient;
+use crate::commands::builtin::{event_json, invalid, parse_options, Dedupe};
+use crate::commands::{execute, Command, Registry};
+use crate::error::*;
+use crate::events::*;
+
+const DEFAULT_BROKER: &str = "127.0.0.1:1883";
+const DEFAULT_PREFIX: &str = "gpmdp";
+
+/// Where the password for `--user` is read from.
+const PASSWORD_VAR: &str = "GPMDP_RC_MQTT_PASSWORD";
+
+const CHANNELS: u64 = CHNL_PLAYSTATE |
+                      CHNL_TRACK |
+                      CHNL_TIME |
+                      CHNL_VOLUME |
+                      CHNL_SHUFFLE |
+                      CHNL_REPEAT |
+                      CHNL_RATING;
+
+/// The commands that can be run from `<prefix>/set/<command>`.
+const SET_COMMANDS: [&str; 15] = [
+    "play", "pause", "next", "prev", "replay", "seek", "skip30", "back10", "thumbs",
+    "rating", "shuffle", "repeat", "volume", "playlist", "clear",
+];
+
+/// The commands taking no arguments, whose payload is ignored: Home
+/// Assistant's buttons send `PRESS`.
+const BUTTONS: [&str; 7] = ["pause", "next", "prev", "replay", "skip30", "back10", "clear"];
+
+/// How long to wait before trying a lost broker again.
+const RETRY: Duration = Duration::from_secs(1);
+
+/*
+ * What wakes the bridge up: a channel push, the broker accepting the
+ * connection, a command published to it, a broker error or the connection
+ * to GPMDP closing.
+ */
+enum Input
+{
+    Changed(Box<ChannelEvent>),
+    Connected,
+    Set(String, String),
+    BrokerError(String),
+    Closed,
+}
+
+/*
+ * Split "<host>:<port>".
+ */
+fn broker_addr(broker: &str) -> Result<(String, u16)>
+{
+    return match broker.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() => {
+            let port = port.parse::<u16>()
+                .map_err(|_| invalid(&format!("invalid broker port '{}'", port)))?;
+            Ok((host.to_string(), port))
+        }
+        _ => Err(invalid(&format!("invalid broker '{}', expected <host>:<port>", broker))),
+    };
+}
+
+/*
+ * The Home Assistant discovery topics and configs for the entities.
+ */
+fn discovery(prefix: &str, discovery_prefix: &str) -> Vec<(String, serde_json::Value)>
+{
+    let node: String = prefix.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let topic = |name: &str| format!("{}/{}", prefix, name);
+    let entities = [
+        ("sensor", "state", json!({
+            "name": "State",
+            "state_topic": topic("playState"),
+            "value_template": "{{ 'playing' if value_json.playing else 'paused' }}",
+        })),
+        ("sensor", "track", json!({
+            "name": "Track",
+            "state_topic": topic("track"),
+            "value_template": "{{ value_json.artist }} - {{ value_json.title }}",
+            "json_attributes_topic": topic("track"),
+        })),
+        ("sensor", "rating", json!({
+            "name": "Rating",
+            "state_topic": topic("rating"),
+            "value_template": "{{ value_json.rating }}",
+        })),
+        ("number", "volume", json!({
+            "name": "Volume",
+            "state_topic": topic("volume"),
+            "value_template": "{{ value_json.volume }}",
+            "command_topic": topic("set/volume"),
+            "min": 0,
+            "max": 100,
+        })),
+        ("switch", "shuffle", json!({
+            "name": "Shuffle",
+            "state_topic": topic("shuffle"),
+            "value_template": "{{ value_json.shuffle }}",
+            "command_topic": topic("set/shuffle"),
+            "payload_on": "on",
+            "payload_off": "off",
+            "state_on": "on",
+            "state_off": "off",
+        })),
+        ("select", "repeat", json!({
+            "name": "Repeat",
+            "state_topic": topic("repeat"),
+            "value_template": "{{ value_json.repeat }}",
+            "command_topic": topic("set/repeat"),
+            "options": ["off", "all", "single"],
+        })),
+        ("button", "play_pause", json!({ "name": "Play/Pause", "command_topic": topic("set/pause") })),
+        ("button", "next", json!({ "name": "Next", "command_topic": topic("set/next") })),
+        ("button", "prev", json!({ "name": "Previous", "command_topic": topic("set/prev") })),
+    ];
+
+    return entities.iter()
+        .map(|(component, object, config)| {
+            let mut config = config.clone();
+            config["unique_id"] = json!(format!("{}_{}", node, object));
+            config["availability_topic"] = json!(topic("availability"));
+            config["device"] = json!({
+                "identifiers": [node],
+                "name": "GPMDP",
+                "model": "Google Play Music Desktop Player",
+            });
+            return (format!("{}/{}/{}/{}/config", discovery_prefix, component, node, object), config);
+        })
+        .collect();
+}
+
+pub(crate) struct Mqtt;
+
+impl Mqtt
+{
+    /*
+     * Publish to the broker until the connection closes, running the
+     * commands set in 'registry'.
+     */
+    fn serve(&self, client: &GpmdpClient, registry: &Registry, args: &serde_json::Value) -> Result<()>
+    {
+        let broker = args["broker"].as_str().unwrap_or(DEFAULT_BROKER);
+        let (host, port) = broker_addr(broker)?;
+        let prefix = args["prefix"].as_str().unwrap_or(DEFAULT_PREFIX);
+        let topic = |name: &str| format!("{}/{}", prefix, name);
+
+        let mut options = MqttOptions::new(format!("gpmdp_rc-{}", std::process::id()), host, port);
+        options.set_last_will(LastWill::new(topic("availability"), "offline", QoS::AtLeastOnce, true));
+        if let Some(user) = args["user"].as_str() {
+            options.set_credentials(user, std::env::var(PASSWORD_VAR).unwrap_or_default());
+        }
+        let (mqtt, mut connection) = Client::new(options, 64);
+        /* state is dropped rather than waited on while the broker is away */
+        let publish = |name: &str, payload: String, retain: bool| {
+            let _ = mqtt.try_publish(name, QoS::AtLeastOnce, retain, payload);
+        };
+
+        let (tx, inputs) = mpsc::channel();
+
+        let events = client.subscribe();
+        let events_tx = tx.clone();
+        thread::spawn(move || {
+            for event in events.iter() {
+                if event.channel_bit() & CHANNELS != 0 &&
+                   events_tx.send(Input::Changed(Box::new(event))).is_err() {
+                    return;
+                }
+            }
+            let _ = events_tx.send(Input::Closed);
+        });
+        if let Some(e) = client.close_reason() {
+            return Err(e);
+        }
+
+        let set_prefix = topic("set/");
+        let poller = thread::spawn(move || {
+            for notification in connection.iter() {
+                let input = match notification {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => Input::Connected,
+                    Ok(Event::Incoming(Packet::Publish(message))) => {
+                        match message.topic.strip_prefix(&set_prefix) {
+                            Some(name) => {
+                                Input::Set(name.to_string(),
+                                           String::from_utf8_lossy(&message.payload).into_owned())
+                            }
+                            None => continue,
+                        }
+                    }
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => return,
+                    Ok(_) => continue,
+                    Err(err) => Input::BrokerError(err.to_string()),
+                };
+                let failed = matches!(input, Input::BrokerError(_));
+                if tx.send(input).is_err() {
+                    return;
+                }
+                /* the next poll tries to reconnect */
+                if failed {
+                    thread::sleep(RETRY);
+                }
+            }
+        });
+
+        let mut connected = false;
+        let mut dedupe = Dedupe::new(1);
+        loop {
+            match inputs.recv() {
+                Ok(Input::Connected) => {
+                    connected = true;
+                    let _ = mqtt.try_subscribe(topic("set/+"), QoS::AtLeastOnce);
+                    if let Some(discovery_prefix) = args["discovery"].as_str() {
+                        for (name, config) in discovery(prefix, discovery_prefix) {
+                            publish(&name, config.to_string(), true);
+                        }
+                    }
+                    publish(&topic("availability"), "online".to_string(), true);
+                    /* the retained state may be gone with the broker, send all of it */
+                    let state = client.state();
+                    for event in state.events(CHANNELS) {
+                        if let Some(event_js) = event_json(&event, &state) {
+                            dedupe.is_new(&event_js);
+                            publish(&topic(event.channel()), event_js.to_string(), true);
+                        }
+                    }
+                }
+                Ok(Input::Changed(event)) => {
+                    if let Some(event_js) = event_json(&event, &client.state()) {
+                        if dedupe.is_new(&event_js) {
+                            publish(&topic(event.channel()), event_js.to_string(), true);
+                        }
+                    }
+                }
+                Ok(Input::Set(name, payload)) => {
+                    let mut argv = vec![name.clone()];
+                    if !BUTTONS.contains(&name.as_str()) {
+                        argv.extend(payload.split_whitespace().map(str::to_string));
+                    }
+                    let res = if SET_COMMANDS.contains(&name.as_str()) {
+                        registry.parse(&argv).and_then(|(command, args)| execute(command, client, registry, &args))
+                    } else {
+                        Err(invalid(&format!("unknown command '{}'", name)))
+                    };
+                    if let Err(e) = res {
+                        publish(&topic("error"), json!({ "command": name, "error": e }).to_string(), false);
+                    }
+                }
+                Ok(Input::BrokerError(err)) => {
+                    if !connected {
+                        return Err(GpmdpError::Connection(format!("MQTT broker {}: {}", broker, err)));
+                    }
+                }
+                Ok(Input::Closed) | Err(_) => break,
+            }
+        }
+
+        publish(&topic("availability"), "offline".to_string(), true);
+        let _ = mqtt.try_disconnect();
+        /* the poller stops once the disconnect is sent or, with the broker away, on its next try */
+        drop(inputs);
+        let _ = poller.join();
+        return Ok(());
+    }
+}
+
+impl Command for Mqtt
+{
+    fn name(&self) -> &'static str { "mqtt" }
+    fn synopsis(&self) -> &'static str
+    {
+        return "[ --broker <host>:<port> ] [ --prefix <topic> ] [ --discovery <prefix> ] \
+                [ --user <name> ]";
+    }
+    fn long_running(&self) -> bool { true }
+
+    /// {"broker": address, "prefix": topic, "discovery": Home Assistant's
+    /// discovery prefix or null, "user": name or null}.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        let options = parse_options(self.name(), args,
+                                    &["--broker", "--prefix", "--discovery", "--user"])?;
+        let broker = options.get("--broker").copied().unwrap_or(DEFAULT_BROKER);
+        broker_addr(broker)?;
+        let prefix = options.get("--prefix").copied().unwrap_or(DEFAULT_PREFIX).trim_end_matches('/');
+        if prefix.is_empty() || prefix.contains(['+', '#']) {
+            return Err(invalid(&format!("invalid topic prefix '{}'", prefix)));
+        }
+        return Ok(json!({
+            "broker": broker,
+            "prefix": prefix,
+            "discovery": options.get("--discovery"),
+            "user": options.get("--user"),
+        }));
+    }
+
+    fn channels(&self, _args: &serde_json::Value) -> u64
+    {
+        return CHANNELS;
+    }
+
+    /// Serves the broker until the connection closes, then fails with why
+    /// it did.
+    fn run(&self,
+           client: &GpmdpClient,
+           registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        self.serve(client, registry, args)?;
+        return match client.close_reason() {
+            Some(e) => Err(e),
+            None => Ok(serde_json::Value::Null),
+        };
+    }
+}
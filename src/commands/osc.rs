@@ -0,0 +1,420 @@
+//! `osc`: an Open Sound Control server in front of GPMDP.
+//!
+//! OSC messages sent over UDP to `<prefix>/<control>` are run on GPMDP, so
+//! TouchOSC layouts and lighting desks can drive the player. Changes to the
+//! track, time, play state and volume are sent back to the targets given,
+//! addressed the same way:
+//!
+//! * `<prefix>/track`: title, artist and album strings
+//! * `<prefix>/time`: elapsed and total seconds
+//! * `<prefix>/position`: the elapsed time as a float from 0 to 1
+//! * `<prefix>/playing`: 1 or 0
+//! * `<prefix>/volume`: a float from 0 to 1
+//! * `<prefix>/error`: why a message sent to the server failed, also sent
+//!   back to its sender
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::Duration;
+use serde_json::json;
+use crate::client::GpmdpClient;
+use crate::commands::builtin::{invalid, parse_options};
+use crate::commands::{Command, Registry};
+use crate::error::*;
+use crate::events::*;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8000";
+const DEFAULT_PREFIX: &str = "/gpmdp";
+
+/// The largest packet taken.
+const MAX_PACKET: usize = 8192;
+
+/// How long the feedback thread takes to notice the server has stopped.
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Arg
+{
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Bool(bool),
+}
+
+/*
+ * A string, NUL terminated and padded to 4 bytes.
+ */
+fn put_string(packet: &mut Vec<u8>, s: &str)
+{
+    packet.extend(s.as_bytes());
+    packet.push(0);
+    while !packet.len().is_multiple_of(4) {
+        packet.push(0);
+    }
+}
+
+fn encode(address: &str, args: &[Arg]) -> Vec<u8>
+{
+    let mut packet = Vec::new();
+    put_string(&mut packet, address);
+    let tags: String = args.iter()
+        .map(|arg| {
+            return match arg {
+                Arg::Int(_)       => 'i',
+                Arg::Float(_)     => 'f',
+                Arg::Str(_)       => 's',
+                Arg::Bool(true)   => 'T',
+                Arg::Bool(false)  => 'F',
+            };
+        })
+        .collect();
+    put_string(&mut packet, &format!(",{}", tags));
+    for arg in args {
+        match arg {
+            Arg::Int(i) => packet.extend(i.to_be_bytes()),
+            Arg::Float(f) => packet.extend(f.to_be_bytes()),
+            Arg::Str(s) => put_string(&mut packet, s),
+            Arg::Bool(_) => {}
+        }
+    }
+    return packet;
+}
+
+/*
+ * Reads the parts of a packet, failing on one cut short.
+ */
+struct Reader<'a>
+{
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a>
+{
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]>
+    {
+        if self.data.len() - self.pos < len {
+            return Err(invalid("OSC packet cut short"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        return Ok(bytes);
+    }
+
+    fn string(&mut self) -> Result<String>
+    {
+        let rest = &self.data[self.pos..];
+        let len = rest.iter().position(|b| *b == 0).ok_or_else(|| invalid("OSC string not terminated"))?;
+        let s = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.bytes((len + 4) / 4 * 4)?;
+        return Ok(s);
+    }
+
+    fn word(&mut self) -> Result<[u8; 4]>
+    {
+        let bytes = self.bytes(4)?;
+        return Ok([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+}
+
+/*
+ * The messages in a packet, those of a bundle's elements in order. Time
+ * tags are ignored, bundles are run when they arrive.
+ */
+fn decode(data: &[u8]) -> Result<Vec<(String, Vec<Arg>)>>
+{
+    let mut reader = Reader { data, pos: 0 };
+    let address = reader.string()?;
+
+    if address == "#bundle" {
+        reader.bytes(8)?;
+        let mut messages = Vec::new();
+        while reader.pos < data.len() {
+            let len = u32::from_be_bytes(reader.word()?) as usize;
+            messages.extend(decode(reader.bytes(len)?)?);
+        }
+        return Ok(messages);
+    }
+
+    let mut args = Vec::new();
+    if reader.pos < data.len() {
+        let tags = reader.string()?;
+        for tag in tags.chars().skip_while(|c| *c == ',') {
+            let arg = match tag {
+                'i' => Arg::Int(i32::from_be_bytes(reader.word()?)),
+                'f' => Arg::Float(f32::from_be_bytes(reader.word()?)),
+                's' => Arg::Str(reader.string()?),
+                'T' => Arg::Bool(true),
+                'F' => Arg::Bool(false),
+                tag => return Err(invalid(&format!("unsupported OSC type '{}'", tag))),
+            };
+            args.push(arg);
+        }
+    }
+    return Ok(vec![(address, args)]);
+}
+
+/*
+ * The single number argument of a message, floats and ints alike.
+ */
+fn number(args: &[Arg]) -> Option<f64>
+{
+    return match args {
+        [Arg::Int(i)] => Some(*i as f64),
+        [Arg::Float(f)] => Some(*f as f64),
+        [Arg::Bool(b)] => Some(if *b { 1.0 } else { 0.0 }),
+        _ => None,
+    };
+}
+
+/*
+ * Run the control 'name' with 'args'. A button sends 1 when pressed and 0
+ * when let go, a 0 to one of the buttons is ignored. Pause only pauses, so
+ * it's left alone when nothing is playing.
+ */
+fn control(client: &GpmdpClient, name: &str, args: &[Arg]) -> Result<()>
+{
+    let missing = || invalid(&format!("'{}' takes a number", name));
+    let released = number(args) == Some(0.0);
+
+    return match name {
+        "play" | "pause" if released => Ok(()),
+        "play" => client.play_pause(),
+        "pause" if !client.state().cur_playing => Ok(()),
+        "pause" => client.play_pause(),
+        "next" if released => Ok(()),
+        "next" => client.forward(),
+        "prev" if released => Ok(()),
+        "prev" => client.rewind(),
+        "volume" => {
+            /* a float is a fader, from 0 to 1 */
+            let level = match args {
+                [Arg::Float(f)] => *f as f64 * 100.0,
+                _ => number(args).ok_or_else(missing)?,
+            };
+            client.set_volume(level.round().clamp(0.0, 100.0) as u32)
+        }
+        "seek" => {
+            /* a float is a fader, the position in the track; an int moves by seconds */
+            match args {
+                [Arg::Float(f)] => {
+                    let total = client.state().cur_track_total as f64;
+                    client.set_current_time(((*f as f64).clamp(0.0, 1.0) * total) as u64)
+                }
+                [Arg::Int(secs)] => client.seek(*secs as i64 * 1000),
+                _ => Err(missing()),
+            }
+        }
+        "playlist" => {
+            match number(args) {
+                Some(n) if n >= 1.0 => client.play_playlist(n as usize),
+                _ => Err(invalid("'playlist' takes a playlist number")),
+            }
+        }
+        "shuffle" => {
+            let on = number(args).ok_or_else(missing)? != 0.0;
+            client.set_shuffle(if on { "ALL_SHUFFLE" } else { "NO_SHUFFLE" })
+        }
+        _ => Err(invalid(&format!("unknown control '{}'", name))),
+    };
+}
+
+/*
+ * The messages telling the targets about 'event'.
+ */
+fn feedback(event: &ChannelEvent, state: &PlayerState) -> Vec<(&'static str, Vec<Arg>)>
+{
+    return match event {
+        ChannelEvent::Track(_) => {
+            let track = &state.cur_track;
+            vec![("track", vec![Arg::Str(track.title().to_string()),
+                                Arg::Str(track.artist().to_string()),
+                                Arg::Str(track.album().to_string())])]
+        }
+        ChannelEvent::Time { current, total } => {
+            let position = if *total > 0 { *current as f32 / *total as f32 } else { 0.0 };
+            vec![("time", vec![Arg::Int((current / 1000) as i32), Arg::Int((total / 1000) as i32)]),
+                 ("position", vec![Arg::Float(position)])]
+        }
+        ChannelEvent::PlayState(playing) => vec![("playing", vec![Arg::Int(*playing as i32)])],
+        ChannelEvent::Volume(volume) => vec![("volume", vec![Arg::Float(*volume as f32 / 100.0)])],
+        _ => Vec::new(),
+    };
+}
+
+fn resolve(addr: &str) -> Result<SocketAddr>
+{
+    return addr.to_socket_addrs().ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| invalid(&format!("invalid address '{}'", addr)));
+}
+
+pub(crate) struct Osc;
+
+impl Osc
+{
+    /*
+     * Run the messages taken on 'socket' until it fails or the connection
+     * closes, sending errors with 'send'.
+     */
+    fn receive(&self,
+               client: &GpmdpClient,
+               socket: &UdpSocket,
+               targets: &[SocketAddr],
+               prefix: &str,
+               send: &dyn Fn(&str, &[Arg])) -> Result<()>
+    {
+        let mut buf = [0; MAX_PACKET];
+        loop {
+            let res = socket.recv_from(&mut buf);
+            if let Some(e) = client.close_reason() {
+                return Err(e);
+            }
+            let (len, from) = match res {
+                Ok(res) => res,
+                Err(err) => return Err(GpmdpError::Connection(format!("OSC socket: {}", err))),
+            };
+            /* the sender hears about its mistakes even when it isn't a target */
+            let error = |e: GpmdpError| {
+                let args = [Arg::Str(e.to_string())];
+                send("error", &args);
+                if !targets.contains(&from) {
+                    let _ = socket.send_to(&encode(&format!("{}/error", prefix), &args), from);
+                }
+            };
+            let messages = match decode(&buf[..len]) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    error(e);
+                    continue;
+                }
+            };
+            for (address, args) in messages {
+                let name = address.strip_prefix(prefix).and_then(|name| name.strip_prefix('/'));
+                let res = match name {
+                    Some(name) => control(client, name, &args),
+                    None => Err(invalid(&format!("unknown address '{}'", address))),
+                };
+                if let Err(e) = res {
+                    error(e);
+                }
+            }
+        }
+    }
+
+    /*
+     * Take messages on 'socket' and send the changes to 'targets' until
+     * the connection closes.
+     */
+    fn serve(&self, client: &GpmdpClient, socket: &UdpSocket, targets: &[SocketAddr], prefix: &str) -> Result<()>
+    {
+        let send = |name: &str, args: &[Arg]| {
+            let packet = encode(&format!("{}/{}", prefix, name), args);
+            for target in targets {
+                let _ = socket.send_to(&packet, target);
+            }
+        };
+
+        /* the socket's own address, to wake up recv_from() when the connection closes */
+        let mut wake = socket.local_addr().map_err(|err| GpmdpError::Connection(err.to_string()))?;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+
+        let events = client.subscribe();
+        if let Some(e) = client.close_reason() {
+            return Err(e);
+        }
+
+        let send = &send;
+        let done = AtomicBool::new(false);
+        return thread::scope(|scope| {
+            let done = &done;
+            scope.spawn(move || {
+                let state = client.state();
+                for event in state.events(CHNL_TRACK | CHNL_TIME | CHNL_PLAYSTATE | CHNL_VOLUME) {
+                    for (name, args) in feedback(&event, &state) {
+                        send(name, &args);
+                    }
+                }
+                /* until the connection closes or the socket fails */
+                while !done.load(Ordering::Relaxed) {
+                    match events.recv_timeout(STOP_POLL) {
+                        Ok(event) => {
+                            for (name, args) in feedback(&event, &client.state()) {
+                                send(name, &args);
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                let _ = socket.send_to(&[], wake);
+            });
+
+            let res = self.receive(client, socket, targets, prefix, send);
+            done.store(true, Ordering::Relaxed);
+            return res;
+        });
+    }
+}
+
+impl Command for Osc
+{
+    fn name(&self) -> &'static str { "osc" }
+    fn synopsis(&self) -> &'static str
+    {
+        return "[ --listen <addr>:<port> ] [ --target <addr>:<port>,... ] [ --prefix <address> ]";
+    }
+    fn long_running(&self) -> bool { true }
+
+    /// {"listen": address, "targets": [address, ..], "prefix": OSC address}.
+    fn parse(&self, args: &[String]) -> Result<serde_json::Value>
+    {
+        let options = parse_options(self.name(), args, &["--listen", "--target", "--prefix"])?;
+        let targets: Vec<&str> = options.get("--target")
+            .map_or(Vec::new(), |targets| targets.split(',').map(str::trim).collect());
+        let prefix = options.get("--prefix").copied().unwrap_or(DEFAULT_PREFIX);
+        if !prefix.starts_with('/') {
+            return Err(invalid(&format!("invalid OSC prefix '{}', it must start with '/'", prefix)));
+        }
+        /* "/" is the root, the controls are /play, /next, ... */
+        let prefix = prefix.trim_end_matches('/');
+        return Ok(json!({
+            "listen": options.get("--listen").copied().unwrap_or(DEFAULT_LISTEN),
+            "targets": targets,
+            "prefix": prefix,
+        }));
+    }
+
+    fn channels(&self, _args: &serde_json::Value) -> u64
+    {
+        return CHNL_PLAYSTATE | CHNL_TRACK | CHNL_TIME | CHNL_VOLUME;
+    }
+
+    /// Serves OSC until the connection closes, then fails with why it did.
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        let listen = args["listen"].as_str().unwrap_or(DEFAULT_LISTEN);
+        let targets = args["targets"].as_array().map_or(Ok(Vec::new()), |targets| {
+            return targets.iter().map(|target| resolve(target.as_str().unwrap_or(""))).collect();
+        })?;
+        let socket = UdpSocket::bind(resolve(listen)?).map_err(|err| {
+            return GpmdpError::Connection(format!("failed to listen on {}: {}", listen, err));
+        })?;
+
+        self.serve(client, &socket, &targets, args["prefix"].as_str().unwrap_or(DEFAULT_PREFIX))?;
+        return match client.close_reason() {
+            Some(e) => Err(e),
+            None => Ok(serde_json::Value::Null),
+        };
+    }
+}
@@ -0,0 +1,166 @@
+//! `gpmdp_rc daemon`: one connection to GPMDP, shared over a Unix socket.
+//!
+//! The daemon authenticates once and keeps the player state current from
+//! GPMDP's channel pushes, then runs the commands it's sent on that
+//! connection. A run of the command line tool that finds the daemon's
+//! socket skips the websocket handshake and the wait for channels.
+//!
+//! The protocol is NDJSON. A request is a line with the command's argv as a
+//! JSON array. The daemon answers with a line per result,
+//! `{"output": <result>}`, and ends with `{"done": true}` or
+//! `{"error": {"kind": .., "message": ..}}` before closing the connection.
+//!
+//! Given a history log, the daemon also records the tracks listened to.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+use std::time::Duration;
+use crate::client::GpmdpClient;
+use crate::commands::*;
+use crate::error::*;
+use crate::history;
+
+/// How long a client has to send its request. The daemon doesn't exit
+/// before its clients are answered, one that never asks mustn't keep it up.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether the daemon runs 'command'. Commands that run until the
+/// connection closes are run without it, as is pairing, which the daemon's
+/// connection is past.
+pub fn runs(command: &dyn Command) -> bool
+{
+    return !command.long_running() && command.needs_auth();
+}
+
+/// Where the daemon listens: `$XDG_RUNTIME_DIR/gpmdp_rc.sock`.
+pub fn socket_path() -> Option<String>
+{
+    return std::env::var("XDG_RUNTIME_DIR").ok()
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| format!("{}/gpmdp_rc.sock", dir));
+}
+
+fn socket_error(path: &str, err: std::io::Error) -> GpmdpError
+{
+    return GpmdpError::Connection(format!("daemon socket {}: {}", path, err));
+}
+
+/*
+ * Write one response line. A write error means the other end has gone,
+ * there's no one left to tell.
+ */
+fn respond(mut stream: &UnixStream, response: serde_json::Value)
+{
+    let _ = writeln!(stream, "{}", response);
+}
+
+/*
+ * Read a request from 'stream', run it and write back its results.
+ */
+fn handle(client: &GpmdpClient, registry: &Registry, stream: UnixStream)
+{
+    let mut line = String::new();
+    let res = stream.set_read_timeout(Some(REQUEST_TIMEOUT))
+        .and_then(|_| BufReader::new(&stream).read_line(&mut line))
+        .map_err(|err| GpmdpError::Connection(err.to_string()))
+        .and_then(|_| Ok(serde_json::from_str::<Vec<String>>(&line)?))
+        .and_then(|argv| {
+            let (command, args) = registry.parse(&argv)?;
+            if !runs(command) {
+                return Err(GpmdpError::InvalidArgument(
+                    format!("'{}' can't be run by the daemon", command.name())));
+            }
+            return Ok((command, args));
+        })
+        .and_then(|(command, args)| {
+            return execute_stream(command, client, registry, &args, &mut |output| {
+                respond(&stream, serde_json::json!({ "output": output }));
+            });
+        });
+
+    match res {
+        Ok(()) => respond(&stream, serde_json::json!({ "done": true })),
+        Err(e) => respond(&stream, serde_json::json!({ "error": e })),
+    }
+}
+
+/// Listen on 'path' and run the commands sent there on 'client' until
+/// GPMDP closes the connection, recording listens to 'history' if given.
+/// Fails if another daemon is listening on 'path' already, a stale socket
+/// is replaced.
+pub fn serve(client: &GpmdpClient,
+             registry: &Registry,
+             path: &str,
+             history: Option<history::Log>) -> Result<()>
+{
+    if UnixStream::connect(path).is_ok() {
+        return Err(GpmdpError::Config(format!("a daemon is already listening on {}", path)));
+    }
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path).map_err(|err| socket_error(path, err))?;
+
+    /* wake up accept() when the connection closes */
+    let closed = client.subscribe();
+    let waker = path.to_string();
+    thread::spawn(move || {
+        for _ in closed {}
+        let _ = UnixStream::connect(waker);
+    });
+    if let Some(e) = client.close_reason() {
+        let _ = std::fs::remove_file(path);
+        return Err(e);
+    }
+
+    let res = thread::scope(|scope| {
+        if let Some(mut log) = history {
+            scope.spawn(move || history::record(client, &mut log));
+        }
+        for stream in listener.incoming() {
+            if let Some(e) = client.close_reason() {
+                return Err(e);
+            }
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(move || handle(client, registry, stream));
+                }
+                Err(err) => return Err(socket_error(path, err)),
+            }
+        }
+        return Ok(());
+    });
+
+    let _ = std::fs::remove_file(path);
+    return res;
+}
+
+/// Connect to the daemon on 'path', None if there's none.
+pub fn connect(path: &str) -> Option<UnixStream>
+{
+    return UnixStream::connect(path).ok();
+}
+
+/// Send 'argv' to the daemon and pass each of its results to 'emit'.
+pub fn request(stream: UnixStream,
+               argv: &[String],
+               emit: &mut dyn FnMut(serde_json::Value)) -> Result<()>
+{
+    let lost = |err: std::io::Error| GpmdpError::Connection(format!("daemon: {}", err));
+
+    let mut writer = &stream;
+    writeln!(writer, "{}", serde_json::to_string(argv)?).map_err(lost)?;
+
+    for line in BufReader::new(&stream).lines() {
+        let mut response: serde_json::Value = serde_json::from_str(&line.map_err(lost)?)?;
+        if let Some(output) = response.get_mut("output") {
+            emit(output.take());
+        } else if let Some(error) = response.get_mut("error") {
+            return Err(serde_json::from_value(error.take())?);
+        } else if response.get("done").is_some() {
+            return Ok(());
+        } else {
+            return Err(GpmdpError::Decode(format!("unexpected daemon response {}", response)));
+        }
+    }
+    return Err(GpmdpError::Connection("daemon closed the connection".to_string()));
+}
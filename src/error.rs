@@ -0,0 +1,97 @@
+//! Errors returned by the client and their process exit codes.
+
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Serialized as {"kind": .., "message": ..}, the kind being `kind()`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum GpmdpError
+{
+    /// The config file is missing, unreadable or incomplete.
+    Config(String),
+    /// The websocket could not be opened or was lost.
+    Connection(String),
+    /// GPMDP rejected the auth token or pairing code.
+    Auth(String),
+    /// GPMDP didn't answer in time; says what we were waiting for.
+    Timeout(String),
+    /// A message from GPMDP didn't have the expected shape.
+    Decode(String),
+    /// A command was given bad arguments.
+    InvalidArgument(String),
+    /// GPMDP answered a request with an error.
+    Server(String),
+}
+
+pub type Result<T> = std::result::Result<T, GpmdpError>;
+
+impl GpmdpError
+{
+    /// Exit code the command line tool reports for this error.
+    pub fn exit_code(&self) -> i32
+    {
+        return match self {
+            GpmdpError::InvalidArgument(_) => 2,
+            GpmdpError::Config(_)          => 3,
+            GpmdpError::Connection(_)      => 4,
+            GpmdpError::Auth(_)            => 5,
+            GpmdpError::Timeout(_)         => 6,
+            GpmdpError::Decode(_)          => 7,
+            GpmdpError::Server(_)          => 8,
+        };
+    }
+
+    /// Short machine readable name of the error kind.
+    pub fn kind(&self) -> &'static str
+    {
+        return match self {
+            GpmdpError::Config(_)          => "config",
+            GpmdpError::Connection(_)      => "connection",
+            GpmdpError::Auth(_)            => "auth",
+            GpmdpError::Timeout(_)         => "timeout",
+            GpmdpError::Decode(_)          => "decode",
+            GpmdpError::InvalidArgument(_) => "invalid_argument",
+            GpmdpError::Server(_)          => "server",
+        };
+    }
+
+    /// The error as printed by `--json`:
+    /// {"error": {"kind": .., "message": .., "exit_code": ..}}.
+    pub fn to_json(&self) -> serde_json::Value
+    {
+        return serde_json::json!({
+            "error": {
+                "kind": self.kind(),
+                "message": self.to_string(),
+                "exit_code": self.exit_code(),
+            }
+        });
+    }
+}
+
+impl fmt::Display for GpmdpError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        return match self {
+            GpmdpError::Config(msg)          => write!(f, "config: {}", msg),
+            GpmdpError::Connection(msg)      => write!(f, "connection: {}", msg),
+            GpmdpError::Auth(msg)            => write!(f, "authentication: {}", msg),
+            GpmdpError::Timeout(msg)         => write!(f, "timeout waiting for {}", msg),
+            GpmdpError::Decode(msg)          => write!(f, "protocol: {}", msg),
+            GpmdpError::InvalidArgument(msg) => write!(f, "{}", msg),
+            GpmdpError::Server(msg)          => write!(f, "GPMDP: {}", msg),
+        };
+    }
+}
+
+impl std::error::Error for GpmdpError {}
+
+impl From<serde_json::Error> for GpmdpError
+{
+    fn from(err: serde_json::Error) -> GpmdpError
+    {
+        return GpmdpError::Decode(err.to_string());
+    }
+}
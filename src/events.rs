@@ -0,0 +1,350 @@
+//! Decoding of the channel messages GPMDP pushes and the player state
+//! built from them.
+
+use serde::Deserialize;
+use crate::error::*;
+use crate::models::*;
+
+pub const CHNL_API_VERSION: u64         = 0x0001;
+pub const CHNL_PLAYSTATE: u64           = 0x0002;
+pub const CHNL_TRACK: u64               = 0x0004;
+pub const CHNL_LYRICS: u64              = 0x0008;
+pub const CHNL_TIME: u64                = 0x0010;
+pub const CHNL_RATING: u64              = 0x0020;
+pub const CHNL_SHUFFLE: u64             = 0x0040;
+pub const CHNL_REPEAT: u64              = 0x0080;
+pub const CHNL_PLAYLISTS: u64           = 0x0100;
+pub const CHNL_QUEUE: u64               = 0x0200;
+pub const CHNL_SEARCH_RESULTS: u64      = 0x0400;
+pub const CHNL_LIBRARY: u64             = 0x0800;
+pub const CHNL_VOLUME: u64              = 0x1000;
+pub const CHNL_SETTINGS_THEMECOLOR: u64 = 0x2000;
+pub const CHNL_SETTINGS_THEME: u64      = 0x4000;
+pub const CHNL_SETTINGS_THEMETYPE: u64  = 0x8000;
+
+pub const CHNLS_ALL: u64 = 0x17FE; //0xFFFF;
+
+/// Channel names in CHNL_* bit order.
+const CHNL_NAMES: [&str; 16] = [
+    "API_VERSION",
+    "playState",
+    "track",
+    "lyrics",
+    "time",
+    "rating",
+    "shuffle",
+    "repeat",
+    "playlists",
+    "queue",
+    "search-results",
+    "library",
+    "volume",
+    "settings:themeColor",
+    "settings:theme",
+    "settings:themeType",
+];
+
+/// CHNL_* bit for a channel name, 0 for channels that aren't tracked.
+pub fn channel_bit(channel: &str) -> u64
+{
+    return CHNL_NAMES.iter()
+        .position(|name| *name == channel)
+        .map_or(0, |i| 1 << i);
+}
+
+/// Channel name for a single CHNL_* bit.
+pub fn channel_name(chnl: u64) -> Option<&'static str>
+{
+    if !chnl.is_power_of_two() {
+        return None;
+    }
+    return CHNL_NAMES.get(chnl.trailing_zeros() as usize).copied();
+}
+
+/// Comma separated channel names for a set of CHNL_* bits.
+pub fn channel_list(chnls: u64) -> String
+{
+    return (0..CHNL_NAMES.len())
+        .filter(|i| chnls & (1 << i) != 0)
+        .map(|i| CHNL_NAMES[i])
+        .collect::<Vec<&str>>()
+        .join(", ");
+}
+
+/// Value of `playback.getPlaybackState()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackState
+{
+    Stopped,
+    Paused,
+    Playing,
+    Unknown,
+}
+
+impl PlaybackState
+{
+    pub fn from_u64(value: u64) -> PlaybackState
+    {
+        return match value {
+            0 => PlaybackState::Stopped,
+            1 => PlaybackState::Paused,
+            2 => PlaybackState::Playing,
+            _ => PlaybackState::Unknown,
+        };
+    }
+
+    pub fn as_str(&self) -> &'static str
+    {
+        return match self {
+            PlaybackState::Stopped => "stopped",
+            PlaybackState::Paused  => "paused",
+            PlaybackState::Playing => "playing",
+            PlaybackState::Unknown => "unknown",
+        };
+    }
+}
+
+#[derive(Deserialize)]
+struct TimePayload
+{
+    current: u64,
+    total: u64,
+}
+
+#[derive(Deserialize)]
+struct RatingPayload
+{
+    liked: bool,
+    disliked: bool,
+}
+
+/// A decoded channel push: `{ "channel": <name>, "payload": <payload> }`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelEvent
+{
+    /// Auth token, or CODE_REQUIRED when the token was rejected.
+    Connect(String),
+    ApiVersion(String),
+    /// Whether a track is playing.
+    PlayState(bool),
+    Track(Track),
+    Lyrics(Option<String>),
+    /// Progress and length of the current track in ms.
+    Time { current: u64, total: u64 },
+    Rating { liked: bool, disliked: bool },
+    /// ALL_SHUFFLE or NO_SHUFFLE.
+    Shuffle(String),
+    /// LIST_REPEAT, SINGLE_REPEAT or NO_REPEAT.
+    Repeat(String),
+    Playlists(Vec<Playlist>),
+    Queue(Vec<Track>),
+    SearchResults(SearchResults),
+    Library(serde_json::Value),
+    Volume(u64),
+    ThemeColor(String),
+    Theme(bool),
+    ThemeType(String),
+    /// A channel this client doesn't know about.
+    Other(String, serde_json::Value),
+}
+
+impl ChannelEvent
+{
+    /// Decode a channel push from its channel name and payload.
+    pub fn decode(channel: &str,
+                  payload: &serde_json::Value) -> Result<ChannelEvent>
+    {
+        fn de<'a, T: Deserialize<'a>>(payload: &'a serde_json::Value)
+            -> std::result::Result<T, serde_json::Error>
+        {
+            return T::deserialize(payload);
+        }
+
+        let event = match channel {
+            "connect" => de(payload).map(ChannelEvent::Connect),
+            "API_VERSION" => de(payload).map(ChannelEvent::ApiVersion),
+            "playState" => de(payload).map(ChannelEvent::PlayState),
+            "track" => de(payload).map(ChannelEvent::Track),
+            "lyrics" => de(payload).map(ChannelEvent::Lyrics),
+            "time" => de::<TimePayload>(payload).map(|time| {
+                ChannelEvent::Time { current: time.current, total: time.total }
+            }),
+            "rating" => de::<RatingPayload>(payload).map(|rating| {
+                ChannelEvent::Rating { liked: rating.liked, disliked: rating.disliked }
+            }),
+            "shuffle" => de(payload).map(ChannelEvent::Shuffle),
+            "repeat" => de(payload).map(ChannelEvent::Repeat),
+            "playlists" => de(payload).map(ChannelEvent::Playlists),
+            "queue" => de(payload).map(ChannelEvent::Queue),
+            "search-results" => de(payload).map(ChannelEvent::SearchResults),
+            "library" => Ok(ChannelEvent::Library(payload.clone())),
+            "volume" => de(payload).map(ChannelEvent::Volume),
+            "settings:themeColor" => de(payload).map(ChannelEvent::ThemeColor),
+            "settings:theme" => de(payload).map(ChannelEvent::Theme),
+            "settings:themeType" => de(payload).map(ChannelEvent::ThemeType),
+            _ => Ok(ChannelEvent::Other(channel.to_string(), payload.clone())),
+        };
+
+        return event.map_err(|e| {
+            GpmdpError::Decode(format!("invalid '{}' payload: {}", channel, e))
+        });
+    }
+
+    /// Decode a raw message, None if it isn't a channel push.
+    pub fn from_message(js: &serde_json::Value) -> Option<Result<ChannelEvent>>
+    {
+        let channel = js["channel"].as_str()?;
+        return Some(ChannelEvent::decode(channel, &js["payload"]));
+    }
+
+    /// Name of the channel this event came in on.
+    pub fn channel(&self) -> &str
+    {
+        return match self {
+            ChannelEvent::Connect(_) => "connect",
+            ChannelEvent::Other(channel, _) => channel,
+            _ => channel_name(self.channel_bit()).unwrap_or(""),
+        };
+    }
+
+    /// CHNL_* bit of the channel this event came in on.
+    pub fn channel_bit(&self) -> u64
+    {
+        return match self {
+            ChannelEvent::Connect(_)       => 0,
+            ChannelEvent::ApiVersion(_)    => CHNL_API_VERSION,
+            ChannelEvent::PlayState(_)     => CHNL_PLAYSTATE,
+            ChannelEvent::Track(_)         => CHNL_TRACK,
+            ChannelEvent::Lyrics(_)        => CHNL_LYRICS,
+            ChannelEvent::Time { .. }      => CHNL_TIME,
+            ChannelEvent::Rating { .. }    => CHNL_RATING,
+            ChannelEvent::Shuffle(_)       => CHNL_SHUFFLE,
+            ChannelEvent::Repeat(_)        => CHNL_REPEAT,
+            ChannelEvent::Playlists(_)     => CHNL_PLAYLISTS,
+            ChannelEvent::Queue(_)         => CHNL_QUEUE,
+            ChannelEvent::SearchResults(_) => CHNL_SEARCH_RESULTS,
+            ChannelEvent::Library(_)       => CHNL_LIBRARY,
+            ChannelEvent::Volume(_)        => CHNL_VOLUME,
+            ChannelEvent::ThemeColor(_)    => CHNL_SETTINGS_THEMECOLOR,
+            ChannelEvent::Theme(_)         => CHNL_SETTINGS_THEME,
+            ChannelEvent::ThemeType(_)     => CHNL_SETTINGS_THEMETYPE,
+            ChannelEvent::Other(..)        => 0,
+        };
+    }
+}
+
+/// Player state as last pushed by GPMDP on its channels.
+#[derive(Clone, Debug, Default)]
+pub struct PlayerState
+{
+    pub cur_playing: bool,
+    pub cur_volume: u64,
+    pub cur_shuffle: String,
+    pub cur_repeat: String,
+    pub cur_track: Track,
+    pub cur_track_lyrics: String,
+    pub cur_track_progress: u64,
+    pub cur_track_total: u64,
+    pub cur_track_liked: bool,
+    pub cur_track_disliked: bool,
+    pub cur_queue: Vec<Track>,
+    pub cur_playlists: Vec<Playlist>,
+    pub cur_search: SearchResults,
+    pub api_version: String,
+    /// CHNL_* bits of every channel received so far.
+    pub chnls_rcvd: u64,
+}
+
+impl PlayerState
+{
+    /// Fold a channel event into the state.
+    pub fn apply(&mut self, event: &ChannelEvent)
+    {
+        match event {
+            ChannelEvent::ApiVersion(version) => {
+                self.api_version = version.clone();
+            }
+            ChannelEvent::PlayState(playing) => {
+                self.cur_playing = *playing;
+            }
+            ChannelEvent::Track(track) => {
+                self.cur_track = track.clone();
+            }
+            ChannelEvent::Lyrics(lyrics) => {
+                self.cur_track_lyrics = lyrics.clone().unwrap_or_default();
+            }
+            ChannelEvent::Time { current, total } => {
+                self.cur_track_progress = *current;
+                self.cur_track_total = *total;
+            }
+            ChannelEvent::Rating { liked, disliked } => {
+                self.cur_track_liked = *liked;
+                self.cur_track_disliked = *disliked;
+            }
+            ChannelEvent::Shuffle(shuffle) => {
+                self.cur_shuffle = shuffle.clone();
+            }
+            ChannelEvent::Repeat(repeat) => {
+                self.cur_repeat = repeat.clone();
+            }
+            ChannelEvent::Playlists(playlists) => {
+                self.cur_playlists = playlists.clone();
+            }
+            ChannelEvent::Queue(queue) => {
+                self.cur_queue = queue.clone();
+            }
+            ChannelEvent::SearchResults(search) => {
+                self.cur_search = search.clone();
+            }
+            ChannelEvent::Volume(volume) => {
+                self.cur_volume = *volume;
+            }
+            _ => {}
+        }
+
+        self.chnls_rcvd |= event.channel_bit();
+    }
+
+    /// Events that would rebuild the state of the channels in 'chnls' that
+    /// have been received, in CHNL_* bit order.
+    pub fn events(&self, chnls: u64) -> Vec<ChannelEvent>
+    {
+        let chnls = chnls & self.chnls_rcvd;
+        let mut events = Vec::new();
+        let mut add = |chnl: u64, event: ChannelEvent| {
+            if chnls & chnl != 0 {
+                events.push(event);
+            }
+        };
+
+        add(CHNL_API_VERSION, ChannelEvent::ApiVersion(self.api_version.clone()));
+        add(CHNL_PLAYSTATE, ChannelEvent::PlayState(self.cur_playing));
+        add(CHNL_TRACK, ChannelEvent::Track(self.cur_track.clone()));
+        add(CHNL_LYRICS, ChannelEvent::Lyrics(Some(self.cur_track_lyrics.clone())
+                                              .filter(|lyrics| !lyrics.is_empty())));
+        add(CHNL_TIME, ChannelEvent::Time { current: self.cur_track_progress,
+                                            total: self.cur_track_total });
+        add(CHNL_RATING, ChannelEvent::Rating { liked: self.cur_track_liked,
+                                                disliked: self.cur_track_disliked });
+        add(CHNL_SHUFFLE, ChannelEvent::Shuffle(self.cur_shuffle.clone()));
+        add(CHNL_REPEAT, ChannelEvent::Repeat(self.cur_repeat.clone()));
+        add(CHNL_PLAYLISTS, ChannelEvent::Playlists(self.cur_playlists.clone()));
+        add(CHNL_QUEUE, ChannelEvent::Queue(self.cur_queue.clone()));
+        add(CHNL_SEARCH_RESULTS, ChannelEvent::SearchResults(self.cur_search.clone()));
+        add(CHNL_VOLUME, ChannelEvent::Volume(self.cur_volume));
+        return events;
+    }
+
+    /// 1-based position of the current track in the queue, 0 if not found.
+    pub fn queue_position(&self) -> usize
+    {
+        return self.cur_queue.iter()
+            .position(|track| track.same_song(&self.cur_track))
+            .map_or(0, |i| i + 1);
+    }
+
+    pub fn queue_length(&self) -> usize
+    {
+        return self.cur_queue.len();
+    }
+}
@@ -0,0 +1,210 @@
+//! `--format` templates, in the style of `mpc --format`.
+//!
+//! A template is text with `%field%` placeholders, filled in from the keys
+//! of a command's JSON result (see the README for the keys each command
+//! has):
+//!
+//! ```text
+//! %artist% - %title% [%time_elapsed_fmt%/%time_total_fmt%]
+//! ```
+//!
+//! `[ ... ]` is only printed when every field in it is set, i.e. not
+//! missing, null or an empty string. `|` inside brackets separates
+//! alternatives, the first one with all its fields set is printed:
+//!
+//! ```text
+//! [%album_artist%|%artist%] - %title%[ (%album%)]
+//! ```
+//!
+//! `\` escapes the next character, so `\[`, `\]`, `\|`, `\%` and `\\` are
+//! literals and `\n` and `\t` are a newline and a tab.
+//!
+//! mpc's names work too, e.g. `%elapsed%` and `%total%` for the times and
+//! `%albumartist%` for `%album_artist%`. Fields the command doesn't have
+//! fail when the template is parsed.
+
+use crate::error::*;
+
+/// mpc style names and the fields they stand for, the first one the
+/// command has is used.
+const ALIASES: [(&str, &[&str]); 7] = [
+    ("elapsed",     &["time_elapsed_fmt"]),
+    ("currenttime", &["time_elapsed_fmt"]),
+    ("total",       &["time_total_fmt"]),
+    ("totaltime",   &["time_total_fmt"]),
+    ("albumartist", &["album_artist"]),
+    ("track",       &["index"]),
+    ("position",    &["queue_track", "num"]),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part
+{
+    Text(String),
+    Field(String),
+    /// Alternatives, each a sequence of parts.
+    Group(Vec<Vec<Part>>),
+}
+
+/// A parsed format template.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template
+{
+    parts: Vec<Part>,
+}
+
+fn invalid(template: &str, msg: &str) -> GpmdpError
+{
+    return GpmdpError::InvalidArgument(format!("invalid format '{}': {}", template, msg));
+}
+
+/*
+ * The field 'name' stands for among 'fields', itself or what it's an
+ * alias of.
+ */
+fn resolve(template: &str, name: &str, fields: &[&str]) -> Result<String>
+{
+    if fields.contains(&name) {
+        return Ok(name.to_string());
+    }
+    return ALIASES.iter()
+        .filter(|(alias, _)| *alias == name)
+        .flat_map(|(_, targets)| targets.iter())
+        .find(|target| fields.contains(target))
+        .map(|target| target.to_string())
+        .ok_or_else(|| invalid(template, &format!("unknown field '%{}%'", name)));
+}
+
+/*
+ * Parse parts up to the end of the template or, inside a group, up to the
+ * closing ']'. Returns the alternatives, a single one outside groups.
+ */
+fn parse_parts(template: &str,
+               fields: &[&str],
+               chars: &mut std::str::Chars,
+               in_group: bool) -> Result<Vec<Vec<Part>>>
+{
+    let mut alternatives = Vec::new();
+    let mut parts = Vec::new();
+    let mut text = String::new();
+
+    loop {
+        let c = match chars.next() {
+            Some(c) => c,
+            None if in_group => return Err(invalid(template, "missing ']'")),
+            None => break,
+        };
+        match c {
+            '\\' => {
+                match chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(c) => text.push(c),
+                    None => return Err(invalid(template, "trailing '\\'")),
+                }
+                continue;
+            }
+            '%' | '[' | ']' | '|' => {}
+            c => {
+                text.push(c);
+                continue;
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(Part::Text(std::mem::take(&mut text)));
+        }
+        match c {
+            '%' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('%') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(invalid(template, "missing '%'")),
+                    }
+                }
+                if name.is_empty() {
+                    return Err(invalid(template, "empty field name"));
+                }
+                parts.push(Part::Field(resolve(template, &name, fields)?));
+            }
+            '[' => parts.push(Part::Group(parse_parts(template, fields, chars, true)?)),
+            ']' if in_group => break,
+            ']' => return Err(invalid(template, "unexpected ']'")),
+            '|' if in_group => alternatives.push(std::mem::take(&mut parts)),
+            _ => return Err(invalid(template, "'|' outside of '[ ]'")),
+        }
+    }
+
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    alternatives.push(parts);
+    return Ok(alternatives);
+}
+
+/*
+ * A field's text, None if it isn't set.
+ */
+fn field(record: &serde_json::Value, name: &str) -> Option<String>
+{
+    let value = match record {
+        serde_json::Value::Object(map) => map.get(name)?,
+        /* a bare number or string is %value% */
+        value if name == "value" => value,
+        _ => return None,
+    };
+    return match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) if s.is_empty() => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        value => Some(value.to_string()),
+    };
+}
+
+/*
+ * Render a sequence of parts, None if one of its fields isn't set.
+ */
+fn render_parts(parts: &[Part], record: &serde_json::Value) -> Option<String>
+{
+    let mut out = String::new();
+    for part in parts {
+        match part {
+            Part::Text(text) => out.push_str(text),
+            Part::Field(name) => out.push_str(&field(record, name)?),
+            Part::Group(alternatives) => {
+                if let Some(text) = alternatives.iter().find_map(|alt| render_parts(alt, record)) {
+                    out.push_str(&text);
+                }
+            }
+        }
+    }
+    return Some(out);
+}
+
+impl Template
+{
+    /// Parse a template for records with the keys in 'fields'.
+    pub fn parse(template: &str, fields: &[&str]) -> Result<Template>
+    {
+        let mut chars = template.chars();
+        let mut alternatives = parse_parts(template, fields, &mut chars, false)?;
+        return Ok(Template { parts: alternatives.remove(0) });
+    }
+
+    /// Fill in the template from a record. Fields that aren't set outside
+    /// of brackets are left empty.
+    pub fn render(&self, record: &serde_json::Value) -> String
+    {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Field(name) => out.push_str(&field(record, name).unwrap_or_default()),
+                part => out.push_str(&render_parts(std::slice::from_ref(part), record)
+                                     .unwrap_or_default()),
+            }
+        }
+        return out;
+    }
+}
@@ -0,0 +1,265 @@
+//! Listening history: the tracks actually listened to, kept in a local
+//! NDJSON file and exported for Last.fm and ListenBrainz.
+//!
+//! A track counts as a listen once it has played for half its length or 4
+//! minutes, whichever comes first, and only if it's longer than 30 seconds.
+//! Time skipped by seeking doesn't count. Each listen is one line:
+//!
+//! ```text
+//! {"listened_at":1697625600,"artist":"Mock Artist","title":"Second Song","album":"Mock Album","track_number":2,"duration":200}
+//! ```
+//!
+//! `listened_at` is when the track started playing, in seconds since the
+//! epoch, and `duration` is its length in seconds.
+
+use std::fs;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::client::GpmdpClient;
+use crate::error::*;
+use crate::events::*;
+use crate::models::Track;
+
+/// Tracks this long or shorter are never listens, in ms.
+const MIN_LENGTH: u64 = 30_000;
+
+/// A track has been listened to after playing this long, in ms, however
+/// long it is.
+const MAX_WAIT: u64 = 240_000;
+
+/// A bigger jump in the time is a seek rather than playing, in ms.
+const MAX_STEP: u64 = 5_000;
+
+/// One listen, a line of the history file.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Listen
+{
+    /// When the track started playing, in seconds since the epoch.
+    pub listened_at: u64,
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    pub track_number: Option<u64>,
+    /// Length of the track in seconds.
+    pub duration: u64,
+}
+
+/// Applies the scrobble rules to the track and time channels.
+#[derive(Debug, Default)]
+pub struct Scrobbler
+{
+    track: Option<Track>,
+    /// When the track started, in seconds since the epoch.
+    started: u64,
+    /// How long the track has played, in ms.
+    listened: u64,
+    /// The time in the track last pushed, in ms.
+    position: Option<u64>,
+    /// Length of the track in ms.
+    total: u64,
+    recorded: bool,
+}
+
+impl Scrobbler
+{
+    pub fn new() -> Scrobbler
+    {
+        return Scrobbler::default();
+    }
+
+    /// Fold in a channel event that arrived at 'now', in seconds since the
+    /// epoch. Returns the listen it completes, if any.
+    pub fn update(&mut self, event: &ChannelEvent, now: u64) -> Option<Listen>
+    {
+        match event {
+            ChannelEvent::Track(track) => {
+                /* the same track pushed again is no new play */
+                if self.track.as_ref().is_some_and(|cur| cur.same_song(track)) {
+                    return None;
+                }
+                *self = Scrobbler {
+                    track: Some(track.clone()),
+                    started: now,
+                    total: track.duration.unwrap_or(0),
+                    ..Scrobbler::default()
+                };
+                return None;
+            }
+            ChannelEvent::Time { current, total } => {
+                if *total > 0 {
+                    self.total = *total;
+                }
+                if let Some(position) = self.position {
+                    if *current >= position && current - position <= MAX_STEP {
+                        self.listened += current - position;
+                    } else if self.recorded && *current < MAX_STEP {
+                        /* back to the start after a listen: repeating the track */
+                        self.started = now;
+                        self.listened = 0;
+                        self.recorded = false;
+                    }
+                }
+                self.position = Some(*current);
+                return self.listen();
+            }
+            _ => return None,
+        }
+    }
+
+    /*
+     * The listen, if the track has played long enough and isn't recorded
+     * already.
+     */
+    fn listen(&mut self) -> Option<Listen>
+    {
+        let track = self.track.as_ref()?;
+        if self.recorded || track.title().is_empty() || self.total <= MIN_LENGTH ||
+           self.listened < (self.total / 2).min(MAX_WAIT) {
+            return None;
+        }
+        self.recorded = true;
+        return Some(Listen {
+            listened_at: self.started,
+            artist: track.artist().to_string(),
+            title: track.title().to_string(),
+            album: track.album().to_string(),
+            track_number: track.index,
+            duration: self.total / 1000,
+        });
+    }
+}
+
+/// Where the history is kept when the config file's `history` is `true`:
+/// `$XDG_DATA_HOME/gpmdp_rc/history.ndjson`, or under `~/.local/share`.
+pub fn default_path() -> Option<String>
+{
+    let non_empty = |var: &str| std::env::var(var).ok().filter(|dir| !dir.is_empty());
+    return non_empty("XDG_DATA_HOME")
+        .or_else(|| non_empty("HOME").map(|home| format!("{}/.local/share", home)))
+        .map(|dir| format!("{}/gpmdp_rc/history.ndjson", dir));
+}
+
+/// A history file, open for appending listens.
+pub struct Log
+{
+    out: fs::File,
+}
+
+impl Log
+{
+    /// Open 'file', creating it and its directory if need be.
+    pub fn open(file: &str) -> Result<Log>
+    {
+        let failed = |e: std::io::Error| {
+            GpmdpError::Config(format!("failed to open history {}: {}", file, e))
+        };
+        if let Some(dir) = std::path::Path::new(file).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(failed)?;
+        }
+        let out = fs::OpenOptions::new().create(true).append(true).open(file).map_err(failed)?;
+        return Ok(Log { out });
+    }
+
+    /// Append a listen. Write errors are ignored like a transcript's.
+    pub fn append(&mut self, listen: &Listen)
+    {
+        if let Ok(line) = serde_json::to_string(listen) {
+            let _ = writeln!(self.out, "{}", line);
+            let _ = self.out.flush();
+        }
+    }
+}
+
+/// Record the listens on 'client' to 'log' until the connection closes.
+pub fn record(client: &GpmdpClient, log: &mut Log) -> Result<()>
+{
+    let events = client.subscribe();
+    if let Some(e) = client.close_reason() {
+        return Err(e);
+    }
+
+    let now = || SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let mut scrobbler = Scrobbler::new();
+    for event in client.state().events(CHNL_TRACK | CHNL_TIME).into_iter().chain(events.iter()) {
+        if let Some(listen) = scrobbler.update(&event, now()) {
+            log.append(&listen);
+        }
+    }
+    return Ok(());
+}
+
+/// Parse a history, one listen per line. Blank lines are skipped.
+pub fn parse(s: &str) -> Result<Vec<Listen>>
+{
+    let mut listens = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let listen = serde_json::from_str(line).map_err(|e| {
+            GpmdpError::Decode(format!("history line {}: {}", i + 1, e))
+        })?;
+        listens.push(listen);
+    }
+    return Ok(listens);
+}
+
+/// Read and parse the history in 'file'.
+pub fn load(file: &str) -> Result<Vec<Listen>>
+{
+    let s = fs::read_to_string(file).map_err(|e| {
+        GpmdpError::Config(format!("failed to read history {}: {}", file, e))
+    })?;
+    return parse(&s);
+}
+
+/// The listens as an Audioscrobbler `.scrobbler.log`, as portable players
+/// leave for Last.fm uploaders.
+pub fn scrobbler_log(listens: &[Listen]) -> String
+{
+    /* fields are tab separated */
+    let field = |s: &str| s.replace(['\t', '\n'], " ");
+    let mut log = format!("#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/gpmdp_rc {}\n",
+                          env!("CARGO_PKG_VERSION"));
+    for listen in listens {
+        log += &format!("{}\t{}\t{}\t{}\t{}\tL\t{}\t\n",
+                        field(&listen.artist),
+                        field(&listen.album),
+                        field(&listen.title),
+                        listen.track_number.map_or(String::new(), |n| n.to_string()),
+                        listen.duration,
+                        listen.listened_at);
+    }
+    return log;
+}
+
+/// The listens as a ListenBrainz `import` submission.
+pub fn listenbrainz(listens: &[Listen]) -> serde_json::Value
+{
+    let payload: Vec<serde_json::Value> = listens.iter()
+        .map(|listen| {
+            let mut metadata = serde_json::json!({
+                "artist_name": listen.artist,
+                "track_name": listen.title,
+                "additional_info": {
+                    "duration_ms": listen.duration * 1000,
+                    "media_player": "Google Play Music Desktop Player",
+                    "submission_client": "gpmdp_rc",
+                    "submission_client_version": env!("CARGO_PKG_VERSION"),
+                },
+            });
+            if !listen.album.is_empty() {
+                metadata["release_name"] = serde_json::json!(listen.album);
+            }
+            if let Some(n) = listen.track_number {
+                metadata["additional_info"]["tracknumber"] = serde_json::json!(n);
+            }
+            return serde_json::json!({
+                "listened_at": listen.listened_at,
+                "track_metadata": metadata,
+            });
+        })
+        .collect();
+    return serde_json::json!({ "listen_type": "import", "payload": payload });
+}
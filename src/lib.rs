@@ -0,0 +1,41 @@
+//! Client library for the Google Play Music Desktop Player (GPMDP)
+//! websocket playback API.
+//!
+//! ```no_run
+//! let client = gpmdp_rc::GpmdpClient::connect("ws://127.0.0.1:5672").unwrap();
+//! client.authenticate("<auth_token>").unwrap();
+//! client.play_pause().unwrap();
+//! println!("volume: {}", client.get_volume().unwrap());
+//! ```
+
+#![allow(clippy::needless_return)]
+
+extern crate serde;
+extern crate serde_json;
+extern crate ws;
+
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod client;
+pub mod commands;
+#[cfg(unix)]
+pub mod daemon;
+pub mod error;
+pub mod events;
+pub mod format;
+pub mod history;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod models;
+#[cfg(feature = "shell")]
+pub mod shell;
+pub mod transcript;
+#[cfg(feature = "tui")]
+pub mod tui;
+
+#[cfg(feature = "async")]
+pub use async_client::AsyncGpmdpClient;
+pub use client::GpmdpClient;
+pub use error::{GpmdpError, Result};
+pub use events::{ChannelEvent, PlaybackState, PlayerState};
+pub use models::{Album, Artist, Playlist, SearchResult, SearchResults, Track};
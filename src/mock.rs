@@ -0,0 +1,524 @@
+//! A stand-in for GPMDP's websocket API, enabled with the `mock` feature.
+//!
+//! The mock does the connect/auth handshake, pushes a scripted payload for
+//! every channel once it has answered the client's first connect and
+//! answers `requestID` calls. Calls
+//! that change the player (playPause, forward, setCurrentTime, setVolume,
+//! ...) update the script and push the new value like GPMDP does. Every call is recorded
+//! so tests can check what a client sent.
+//!
+//! ```no_run
+//! let server = gpmdp_rc::mock::MockServer::start(Default::default()).unwrap();
+//! let client = gpmdp_rc::GpmdpClient::connect(server.url()).unwrap();
+//! client.authenticate(gpmdp_rc::mock::MOCK_TOKEN).unwrap();
+//! client.play_pause().unwrap();
+//! assert_eq!(server.calls()[0].method, "playPause");
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use serde::Deserialize;
+use serde_json::json;
+use crate::client::ws_error_str;
+use crate::error::*;
+
+/// Auth token the default script accepts.
+pub const MOCK_TOKEN: &str = "mock-token";
+
+/// Pairing code the default script accepts.
+pub const MOCK_CODE: &str = "1234";
+
+/// What the mock answers with. Missing fields in a JSON script fall back to
+/// the defaults.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MockScript
+{
+    /// Token handed out for the pairing code and accepted on connect.
+    pub token: String,
+    /// Pairing code "shown" when a client asks for one.
+    pub code: String,
+    /// Payload pushed on each channel when a client connects.
+    pub channels: serde_json::Map<String, serde_json::Value>,
+    /// Fixed "value" for a "namespace.method", instead of the built in
+    /// behavior.
+    pub responses: HashMap<String, serde_json::Value>,
+}
+
+fn mock_track(id: &str, title: &str, index: u64) -> serde_json::Value
+{
+    return json!({
+        "id": id,
+        "title": title,
+        "artist": "Mock Artist",
+        "album": "Mock Album",
+        "albumArtist": "Mock Artist",
+        "index": index,
+        "duration": 200000,
+        "playCount": 0,
+        "albumId": "mock-album",
+        "artistId": "mock-artist",
+    });
+}
+
+impl Default for MockScript
+{
+    fn default() -> MockScript
+    {
+        let tracks = vec![
+            mock_track("mock-track-1", "First Song", 1),
+            mock_track("mock-track-2", "Second Song", 2),
+            mock_track("mock-track-3", "Third Song", 3),
+        ];
+        let album = json!({
+            "id": "mock-album",
+            "name": "Mock Album",
+            "artist": "Mock Artist",
+            "tracks": tracks,
+        });
+        let artist = json!({
+            "id": "mock-artist",
+            "name": "Mock Artist",
+            "albums": [album],
+        });
+
+        let mut channels = serde_json::Map::new();
+        channels.insert("API_VERSION".to_string(), json!("1.1.0"));
+        channels.insert("playState".to_string(), json!(true));
+        channels.insert("track".to_string(), tracks[1].clone());
+        channels.insert("lyrics".to_string(), json!("la la la"));
+        channels.insert("time".to_string(), json!({ "current": 65000, "total": 200000 }));
+        channels.insert("rating".to_string(), json!({ "liked": false, "disliked": false }));
+        channels.insert("shuffle".to_string(), json!("NO_SHUFFLE"));
+        channels.insert("repeat".to_string(), json!("NO_REPEAT"));
+        channels.insert("playlists".to_string(), json!([
+            { "id": "mock-playlist-1", "name": "Mock Playlist", "tracks": tracks },
+            { "id": "mock-playlist-2", "name": "Road Trip", "tracks": [tracks[2]] },
+        ]));
+        channels.insert("queue".to_string(), json!(tracks));
+        channels.insert("search-results".to_string(), json!({
+            "searchText": "mock",
+            "bestMatch": { "type": "artist", "value": artist },
+            "artists": [artist],
+            "albums": [album],
+            "tracks": [tracks[0], tracks[1]],
+        }));
+        channels.insert("library".to_string(), json!({}));
+        channels.insert("volume".to_string(), json!(50));
+        channels.insert("settings:themeColor".to_string(), json!("#ED6237"));
+        channels.insert("settings:theme".to_string(), json!(false));
+        channels.insert("settings:themeType".to_string(), json!("FULL"));
+
+        return MockScript {
+            token: MOCK_TOKEN.to_string(),
+            code: MOCK_CODE.to_string(),
+            channels,
+            responses: HashMap::new(),
+        };
+    }
+}
+
+/// A request the mock received.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockCall
+{
+    pub namespace: String,
+    pub method: String,
+    pub arguments: Vec<serde_json::Value>,
+}
+
+struct MockState
+{
+    script: MockScript,
+    calls: Vec<MockCall>,
+    /// Star rating (0-5) when it isn't a thumbs up (5) or down (1).
+    stars: u64,
+}
+
+impl MockState
+{
+    fn channel(&self, channel: &str) -> serde_json::Value
+    {
+        return self.script.channels.get(channel).cloned()
+            .unwrap_or(serde_json::Value::Null);
+    }
+
+    fn rating(&self) -> u64
+    {
+        let rating = self.channel("rating");
+        if rating["liked"] == json!(true) {
+            return 5;
+        }
+        if rating["disliked"] == json!(true) {
+            return 1;
+        }
+        return self.stars;
+    }
+
+    fn set_rating(&mut self, stars: u64, pushes: &mut Vec<(String, serde_json::Value)>)
+    {
+        self.stars = stars;
+        self.set_channel("rating", json!({ "liked": stars == 5, "disliked": stars == 1 }),
+                         pushes);
+    }
+
+    fn channel_u64(&self, channel: &str) -> u64
+    {
+        return self.script.channels.get(channel)
+            .and_then(|v| v.as_u64()).unwrap_or(0);
+    }
+
+    fn set_channel(&mut self,
+                   channel: &str,
+                   payload: serde_json::Value,
+                   pushes: &mut Vec<(String, serde_json::Value)>)
+    {
+        self.script.channels.insert(channel.to_string(), payload.clone());
+        pushes.push((channel.to_string(), payload));
+    }
+
+    /*
+     * Play along with the calls that change the player. Returns the
+     * response value and the channel pushes to broadcast.
+     */
+    fn answer(&mut self,
+              call: &MockCall) -> (serde_json::Value, Vec<(String, serde_json::Value)>)
+    {
+        let mut pushes = Vec::new();
+        let arg = call.arguments.first().cloned().unwrap_or(serde_json::Value::Null);
+
+        let key = format!("{}.{}", call.namespace, call.method);
+        if let Some(value) = self.script.responses.get(&key) {
+            return (value.clone(), pushes);
+        }
+
+        let value = match key.as_str() {
+            "playback.getPlaybackState" => {
+                let playing = self.script.channels.get("playState") == Some(&json!(true));
+                json!(if playing { 2 } else { 1 })
+            }
+            "playback.playPause" => {
+                let playing = self.script.channels.get("playState") == Some(&json!(true));
+                self.set_channel("playState", json!(!playing), &mut pushes);
+                serde_json::Value::Null
+            }
+            "playback.setCurrentTime" => {
+                let total = self.script.channels.get("time")
+                    .and_then(|time| time["total"].as_u64()).unwrap_or(0);
+                let current = arg.as_u64().unwrap_or(0);
+                self.set_channel("time", json!({ "current": current, "total": total }),
+                                 &mut pushes);
+                serde_json::Value::Null
+            }
+            "playback.isPodcast" => json!(false),
+            "playback.forwardThirty" | "playback.rewindTen" => {
+                let time = self.channel("time");
+                let (current, total) = (time["current"].as_u64().unwrap_or(0),
+                                        time["total"].as_u64().unwrap_or(0));
+                let current = if call.method == "forwardThirty" {
+                    (current + 30000).min(total)
+                } else {
+                    current.saturating_sub(10000)
+                };
+                self.set_channel("time", json!({ "current": current, "total": total }),
+                                 &mut pushes);
+                serde_json::Value::Null
+            }
+            // the next or previous track in the queue, from its start
+            "playback.forward" | "playback.rewind" => {
+                let queue = self.channel("queue");
+                let queue = queue.as_array().cloned().unwrap_or_default();
+                let current = self.channel("track");
+                let pos = queue.iter().position(|track| track["id"] == current["id"]);
+                let pos = match (pos, call.method == "forward") {
+                    (Some(pos), true) if pos + 1 < queue.len() => Some(pos + 1),
+                    (Some(pos), false) if pos > 0 => Some(pos - 1),
+                    _ => None,
+                };
+                if let Some(track) = pos.map(|pos| queue[pos].clone()) {
+                    let total = track["duration"].as_u64().unwrap_or(0);
+                    self.set_channel("track", track, &mut pushes);
+                    self.set_channel("time", json!({ "current": 0, "total": total }), &mut pushes);
+                }
+                serde_json::Value::Null
+            }
+            "playback.getShuffle" => self.channel("shuffle"),
+            "playback.setShuffle" => {
+                self.set_channel("shuffle", arg, &mut pushes);
+                serde_json::Value::Null
+            }
+            "playback.getRepeat" => self.channel("repeat"),
+            "playback.setRepeat" => {
+                self.set_channel("repeat", arg, &mut pushes);
+                serde_json::Value::Null
+            }
+            "volume.getVolume" => json!(self.channel_u64("volume")),
+            "volume.setVolume" => {
+                self.set_channel("volume", json!(arg.as_u64().unwrap_or(0).min(100)),
+                                 &mut pushes);
+                serde_json::Value::Null
+            }
+            "volume.increaseVolume" | "volume.decreaseVolume" => {
+                let volume = self.channel_u64("volume");
+                let amount = arg.as_u64().unwrap_or(5);
+                let volume = if call.method == "increaseVolume" {
+                    (volume + amount).min(100)
+                } else {
+                    volume.saturating_sub(amount)
+                };
+                self.set_channel("volume", json!(volume), &mut pushes);
+                serde_json::Value::Null
+            }
+            // a thumb the other way is only removed, like GPMDP does
+            "rating.toggleThumbsUp" => {
+                let stars = match self.rating() { 1 | 5 => 0, _ => 5 };
+                self.set_rating(stars, &mut pushes);
+                serde_json::Value::Null
+            }
+            "rating.toggleThumbsDown" => {
+                let stars = match self.rating() { 1 | 5 => 0, _ => 1 };
+                self.set_rating(stars, &mut pushes);
+                serde_json::Value::Null
+            }
+            // GPMDP hands the rating back as a string
+            "rating.getRating" => json!(self.rating().to_string()),
+            "rating.setRating" => {
+                self.set_rating(arg.as_u64().unwrap_or(0).min(5), &mut pushes);
+                serde_json::Value::Null
+            }
+            "rating.resetRating" => {
+                self.set_rating(0, &mut pushes);
+                serde_json::Value::Null
+            }
+            "playlists.play" | "playlists.playWithTrack" => {
+                let tracks = arg["tracks"].clone();
+                let track = match call.arguments.get(1) {
+                    Some(track) => track.clone(),
+                    None => tracks[0].clone(),
+                };
+                self.set_channel("queue", tracks, &mut pushes);
+                self.set_channel("track", track, &mut pushes);
+                serde_json::Value::Null
+            }
+            "queue.clear" => {
+                self.set_channel("queue", json!([]), &mut pushes);
+                serde_json::Value::Null
+            }
+            "search.performSearch" => {
+                let mut results = self.script.channels.get("search-results").cloned()
+                    .unwrap_or_else(|| json!({}));
+                results["searchText"] = arg;
+                self.set_channel("search-results", results.clone(), &mut pushes);
+                results
+            }
+            _ => serde_json::Value::Null,
+        };
+
+        return (value, pushes);
+    }
+}
+
+fn lock(state: &Mutex<MockState>) -> MutexGuard<'_, MockState>
+{
+    return state.lock().unwrap_or_else(|e| e.into_inner());
+}
+
+fn channel_push(channel: &str, payload: &serde_json::Value) -> String
+{
+    return json!({ "channel": channel, "payload": payload }).to_string();
+}
+
+struct MockConnection
+{
+    out: ws::Sender,
+    state: Arc<Mutex<MockState>>,
+    authenticated: bool,
+    /// Whether the channels have been pushed yet.
+    pushed: bool,
+}
+
+impl MockConnection
+{
+    /*
+     * Check the token or pairing code of a connect and return the
+     * 'connect' payload to answer with, if any.
+     */
+    fn handle_connect(&mut self, arguments: &[serde_json::Value]) -> Option<String>
+    {
+        let state = lock(&self.state);
+        let secret = arguments.get(1).and_then(|v| v.as_str());
+
+        if secret == Some(state.script.token.as_str()) {
+            self.authenticated = true;
+            return None;
+        }
+
+        self.authenticated = false;
+        if secret == Some(state.script.code.as_str()) {
+            return Some(state.script.token.clone());
+        }
+        return Some("CODE_REQUIRED".to_string());
+    }
+}
+
+impl ws::Handler for MockConnection
+{
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()>
+    {
+        let js: serde_json::Value = match msg.as_text().map(serde_json::from_str) {
+            Ok(Ok(js)) => js,
+            _ => return Ok(()),
+        };
+
+        let call = MockCall {
+            namespace: js["namespace"].as_str().unwrap_or("").to_string(),
+            method: js["method"].as_str().unwrap_or("").to_string(),
+            arguments: js["arguments"].as_array().cloned().unwrap_or_default(),
+        };
+
+        if call.namespace == "connect" {
+            if let Some(payload) = self.handle_connect(&call.arguments) {
+                self.out.send(channel_push("connect", &json!(payload)))?;
+            }
+            /* after the answer, so a rejected token is known before any channel */
+            if !self.pushed {
+                self.pushed = true;
+                let state = lock(&self.state);
+                for (channel, payload) in &state.script.channels {
+                    self.out.send(channel_push(channel, payload))?;
+                }
+            }
+            return Ok(());
+        }
+
+        let request_id = match js["requestID"].as_u64() {
+            Some(request_id) => request_id,
+            None => return Ok(()),
+        };
+
+        if !self.authenticated {
+            return self.out.send(json!({
+                "namespace": call.namespace,
+                "method": call.method,
+                "requestID": request_id,
+                "type": "error",
+                "value": "not authenticated",
+            }).to_string());
+        }
+
+        let mut state = lock(&self.state);
+        let (value, pushes) = state.answer(&call);
+        state.calls.push(call.clone());
+        drop(state);
+
+        self.out.send(json!({
+            "namespace": call.namespace,
+            "method": call.method,
+            "requestID": request_id,
+            "type": "return",
+            "value": value,
+        }).to_string())?;
+
+        for (channel, payload) in pushes {
+            self.out.broadcast(channel_push(&channel, &payload))?;
+        }
+        return Ok(());
+    }
+}
+
+/// A mock GPMDP listening on a local port.
+pub struct MockServer
+{
+    url: String,
+    state: Arc<Mutex<MockState>>,
+    broadcaster: ws::Sender,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MockServer
+{
+    /// Listen on a free port on 127.0.0.1.
+    pub fn start(script: MockScript) -> Result<MockServer>
+    {
+        return MockServer::bind("127.0.0.1:0", script);
+    }
+
+    /// Listen on 'addr' (e.g. 127.0.0.1:5672).
+    pub fn bind(addr: &str, script: MockScript) -> Result<MockServer>
+    {
+        let state = Arc::new(Mutex::new(MockState { script, calls: Vec::new(), stars: 0 }));
+
+        let factory_state = state.clone();
+        let socket = ws::WebSocket::new(move |out| {
+            MockConnection {
+                out,
+                state: factory_state.clone(),
+                authenticated: false,
+                pushed: false,
+            }
+        }).map_err(|e| GpmdpError::Connection(ws_error_str(&e)))?;
+        let socket = socket.bind(addr)
+            .map_err(|e| GpmdpError::Connection(ws_error_str(&e)))?;
+
+        let local_addr = socket.local_addr()
+            .map_err(|e| GpmdpError::Connection(e.to_string()))?;
+        let broadcaster = socket.broadcaster();
+        let thread = thread::spawn(move || {
+            let _ = socket.run();
+        });
+
+        return Ok(MockServer {
+            url: format!("ws://{}", local_addr),
+            state,
+            broadcaster,
+            thread: Some(thread),
+        });
+    }
+
+    /// Websocket url to connect to.
+    pub fn url(&self) -> &str
+    {
+        return &self.url;
+    }
+
+    /// Every request received so far, oldest first. Connect handshakes
+    /// aren't included.
+    pub fn calls(&self) -> Vec<MockCall>
+    {
+        return lock(&self.state).calls.clone();
+    }
+
+    /// Replace the payload of 'channel' and push it to every client.
+    pub fn push(&self, channel: &str, payload: serde_json::Value)
+    {
+        lock(&self.state).script.channels.insert(channel.to_string(), payload.clone());
+        let _ = self.broadcaster.send(channel_push(channel, &payload));
+    }
+
+    /// Answer 'namespace.method' with 'value' from now on.
+    pub fn set_response(&self, namespace: &str, method: &str, value: serde_json::Value)
+    {
+        lock(&self.state).script.responses
+            .insert(format!("{}.{}", namespace, method), value);
+    }
+
+    /// Block until the server stops.
+    pub fn wait(mut self)
+    {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MockServer
+{
+    fn drop(&mut self)
+    {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.broadcaster.shutdown();
+            let _ = thread.join();
+        }
+    }
+}
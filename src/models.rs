@@ -0,0 +1,218 @@
+//! Typed versions of the objects GPMDP sends and accepts.
+//!
+//! Every field is optional since GPMDP leaves out or nulls whatever it
+//! doesn't know. Unrecognized fields are kept in `extra` and the missing
+//! ones stay out when serializing, so an object can be handed back to
+//! GPMDP (e.g. `queue.playTrack(Track)`) as it was sent. Nulls are left out
+//! too, and lists are always written, `[]` when GPMDP left them out.
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/*
+ * Treat a missing or null array as empty.
+ */
+fn nullable_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where D: Deserializer<'de>,
+          T: Deserialize<'de>
+{
+    return Option::<Vec<T>>::deserialize(deserializer)
+        .map(|v| v.unwrap_or_default());
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Track
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_art: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<u64>,
+    /// Length of the track in ms.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub play_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist_image: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Track
+{
+    pub fn title(&self) -> &str
+    {
+        return self.title.as_deref().unwrap_or("");
+    }
+
+    pub fn artist(&self) -> &str
+    {
+        return self.artist.as_deref().unwrap_or("");
+    }
+
+    pub fn album(&self) -> &str
+    {
+        return self.album.as_deref().unwrap_or("");
+    }
+
+    /// Whether 'other' is the same song, matched on artist, album and title.
+    pub fn same_song(&self, other: &Track) -> bool
+    {
+        return self.artist == other.artist &&
+               self.album == other.album &&
+               self.title == other.title;
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Album
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_art: Option<String>,
+    #[serde(default, deserialize_with = "nullable_vec")]
+    pub tracks: Vec<Track>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Album
+{
+    pub fn name(&self) -> &str
+    {
+        return self.name.as_deref().unwrap_or("");
+    }
+
+    pub fn artist(&self) -> &str
+    {
+        return self.artist.as_deref().unwrap_or("");
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Artist
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(default, deserialize_with = "nullable_vec")]
+    pub albums: Vec<Album>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Artist
+{
+    pub fn name(&self) -> &str
+    {
+        return self.name.as_deref().unwrap_or("");
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Playlist
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "nullable_vec")]
+    pub tracks: Vec<Track>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Playlist
+{
+    pub fn name(&self) -> &str
+    {
+        return self.name.as_deref().unwrap_or("");
+    }
+}
+
+/// A single search hit, as passed to `search.playResult(result)`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "value")]
+pub enum SearchResult
+{
+    #[serde(rename = "artist", alias = "Artist")]
+    Artist(Artist),
+    #[serde(rename = "album", alias = "Album")]
+    Album(Album),
+    #[serde(rename = "track", alias = "Track")]
+    Track(Track),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_match: Option<SearchResult>,
+    #[serde(default, deserialize_with = "nullable_vec")]
+    pub albums: Vec<Album>,
+    #[serde(default, deserialize_with = "nullable_vec")]
+    pub artists: Vec<Artist>,
+    #[serde(default, deserialize_with = "nullable_vec")]
+    pub tracks: Vec<Track>,
+}
+
+impl SearchResults
+{
+    /// Total number of results across artists, albums and tracks.
+    pub fn len(&self) -> usize
+    {
+        return self.artists.len() + self.albums.len() + self.tracks.len();
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        return self.len() == 0;
+    }
+
+    /// Result number 'result_num' (1-based). Results are numbered artists
+    /// first, then albums, then tracks.
+    pub fn get(&self, result_num: usize) -> Option<SearchResult>
+    {
+        let mut i = result_num.checked_sub(1)?;
+
+        if i < self.artists.len() {
+            return Some(SearchResult::Artist(self.artists[i].clone()));
+        }
+        i -= self.artists.len();
+
+        if i < self.albums.len() {
+            return Some(SearchResult::Album(self.albums[i].clone()));
+        }
+        i -= self.albums.len();
+
+        return self.tracks.get(i).cloned().map(SearchResult::Track);
+    }
+}
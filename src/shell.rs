@@ -0,0 +1,284 @@
+//! `gpmdp_rc shell`: the commands at a prompt, over one connection.
+//!
+//! The connection is authenticated once and the player state is kept up to
+//! date by GPMDP's channel pushes, so commands don't wait on channels after
+//! the first one and `results <result#>` plays from the last search without
+//! asking GPMDP again. Lines are read with rustyline: line editing, history
+//! and tab completion of command names, keywords, queue tracks, playlists
+//! and search results.
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use crate::client::GpmdpClient;
+use crate::commands::*;
+use crate::error::*;
+use crate::models::Track;
+
+const PROMPT: &str = "gpmdp> ";
+
+/// Keywords completed after a command name.
+const KEYWORDS: [(&str, &[&str]); 7] = [
+    ("seek", &["forward", "backward"]),
+    ("thumbs", &["up", "down"]),
+    ("rating", &["get", "set", "reset"]),
+    ("shuffle", &["on", "off"]),
+    ("repeat", &["all", "single", "off"]),
+    ("volume", &["up", "down"]),
+    ("playlist", &["--tracks"]),
+];
+
+/// Split a line into words. Words are separated by whitespace, '...' and
+/// "..." quote and a backslash escapes the next character outside of ''.
+pub fn split_words(line: &str) -> Result<Vec<String>>
+{
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.get_or_insert_with(String::new).push(c),
+            (_, '\\') => {
+                match chars.next() {
+                    Some(c) => word.get_or_insert_with(String::new).push(c),
+                    None => return Err(GpmdpError::InvalidArgument("trailing '\\'".to_string())),
+                }
+            }
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(GpmdpError::InvalidArgument("unterminated quote".to_string()));
+    }
+    words.extend(word);
+    return Ok(words);
+}
+
+/*
+ * Numbered completions: 'word' matches a number's start or, ignoring case,
+ * the start of its name. The number is what gets inserted.
+ */
+fn numbered(word: &str, names: &[String]) -> Vec<Pair>
+{
+    let lower = word.to_lowercase();
+    return names.iter().enumerate()
+        .map(|(i, name)| (i + 1, name))
+        .filter(|(num, name)| {
+            num.to_string().starts_with(word) || name.to_lowercase().starts_with(&lower)
+        })
+        .map(|(num, name)| Pair { display: format!("{}: {}", num, name),
+                                  replacement: num.to_string() })
+        .collect();
+}
+
+fn track_names(tracks: &[Track]) -> Vec<String>
+{
+    return tracks.iter()
+        .map(|track| format!("{} | {} | {}", track.artist(), track.album(), track.title()))
+        .collect();
+}
+
+struct ShellHelper<'a>
+{
+    client: &'a GpmdpClient,
+    names: Vec<&'static str>,
+}
+
+impl<'a> ShellHelper<'a>
+{
+    /*
+     * Completions for the word being typed, 'args' being the words before
+     * it.
+     */
+    fn candidates(&self, args: &[&str], word: &str) -> Vec<Pair>
+    {
+        let plain = |words: &[&str]| -> Vec<Pair> {
+            words.iter()
+                .filter(|w| w.starts_with(word))
+                .map(|w| Pair { display: w.to_string(), replacement: format!("{} ", w) })
+                .collect()
+        };
+
+        let state = self.client.state();
+        return match args {
+            [] => plain(&self.names),
+            ["play"] => numbered(word, &track_names(&state.cur_queue)),
+            ["playlist"] => {
+                let names: Vec<String> = state.cur_playlists.iter()
+                    .map(|playlist| playlist.name().to_string())
+                    .collect();
+                numbered(word, &names)
+            }
+            ["playlist", num] => {
+                let playlist = num.parse::<usize>().ok()
+                    .and_then(|num| num.checked_sub(1))
+                    .and_then(|i| state.cur_playlists.get(i));
+                let mut pairs = match playlist {
+                    Some(playlist) => numbered(word, &track_names(&playlist.tracks)),
+                    None => Vec::new(),
+                };
+                pairs.extend(plain(&["--tracks"]));
+                pairs
+            }
+            ["results"] => {
+                let search = &state.cur_search;
+                let mut names: Vec<String> = search.artists.iter()
+                    .map(|artist| artist.name().to_string())
+                    .collect();
+                names.extend(search.albums.iter()
+                             .map(|album| format!("{} | {}", album.artist(), album.name())));
+                names.extend(track_names(&search.tracks));
+                numbered(word, &names)
+            }
+            [command] => {
+                KEYWORDS.iter()
+                    .find(|(name, _)| name == command)
+                    .map_or(Vec::new(), |(_, keywords)| plain(keywords))
+            }
+            _ => Vec::new(),
+        };
+    }
+}
+
+impl<'a> Completer for ShellHelper<'a>
+{
+    type Candidate = Pair;
+
+    fn complete(&self,
+                line: &str,
+                pos: usize,
+                _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)>
+    {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let args: Vec<&str> = before[..start].split_whitespace().collect();
+        return Ok((start, self.candidates(&args, &before[start..])));
+    }
+}
+
+impl<'a> Hinter for ShellHelper<'a>
+{
+    type Hint = String;
+}
+
+impl<'a> Highlighter for ShellHelper<'a> {}
+
+impl<'a> Validator for ShellHelper<'a> {}
+
+impl<'a> Helper for ShellHelper<'a> {}
+
+fn readline_error(err: ReadlineError) -> GpmdpError
+{
+    return GpmdpError::Config(format!("terminal: {}", err));
+}
+
+/*
+ * Run one command line, printing its results and errors in 'format'.
+ */
+fn run_line(client: &GpmdpClient, registry: &Registry, words: &[String], format: &Output)
+{
+    let print = |text: String| {
+        if !text.is_empty() {
+            println!("{}", text);
+        }
+    };
+
+    let res = registry.parse(words).and_then(|(command, args)| {
+        /* these would never give the prompt back */
+        if command.long_running() {
+            return Err(GpmdpError::InvalidArgument(
+                format!("'{}' can't be run from the shell", command.name())));
+        }
+        return execute_stream(command, client, registry, &args, &mut |output| {
+            print(format_output(command, &output, format));
+        });
+    });
+    if let Err(e) = res {
+        print(format_error(&e, format));
+    }
+}
+
+/*
+ * The usage lines of the commands that can be run from the shell.
+ */
+fn help(registry: &Registry) -> String
+{
+    let mut lines: Vec<String> = registry.commands()
+        .filter(|command| !command.long_running())
+        .map(|command| format!("  {} {}", command.name(), command.synopsis()).trim_end().to_string())
+        .collect();
+    lines.push("  help".to_string());
+    lines.push("  quit".to_string());
+    return lines.join("\n");
+}
+
+/// Read command lines until end of input, "quit" or "exit", or until the
+/// connection closes. 'format' says how to print each command's results,
+/// 'history' is the file to keep the line history in.
+pub fn run(client: &GpmdpClient,
+           registry: &Registry,
+           format: &dyn Fn(&str) -> Output,
+           history: Option<&str>) -> Result<()>
+{
+    let mut names: Vec<&'static str> = registry.commands()
+        .filter(|command| !command.long_running())
+        .map(|command| command.name())
+        .collect();
+    names.extend(&["help", "quit"]);
+
+    let mut editor: Editor<ShellHelper, DefaultHistory> =
+        Editor::new().map_err(readline_error)?;
+    editor.set_helper(Some(ShellHelper { client, names }));
+    if let Some(file) = history {
+        /* there's none the first time */
+        let _ = editor.load_history(file);
+    }
+
+    let res = loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break Ok(()),
+            Err(err) => break Err(readline_error(err)),
+        };
+        let words = match split_words(&line) {
+            Ok(words) => words,
+            Err(e) => {
+                println!("ERROR: {}", e);
+                continue;
+            }
+        };
+        if words.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+
+        match words[0].as_str() {
+            "quit" | "exit" => break Ok(()),
+            "help" => println!("{}", help(registry)),
+            name => run_line(client, registry, &words, &format(name)),
+        }
+
+        if let Some(e) = client.close_reason() {
+            break Err(e);
+        }
+    };
+
+    if let Some(file) = history {
+        let _ = editor.save_history(file);
+    }
+    return res;
+}
@@ -0,0 +1,225 @@
+//! NDJSON transcripts of a websocket session, for bug reports and
+//! regression fixtures.
+//!
+//! Every frame is one line:
+//!
+//! ```text
+//! {"ts":1697625600123,"dir":"out","msg":{"method":"getVolume","namespace":"volume","requestID":1}}
+//! {"ts":1697625600131,"dir":"in","msg":{"method":"getVolume","namespace":"volume","requestID":1,"type":"return","value":50}}
+//! ```
+//!
+//! `ts` is the wall clock time in ms since the epoch and `dir` is "out" for
+//! frames the client sent and "in" for frames GPMDP sent. Auth tokens and
+//! pairing codes are replaced with "<redacted>" so transcripts can be shared.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::error::*;
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction
+{
+    /// GPMDP to client.
+    In,
+    /// Client to GPMDP.
+    Out,
+}
+
+/// One line of a transcript.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Frame
+{
+    /// Wall clock time in ms since the epoch.
+    pub ts: u64,
+    pub dir: Direction,
+    /// The frame as sent, or a JSON string if it wasn't valid JSON.
+    pub msg: serde_json::Value,
+}
+
+/*
+ * Blank out the secrets in a connect request or 'connect' channel push.
+ */
+fn redact(dir: Direction, msg: &mut serde_json::Value)
+{
+    match dir {
+        Direction::Out if msg["namespace"] == "connect" => {
+            if let Some(args) = msg["arguments"].as_array_mut() {
+                for arg in args.iter_mut().skip(1) {
+                    *arg = serde_json::json!(REDACTED);
+                }
+            }
+        }
+        Direction::In if msg["channel"] == "connect" && msg["payload"] != "CODE_REQUIRED" => {
+            msg["payload"] = serde_json::json!(REDACTED);
+        }
+        _ => {}
+    }
+}
+
+/// Writes every frame of a session to a transcript.
+pub struct Recorder
+{
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Recorder
+{
+    /// Record to 'file', replacing whatever was there.
+    pub fn create(file: &str) -> Result<Recorder>
+    {
+        let out = fs::File::create(file).map_err(|e| {
+            GpmdpError::Config(format!("failed to create transcript {}: {}", file, e))
+        })?;
+        return Ok(Recorder::new(out));
+    }
+
+    pub fn new<W: Write + Send + 'static>(out: W) -> Recorder
+    {
+        return Recorder { out: Mutex::new(Box::new(out)) };
+    }
+
+    /// Append a frame. Write errors are ignored so a full disk doesn't
+    /// take the session down with it.
+    pub fn record(&self, dir: Direction, text: &str)
+    {
+        let mut msg = serde_json::from_str(text)
+            .unwrap_or_else(|_| serde_json::Value::String(text.to_string()));
+        redact(dir, &mut msg);
+
+        // timestamp under the lock so the lines stay in time order
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let line = match serde_json::to_string(&Frame { ts, dir, msg }) {
+            Ok(line) => line,
+            Err(_err) => return,
+        };
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+}
+
+/// Parse a transcript, one frame per line. Blank lines are skipped.
+pub fn parse(s: &str) -> Result<Vec<Frame>>
+{
+    let mut frames = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame = serde_json::from_str(line).map_err(|e| {
+            GpmdpError::Decode(format!("transcript line {}: {}", i + 1, e))
+        })?;
+        frames.push(frame);
+    }
+    return Ok(frames);
+}
+
+/// Read and parse the transcript in 'file'.
+pub fn load(file: &str) -> Result<Vec<Frame>>
+{
+    let s = fs::read_to_string(file).map_err(|e| {
+        GpmdpError::Config(format!("failed to read transcript {}: {}", file, e))
+    })?;
+    return parse(&s);
+}
+
+/*
+ * Whether the client sent the request the transcript expects next. The
+ * requestID can differ, as can the secret in a connect.
+ */
+fn same_request(expected: &serde_json::Value, sent: &serde_json::Value) -> bool
+{
+    if expected["namespace"] != sent["namespace"] || expected["method"] != sent["method"] {
+        return false;
+    }
+    if sent["namespace"] == "connect" {
+        return expected["arguments"].as_array().map(|a| a.len()) ==
+               sent["arguments"].as_array().map(|a| a.len());
+    }
+    let no_args = serde_json::json!([]);
+    let args = |js: &serde_json::Value| -> serde_json::Value {
+        if js["arguments"].is_null() { no_args.clone() } else { js["arguments"].clone() }
+    };
+    return args(expected) == args(sent);
+}
+
+fn request_name(js: &serde_json::Value) -> String
+{
+    return format!("{}.{}",
+                   js["namespace"].as_str().unwrap_or(""),
+                   js["method"].as_str().unwrap_or(""));
+}
+
+/*
+ * Plays a transcript back to a client: the recorded inbound frames are
+ * handed over in order, pausing at each recorded outbound frame until the
+ * client sends the matching request.
+ */
+pub(crate) struct Replay
+{
+    frames: VecDeque<Frame>,
+    /// Recorded requestID -> the one the client used.
+    request_ids: HashMap<u64, u64>,
+}
+
+impl Replay
+{
+    pub(crate) fn new(frames: Vec<Frame>) -> Replay
+    {
+        return Replay { frames: frames.into(), request_ids: HashMap::new() };
+    }
+
+    /// Inbound frames up to the next outbound one.
+    pub(crate) fn inbound(&mut self) -> Vec<String>
+    {
+        let mut msgs = Vec::new();
+        while let Some(frame) = self.frames.front() {
+            if frame.dir == Direction::Out {
+                break;
+            }
+            let mut msg = self.frames.pop_front().unwrap().msg;
+            if let Some(request_id) = msg["requestID"].as_u64() {
+                if let Some(sent_id) = self.request_ids.remove(&request_id) {
+                    msg["requestID"] = serde_json::json!(sent_id);
+                }
+            }
+            msgs.push(match msg {
+                serde_json::Value::String(text) => text,
+                msg => msg.to_string(),
+            });
+        }
+        return msgs;
+    }
+
+    /// Check a frame the client sent against the next outbound frame.
+    pub(crate) fn outbound(&mut self, text: &str) -> Result<()>
+    {
+        let sent: serde_json::Value = serde_json::from_str(text)?;
+        let expected = match self.frames.pop_front() {
+            Some(frame) => frame.msg,
+            None => {
+                return Err(GpmdpError::Connection(
+                    format!("replay: transcript ended before {}", request_name(&sent))));
+            }
+        };
+
+        if !same_request(&expected, &sent) {
+            return Err(GpmdpError::Decode(
+                format!("replay: expected {} but got {}", expected, sent)));
+        }
+
+        if let (Some(recorded), Some(sent_id)) = (expected["requestID"].as_u64(),
+                                                  sent["requestID"].as_u64()) {
+            self.request_ids.insert(recorded, sent_id);
+        }
+        return Ok(());
+    }
+}
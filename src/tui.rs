@@ -0,0 +1,392 @@
+//! `gpmdp_rc tui`: a full screen terminal interface.
+//!
+//! The screen is drawn from the player state GPMDP's channel pushes keep
+//! up to date: the current track with its progress, and a pane each for the
+//! queue, playlists, search results and lyrics. Keys run the same commands
+//! the command line has, through its `Registry`.
+
+use std::time::Duration;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph, Tabs, Wrap};
+use ratatui::Frame;
+use crate::client::GpmdpClient;
+use crate::commands::builtin::{fmt_time, rating_str, repeat_str, shuffle_str};
+use crate::commands::*;
+use crate::error::*;
+use crate::events::PlayerState;
+use crate::models::Track;
+
+/// How often the screen is redrawn, so the progress bar keeps moving.
+const REDRAW_MSECS: u64 = 200;
+
+const HELP: &str = "space play/pause  n/b next/prev  \u{2190}/\u{2192} seek  +/- volume  \
+                    u/d thumbs  s shuffle  r repeat  / search  tab pane  enter play  q quit";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pane
+{
+    Queue,
+    Playlists,
+    Search,
+    Lyrics,
+}
+
+const PANES: [(Pane, &str); 4] = [
+    (Pane::Queue, "Queue"),
+    (Pane::Playlists, "Playlists"),
+    (Pane::Search, "Search"),
+    (Pane::Lyrics, "Lyrics"),
+];
+
+fn track_item(track: &Track) -> String
+{
+    let duration = track.duration.map_or(String::new(), |ms| format!(" ({})", fmt_time(ms)));
+    return format!("{} - {}{}", track.artist(), track.title(), duration);
+}
+
+/*
+ * The lines of a pane's list, numbered the way the command that plays an
+ * entry takes them.
+ */
+fn pane_items(pane: Pane, state: &PlayerState) -> Vec<String>
+{
+    let numbered = |items: Vec<String>| -> Vec<String> {
+        items.into_iter().enumerate().map(|(i, item)| format!("{:3} {}", i + 1, item)).collect()
+    };
+    return match pane {
+        Pane::Queue => numbered(state.cur_queue.iter().map(track_item).collect()),
+        Pane::Playlists => {
+            numbered(state.cur_playlists.iter()
+                     .map(|playlist| format!("{} [{}]", playlist.name(), playlist.tracks.len()))
+                     .collect())
+        }
+        Pane::Search => {
+            let search = &state.cur_search;
+            let mut items: Vec<String> = search.artists.iter()
+                .map(|artist| format!("artist: {}", artist.name()))
+                .collect();
+            items.extend(search.albums.iter()
+                         .map(|album| format!("album: {} - {}", album.artist(), album.name())));
+            items.extend(search.tracks.iter().map(|track| format!("track: {}", track_item(track))));
+            numbered(items)
+        }
+        Pane::Lyrics => state.cur_track_lyrics.lines().map(str::to_string).collect(),
+    };
+}
+
+/// The TUI's state, apart from the terminal.
+pub struct App<'a>
+{
+    client: &'a GpmdpClient,
+    registry: &'a Registry,
+    pane: Pane,
+    lists: [ListState; 4],
+    /// The search being typed after '/'.
+    input: Option<String>,
+    /// Output or error of the last command.
+    message: String,
+    quit: bool,
+}
+
+impl<'a> App<'a>
+{
+    pub fn new(client: &'a GpmdpClient, registry: &'a Registry) -> App<'a>
+    {
+        return App {
+            client,
+            registry,
+            pane: Pane::Queue,
+            lists: Default::default(),
+            input: None,
+            message: String::new(),
+            quit: false,
+        };
+    }
+
+    pub fn pane(&self) -> Pane
+    {
+        return self.pane;
+    }
+
+    /// Whether the user asked to quit.
+    pub fn done(&self) -> bool
+    {
+        return self.quit;
+    }
+
+    /// Output or error of the last command.
+    pub fn message(&self) -> &str
+    {
+        return &self.message;
+    }
+
+    fn list(&mut self) -> &mut ListState
+    {
+        let i = PANES.iter().position(|(pane, _)| *pane == self.pane).unwrap_or(0);
+        return &mut self.lists[i];
+    }
+
+    /*
+     * Run a command line and keep its output or error for the status line.
+     */
+    fn command(&mut self, argv: &[String])
+    {
+        let res = self.registry.parse(argv).and_then(|(command, args)| {
+            return execute(command, self.client, self.registry, &args).map(|output| {
+                return command.render(&command.public(&output));
+            });
+        });
+        self.message = match res {
+            Ok(text) => text.lines().next().unwrap_or("").to_string(),
+            Err(e) => format!("ERROR: {}", e),
+        };
+    }
+
+    fn run(&mut self, argv: &[&str])
+    {
+        let argv: Vec<String> = argv.iter().map(|arg| arg.to_string()).collect();
+        self.command(&argv);
+    }
+
+    /*
+     * Play the selected entry of the pane.
+     */
+    fn play_selected(&mut self)
+    {
+        let num = match self.list().selected() {
+            Some(i) => (i + 1).to_string(),
+            None => return,
+        };
+        match self.pane {
+            Pane::Queue => self.run(&["play", &num]),
+            Pane::Playlists => self.run(&["playlist", &num]),
+            Pane::Search => self.run(&["results", &num]),
+            Pane::Lyrics => {}
+        }
+    }
+
+    fn select(&mut self, pane: Pane)
+    {
+        self.pane = pane;
+    }
+
+    fn move_selection(&mut self, delta: isize)
+    {
+        let len = pane_items(self.pane, &self.client.state()).len();
+        let list = self.list();
+        if len == 0 {
+            list.select(None);
+            return;
+        }
+        let i = list.selected().map_or(0, |i| i as isize + delta);
+        list.select(Some(i.clamp(0, len as isize - 1) as usize));
+    }
+
+    fn search_key(&mut self, key: KeyEvent)
+    {
+        let input = self.input.get_or_insert_with(String::new);
+        match key.code {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Esc => self.input = None,
+            KeyCode::Enter => {
+                let text = self.input.take().unwrap_or_default();
+                if !text.is_empty() {
+                    self.command(&["search".to_string(), text]);
+                    self.message.clear();
+                    self.pane = Pane::Search;
+                    self.list().select(Some(0));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent)
+    {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+        if self.input.is_some() {
+            self.search_key(key);
+            return;
+        }
+
+        let state = self.client.state();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char(' ') | KeyCode::Char('p') => self.run(&["pause"]),
+            KeyCode::Char('n') | KeyCode::Char('>') => self.run(&["next"]),
+            KeyCode::Char('b') | KeyCode::Char('<') => self.run(&["prev"]),
+            KeyCode::Right => self.run(&["seek", "forward"]),
+            KeyCode::Left => self.run(&["seek", "backward"]),
+            KeyCode::Char('+') | KeyCode::Char('=') => self.run(&["volume", "up"]),
+            KeyCode::Char('-') => self.run(&["volume", "down"]),
+            KeyCode::Char('u') => self.run(&["thumbs", "up"]),
+            KeyCode::Char('d') => self.run(&["thumbs", "down"]),
+            KeyCode::Char('s') => {
+                self.run(&["shuffle", if shuffle_str(&state.cur_shuffle) == "on" { "off" } else { "on" }]);
+            }
+            KeyCode::Char('r') => {
+                let next = match repeat_str(&state.cur_repeat) {
+                    "off" => "all",
+                    "all" => "single",
+                    _ => "off",
+                };
+                self.run(&["repeat", next]);
+            }
+            KeyCode::Char('/') => self.input = Some(String::new()),
+            KeyCode::Tab => {
+                let i = PANES.iter().position(|(pane, _)| *pane == self.pane).unwrap_or(0);
+                self.select(PANES[(i + 1) % PANES.len()].0);
+            }
+            KeyCode::Char(c @ '1'..='4') => self.select(PANES[c as usize - '1' as usize].0),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::Enter => self.play_selected(),
+            _ => {}
+        }
+    }
+
+    fn draw_now_playing(&self, frame: &mut Frame, area: Rect, state: &PlayerState)
+    {
+        let [info_area, gauge_area] =
+            Layout::vertical([Constraint::Length(4), Constraint::Length(1)]).areas(area);
+
+        let track = &state.cur_track;
+        let playing = if track.title().is_empty() {
+            "Stopped"
+        } else if state.cur_playing {
+            "Playing"
+        } else {
+            "Paused"
+        };
+        let info = Text::from(vec![
+            Line::styled(format!("{}: {}", playing, track.title()),
+                         Style::default().add_modifier(Modifier::BOLD)),
+            Line::from(format!("{} - {}", track.artist(), track.album())),
+            Line::from(format!("thumbs {} | shuffle {} | repeat {} | volume {}",
+                               rating_str(state.cur_track_liked, state.cur_track_disliked),
+                               shuffle_str(&state.cur_shuffle),
+                               repeat_str(&state.cur_repeat),
+                               state.cur_volume)),
+        ]);
+        frame.render_widget(Paragraph::new(info)
+                            .block(Block::default().borders(Borders::TOP).title(" gpmdp_rc ")),
+                            info_area);
+
+        let ratio = if state.cur_track_total == 0 {
+            0.0
+        } else {
+            (state.cur_track_progress as f64 / state.cur_track_total as f64).clamp(0.0, 1.0)
+        };
+        frame.render_widget(Gauge::default()
+                            .ratio(ratio)
+                            .label(format!("{} / {}", fmt_time(state.cur_track_progress),
+                                           fmt_time(state.cur_track_total))),
+                            gauge_area);
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame)
+    {
+        let state = self.client.state();
+        let [now_area, tabs_area, pane_area, status_area] = Layout::vertical([
+            Constraint::Length(5),
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ]).areas(frame.area());
+
+        self.draw_now_playing(frame, now_area, &state);
+
+        let selected = PANES.iter().position(|(pane, _)| *pane == self.pane).unwrap_or(0);
+        frame.render_widget(Tabs::new(PANES.iter().enumerate()
+                                      .map(|(i, (_, title))| format!("{} {}", i + 1, title)))
+                            .select(selected)
+                            .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+                            tabs_area);
+
+        let items = pane_items(self.pane, &state);
+        let block = Block::default().borders(Borders::ALL);
+        if self.pane == Pane::Lyrics {
+            let scroll = self.list().selected().unwrap_or(0) as u16;
+            let lyrics = if items.is_empty() { "Lyrics not available!".to_string() }
+                         else { items.join("\n") };
+            frame.render_widget(Paragraph::new(lyrics)
+                                .block(block)
+                                .wrap(Wrap { trim: false })
+                                .scroll((scroll, 0)),
+                                pane_area);
+        } else {
+            let current = if self.pane == Pane::Queue { state.queue_position() } else { 0 };
+            let items: Vec<ListItem> = items.into_iter().enumerate()
+                .map(|(i, item)| {
+                    let style = if i + 1 == current {
+                        Style::default().add_modifier(Modifier::BOLD)
+                    } else {
+                        Style::default()
+                    };
+                    ListItem::new(item).style(style)
+                })
+                .collect();
+            let list = List::new(items)
+                .block(block)
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            frame.render_stateful_widget(list, pane_area, self.list());
+        }
+
+        let status = match &self.input {
+            Some(input) => format!("search: {}_", input),
+            None if !self.message.is_empty() => self.message.clone(),
+            None => HELP.to_string(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+}
+
+fn terminal_error(err: std::io::Error) -> GpmdpError
+{
+    return GpmdpError::Config(format!("terminal: {}", err));
+}
+
+/// Take over the terminal until the user quits or the connection closes,
+/// running the commands in 'registry'.
+pub fn run(client: &GpmdpClient, registry: &Registry) -> Result<()>
+{
+    let mut terminal = ratatui::try_init().map_err(terminal_error)?;
+    let mut app = App::new(client, registry);
+
+    let res = loop {
+        if let Err(err) = terminal.draw(|frame| app.draw(frame)) {
+            break Err(terminal_error(err));
+        }
+        match event::poll(Duration::from_millis(REDRAW_MSECS)) {
+            Ok(true) => {
+                match event::read() {
+                    Ok(Event::Key(key)) => app.handle_key(key),
+                    Ok(_) => {}
+                    Err(err) => break Err(terminal_error(err)),
+                }
+            }
+            Ok(false) => {}
+            Err(err) => break Err(terminal_error(err)),
+        }
+        if app.done() {
+            break Ok(());
+        }
+        if let Some(e) = client.close_reason() {
+            break Err(e);
+        }
+    };
+
+    ratatui::restore();
+    return res;
+}
@@ -0,0 +1,72 @@
+//! AsyncGpmdpClient against the mock GPMDP.
+
+#![cfg(feature = "async")]
+#![allow(clippy::needless_return)]
+
+use std::time::Duration;
+use futures_util::{FutureExt, StreamExt};
+use gpmdp_rc::mock::*;
+use gpmdp_rc::*;
+
+async fn connect() -> (MockServer, AsyncGpmdpClient)
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = AsyncGpmdpClient::connect(server.url()).await.unwrap();
+    client.authenticate(MOCK_TOKEN).await.unwrap();
+    return (server, client);
+}
+
+#[tokio::test]
+async fn call()
+{
+    let (server, client) = connect().await;
+
+    let response = client.call("volume", "getVolume", "").await.unwrap();
+    assert_eq!(response["value"], 50);
+    client.call("volume", "setVolume", "[30]").await.unwrap();
+    assert_eq!(client.call("volume", "getVolume", "").await.unwrap()["value"], 30);
+    assert_eq!(server.calls().last().unwrap().method, "getVolume");
+
+    server.set_response("playback", "getPlaybackState", serde_json::json!("soon"));
+    assert_eq!(client.call("playback", "getPlaybackState", "").await.unwrap()["value"], "soon");
+}
+
+#[tokio::test]
+async fn concurrent_calls()
+{
+    let (_server, client) = connect().await;
+
+    let (volume, state, podcast, shuffle) = tokio::join!(
+        client.call("volume", "getVolume", ""),
+        client.call("playback", "getPlaybackState", ""),
+        client.call("playback", "isPodcast", ""),
+        client.call("playback", "getShuffle", ""),
+    );
+    assert_eq!(volume.unwrap()["value"], 50);
+    assert_eq!(state.unwrap()["value"], 2);
+    assert_eq!(podcast.unwrap()["value"], false);
+    assert_eq!(shuffle.unwrap()["value"], "NO_SHUFFLE");
+
+    // a call given up on before its answer leaves the next ones alone
+    assert!(client.call("volume", "getVolume", "").now_or_never().is_none());
+    assert_eq!(client.call("volume", "getVolume", "").await.unwrap()["value"], 50);
+}
+
+#[tokio::test]
+async fn events()
+{
+    let (server, client) = connect().await;
+    client.wait_for_channels(events::CHNLS_ALL).await.unwrap();
+    assert_eq!(client.state().cur_track.title(), "Second Song");
+
+    let mut events = Box::pin(client.events());
+    client.call("playback", "playPause", "").await.unwrap();
+    assert_eq!(events.next().await, Some(ChannelEvent::PlayState(false)));
+    server.push("volume", serde_json::json!(7));
+    assert_eq!(events.next().await, Some(ChannelEvent::Volume(7)));
+    assert_eq!(client.state().cur_volume, 7);
+
+    // the stream ends with the connection
+    drop(server);
+    assert_eq!(tokio::time::timeout(Duration::from_secs(4), events.next()).await, Ok(None));
+}
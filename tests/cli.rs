@@ -0,0 +1,708 @@
+//! End-to-end runs of the gpmdp_rc binary against the mock GPMDP.
+
+#![allow(clippy::needless_return)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use gpmdp_rc::mock::*;
+
+struct Cli
+{
+    server: MockServer,
+    config: PathBuf,
+}
+
+impl Cli
+{
+    fn new(name: &str, token: &str) -> Cli
+    {
+        let server = MockServer::start(MockScript::default()).unwrap();
+        let config = std::env::temp_dir()
+            .join(format!("gpmdp_rc_test_{}_{}.yaml", std::process::id(), name));
+        fs::write(&config, format!("url: {}\ntoken: {}\n", server.url(), token)).unwrap();
+        return Cli { server, config };
+    }
+
+    /// Run a command, feeding it 'input' on stdin. Returns the exit code
+    /// and stdout.
+    fn run(&self, args: &[&str], input: &str) -> (i32, String)
+    {
+        // never a daemon the user has running
+        let mut child = Command::new(env!("CARGO_BIN_EXE_gpmdp_rc"))
+            .arg("-c").arg(&self.config)
+            .args(args)
+            .env_remove("XDG_RUNTIME_DIR")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        return (output.status.code().unwrap(),
+                String::from_utf8(output.stdout).unwrap());
+    }
+
+    fn last_call(&self) -> MockCall
+    {
+        return self.server.calls().pop().expect("no calls made");
+    }
+}
+
+impl Drop for Cli
+{
+    fn drop(&mut self)
+    {
+        let _ = fs::remove_file(&self.config);
+    }
+}
+
+#[test]
+fn status()
+{
+    let cli = Cli::new("status", MOCK_TOKEN);
+    let (code, out) = cli.run(&["status"], "");
+    assert_eq!(code, 0, "{}", out);
+
+    for line in &["state: playing",
+                  "artist: Mock Artist",
+                  "album: Mock Album",
+                  "title: Second Song",
+                  "time_elapsed_fmt: 1:05",
+                  "time_elapsed_secs: 65",
+                  "time_total_fmt: 3:20",
+                  "time_total_secs: 200",
+                  "rating: none",
+                  "volume: 50",
+                  "shuffle: off",
+                  "repeat: off",
+                  "queue_track: 2",
+                  "queue_length: 3",
+                  "podcast: false"] {
+        assert!(out.lines().any(|l| l == *line), "missing '{}' in:\n{}", line, out);
+    }
+    assert_eq!(out.lines().count(), 15, "{}", out);
+}
+
+#[test]
+fn seek()
+{
+    let cli = Cli::new("seek", MOCK_TOKEN);
+
+    // the mock moves the time along with each seek, starting at 1:05
+    for (arg, ms) in &[("-3", 62000), ("+10", 72000), ("forward", 82000),
+                       ("backward", 72000), ("-100", 0), ("500", 200000)] {
+        let (code, out) = cli.run(&["seek", arg], "");
+        assert_eq!(code, 0, "seek {}: {}", arg, out);
+
+        let call = cli.last_call();
+        assert_eq!((call.namespace.as_str(), call.method.as_str()),
+                   ("playback", "setCurrentTime"));
+        assert_eq!(call.arguments, vec![serde_json::json!(ms)], "seek {}", arg);
+    }
+
+    let (code, _) = cli.run(&["seek", "soon"], "");
+    assert_eq!(code, 2);
+}
+
+#[test]
+fn play_queue_track()
+{
+    let cli = Cli::new("play", MOCK_TOKEN);
+    let (code, out) = cli.run(&["play", "3"], "");
+    assert_eq!(code, 0, "{}", out);
+
+    let call = cli.last_call();
+    assert_eq!((call.namespace.as_str(), call.method.as_str()), ("queue", "playTrack"));
+    assert_eq!(call.arguments[0]["id"], "mock-track-3");
+    assert_eq!(call.arguments[0]["title"], "Third Song");
+
+    let (code, out) = cli.run(&["play", "4"], "");
+    assert_eq!(code, 2);
+    assert_eq!(out.trim(), "ERROR: invalid track number 4");
+    assert_eq!(cli.server.calls().len(), 1);
+}
+
+#[test]
+fn play_search_result()
+{
+    let cli = Cli::new("results", MOCK_TOKEN);
+
+    let (code, out) = cli.run(&["results"], "");
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(out, "1: Mock Artist\n\
+                     2: Mock Artist | Mock Album\n\
+                     3: Mock Artist | Mock Album | First Song\n\
+                     4: Mock Artist | Mock Album | Second Song\n");
+
+    cli.run(&["results", "1"], "");
+    let call = cli.last_call();
+    assert_eq!((call.namespace.as_str(), call.method.as_str()), ("search", "playResult"));
+    assert_eq!(call.arguments[0]["id"], "mock-artist");
+
+    cli.run(&["results", "2"], "");
+    assert_eq!(cli.last_call().arguments[0]["id"], "mock-album");
+
+    cli.run(&["results", "4"], "");
+    assert_eq!(cli.last_call().arguments[0]["id"], "mock-track-2");
+
+    let (code, _) = cli.run(&["results", "5"], "");
+    assert_eq!(code, 2);
+    assert_eq!(cli.server.calls().len(), 3);
+}
+
+#[test]
+fn auth()
+{
+    let cli = Cli::new("auth", "");
+
+    let (code, out) = cli.run(&["auth"], &format!("{}\n", MOCK_CODE));
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.ends_with(&format!("Token: {}\n", MOCK_TOKEN)), "{}", out);
+
+    let (code, out) = cli.run(&["auth"], "0000\n");
+    assert_eq!(code, 5, "{}", out);
+    assert!(out.contains("ERROR: authentication: invalid pairing code"), "{}", out);
+}
+
+#[test]
+fn config_errors()
+{
+    let cli = Cli::new("config", MOCK_TOKEN);
+    fs::write(&cli.config, "token: abc\n").unwrap();
+    let (code, out) = cli.run(&["status"], "");
+    assert_eq!(code, 3);
+    assert!(out.starts_with("ERROR: config: no 'url'"), "{}", out);
+
+    fs::write(&cli.config, "url: ws://127.0.0.1:1\n").unwrap();
+    let (code, _) = cli.run(&["status"], "");
+    assert_eq!(code, 4);
+}
+
+#[test]
+fn thumbs_and_rating()
+{
+    let cli = Cli::new("rating", MOCK_TOKEN);
+    let methods = |cli: &Cli| -> Vec<String> {
+        cli.server.calls().into_iter().map(|call| call.method).collect()
+    };
+
+    // thumbs up is only toggled when the track isn't liked yet
+    assert_eq!(cli.run(&["thumbs", "up"], "").0, 0);
+    assert_eq!(cli.run(&["thumbs", "up"], "").0, 0);
+    assert_eq!(methods(&cli), vec!["toggleThumbsUp"]);
+    assert_eq!(cli.run(&["rating", "get"], ""), (0, "5\n".to_string()));
+
+    // from up to down takes two toggles
+    assert_eq!(cli.run(&["thumbs", "down"], "").0, 0);
+    assert_eq!(cli.run(&["status"], "").1.lines().find(|l| l.starts_with("rating:")),
+               Some("rating: down"));
+
+    assert_eq!(cli.run(&["rating", "set", "3"], "").0, 0);
+    assert_eq!(cli.last_call().arguments, vec![serde_json::json!(3)]);
+    assert_eq!(cli.run(&["rating", "get"], ""), (0, "3\n".to_string()));
+
+    assert_eq!(cli.run(&["rating", "reset"], "").0, 0);
+    assert_eq!(cli.run(&["rating", "get"], ""), (0, "0\n".to_string()));
+
+    assert_eq!(methods(&cli), vec!["toggleThumbsUp", "getRating",
+                                   "toggleThumbsDown", "toggleThumbsDown",
+                                   "getPlaybackState", "isPodcast", "setRating", "getRating",
+                                   "resetRating", "getRating"]);
+
+    assert_eq!(cli.run(&["rating", "set", "6"], "").0, 2);
+    assert_eq!(cli.run(&["rating"], "").0, 2);
+}
+
+#[test]
+fn playlist_tracks()
+{
+    let cli = Cli::new("playlist", MOCK_TOKEN);
+
+    let (code, out) = cli.run(&["playlist", "1", "--tracks"], "");
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(out, "1: Mock Artist | Mock Album | First Song\n\
+                     2: Mock Artist | Mock Album | Second Song\n\
+                     3: Mock Artist | Mock Album | Third Song\n");
+    assert!(cli.server.calls().is_empty());
+
+    assert_eq!(cli.run(&["playlist", "1", "3"], "").0, 0);
+    let call = cli.last_call();
+    assert_eq!((call.namespace.as_str(), call.method.as_str()),
+               ("playlists", "playWithTrack"));
+    assert_eq!(call.arguments[0]["name"], "Mock Playlist");
+    assert_eq!(call.arguments[1]["id"], "mock-track-3");
+
+    assert_eq!(cli.run(&["playlist", "2"], "").0, 0);
+    assert_eq!(cli.last_call().method, "play");
+
+    let (code, out) = cli.run(&["playlist", "2", "2"], "");
+    assert_eq!((code, out.as_str()),
+               (2, "ERROR: invalid track number 2 for playlist 'Road Trip'\n"));
+    assert_eq!(cli.run(&["playlist", "3", "--tracks"], "").0, 2);
+    assert_eq!(cli.run(&["playlist", "1", "--all"], "").0, 2);
+    assert_eq!(cli.server.calls().len(), 2);
+}
+
+#[test]
+fn podcast_steps()
+{
+    let cli = Cli::new("podcast", MOCK_TOKEN);
+    let calls = |cli: &Cli| -> Vec<(String, Vec<serde_json::Value>)> {
+        cli.server.calls().into_iter()
+            .filter(|call| call.method != "isPodcast")
+            .map(|call| (call.method, call.arguments))
+            .collect()
+    };
+
+    cli.server.set_response("playback", "isPodcast", serde_json::json!(true));
+    assert!(cli.run(&["status"], "").1.contains("\npodcast: true\n"));
+    assert_eq!(cli.run(&["seek", "forward"], "").0, 0);
+    assert_eq!(cli.run(&["seek", "backward"], "").0, 0);
+    assert_eq!(cli.run(&["seek", "+5"], "").0, 0);
+    assert_eq!(cli.run(&["skip30"], "").0, 0);
+    assert_eq!(cli.run(&["back10"], "").0, 0);
+    assert_eq!(calls(&cli), vec![
+        ("getPlaybackState".to_string(), vec![]),
+        ("forwardThirty".to_string(), vec![]),
+        ("rewindTen".to_string(), vec![]),
+        // 1:05 + 30s - 10s + 5s
+        ("setCurrentTime".to_string(), vec![serde_json::json!(90000)]),
+        ("forwardThirty".to_string(), vec![]),
+        ("rewindTen".to_string(), vec![]),
+    ]);
+
+    // a GPMDP without isPodcast steps 10s and leaves it out of the status
+    cli.server.set_response("playback", "isPodcast", serde_json::Value::Null);
+    let (code, out) = cli.run(&["status"], "");
+    assert_eq!(code, 0);
+    assert!(!out.contains("podcast"), "{}", out);
+    assert_eq!(cli.run(&["seek", "forward"], "").0, 0);
+    assert_eq!(calls(&cli).last(), Some(&("setCurrentTime".to_string(), vec![serde_json::json!(120000)])));
+}
+
+#[test]
+fn json_output()
+{
+    let cli = Cli::new("json", MOCK_TOKEN);
+    let json = |out: &str| -> serde_json::Value { serde_json::from_str(out).unwrap() };
+
+    let (code, out) = cli.run(&["--json", "status"], "");
+    assert_eq!(code, 0, "{}", out);
+    let status = json(&out);
+    assert_eq!(status["title"], "Second Song");
+    assert_eq!(status["time_elapsed_secs"], 65);
+    assert_eq!(status["podcast"], false);
+    assert!(status.get("album_artist").is_none(), "{}", status);
+
+    let (_, out) = cli.run(&["--json", "queue"], "");
+    assert_eq!(json(&out)[2], serde_json::json!({
+        "num": 3, "title": "Third Song", "artist": "Mock Artist",
+        "album": "Mock Album", "duration_secs": 200,
+    }));
+    let (_, out) = cli.run(&["--ndjson", "queue"], "");
+    assert_eq!(out.lines().map(|line| json(line)["num"].clone()).collect::<Vec<_>>(),
+               vec![1, 2, 3]);
+
+    let (_, out) = cli.run(&["--ndjson", "playlists"], "");
+    assert_eq!(json(out.lines().last().unwrap()),
+               serde_json::json!({ "num": 2, "name": "Road Trip", "tracks": 1 }));
+
+    let (_, out) = cli.run(&["--ndjson", "search", "mock"], "");
+    let hits: Vec<serde_json::Value> = out.lines().map(json).collect();
+    assert_eq!(hits.iter().map(|hit| hit["type"].as_str().unwrap()).collect::<Vec<_>>(),
+               vec!["artist", "album", "track", "track"]);
+    assert_eq!(hits[1], serde_json::json!({
+        "type": "album", "num": 2, "name": "Mock Album", "artist": "Mock Artist",
+    }));
+
+    assert_eq!(cli.run(&["--json", "volume"], ""), (0, "50\n".to_string()));
+    assert_eq!(cli.run(&["--json", "next"], ""), (0, "null\n".to_string()));
+    assert_eq!(cli.run(&["--ndjson", "next"], ""), (0, String::new()));
+
+    let (code, out) = cli.run(&["--json", "volume", "loud"], "");
+    assert_eq!(code, 2);
+    assert_eq!(json(&out), serde_json::json!({
+        "error": {
+            "kind": "invalid_argument",
+            "message": "failed to parse volume level",
+            "exit_code": 2,
+        }
+    }));
+}
+
+#[test]
+fn format_templates()
+{
+    let cli = Cli::new("format", MOCK_TOKEN);
+
+    let (code, out) = cli.run(&["--format", "%artist% - %title% [%time_elapsed_fmt%/%time_total_fmt%]\
+                                             [ #%index%][ (%album_art%)]",
+                                "status"], "");
+    assert_eq!((code, out.as_str()), (0, "Mock Artist - Second Song 1:05/3:20 #2\n"));
+    let (code, out) = cli.run(&["--format", "%artist% - %title% [%elapsed%/%total%]", "status"], "");
+    assert_eq!((code, out.as_str()), (0, "Mock Artist - Second Song 1:05/3:20\n"));
+    assert_eq!(cli.run(&["--format", "%title%: %lyrics%", "status"], ""),
+               (0, "Second Song: la la la\n".to_string()));
+    assert_eq!(cli.run(&["--format", "%title% (%mood%)", "status"], ""),
+               (2, "ERROR: invalid format '%title% (%mood%)': unknown field '%mood%'\n".to_string()));
+
+    let mut config = fs::read_to_string(&cli.config).unwrap();
+    config.push_str("format:\n  queue: '%num%. %title%[ (%album_artist%)]'\n");
+    fs::write(&cli.config, config).unwrap();
+    assert_eq!(cli.run(&["queue"], "").1,
+               "1. First Song (Mock Artist)\n\
+                2. Second Song (Mock Artist)\n\
+                3. Third Song (Mock Artist)\n");
+    assert_eq!(cli.run(&["--format", "%title%", "playlist", "2", "--tracks"], "").1,
+               "Third Song\n");
+    assert!(cli.run(&["--ndjson", "queue"], "").1.starts_with("{\"num\":1,"));
+
+    let (code, out) = cli.run(&["--format", "[%title%", "status"], "");
+    assert_eq!((code, out.as_str()), (2, "ERROR: invalid format '[%title%': missing ']'\n"));
+    assert_eq!(cli.run(&["--json", "--format", "%title%", "status"], "").0, 2);
+}
+
+#[test]
+fn i3bar_clicks()
+{
+    let cli = Cli::new("i3bar", MOCK_TOKEN);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gpmdp_rc"))
+        .arg("-c").arg(&cli.config)
+        .args(["bar", "--protocol", "i3bar", "--label", "%state% %title% %volume%"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap()
+        .write_all(b"[\n{\"name\":\"gpmdp_rc\",\"button\":4}\n\
+                     ,{\"name\":\"other\",\"button\":3}\n\
+                     ,{\"name\":\"gpmdp_rc\",\"button\":1}\n")
+        .unwrap();
+
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines().map(Result::unwrap);
+    assert_eq!(lines.next().unwrap(), "{\"version\":1,\"click_events\":true}");
+    assert_eq!(lines.next().unwrap(), "[");
+    assert_eq!(lines.next().unwrap(),
+               "[{\"name\":\"gpmdp_rc\",\"full_text\":\"playing Second Song 50\",\
+                 \"short_text\":\"Second Song\"}]");
+    let paused = lines.find(|line| line.contains("paused")).unwrap();
+    assert!(paused.starts_with(",[{") && paused.contains("\"paused Second Song 55\""), "{}", paused);
+
+    let methods: Vec<String> = cli.server.calls().into_iter().map(|call| call.method).collect();
+    assert_eq!(methods, vec!["increaseVolume", "playPause"]);
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[test]
+fn shell()
+{
+    let cli = Cli::new("shell", MOCK_TOKEN);
+    let home = std::env::temp_dir().join(format!("gpmdp_rc_test_{}_home", std::process::id()));
+    fs::create_dir_all(&home).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gpmdp_rc"))
+        .arg("-c").arg(&cli.config)
+        .arg("shell")
+        .env("HOME", &home)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap()
+        .write_all(b"volume 30\n\
+                     volume\n\
+                     \n\
+                     search 'mock song'\n\
+                     results 3\n\
+                     watch\n\
+                     thumbs\n\
+                     quit\n\
+                     volume\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(),
+               "30\n\
+                1: Mock Artist\n\
+                2: Mock Artist | Mock Album\n\
+                3: Mock Artist | Mock Album | First Song\n\
+                4: Mock Artist | Mock Album | Second Song\n\
+                ERROR: 'watch' can't be run from the shell\n\
+                ERROR: must provide thumbs rating\n");
+
+    let calls = cli.server.calls();
+    let methods: Vec<&str> = calls.iter().map(|call| call.method.as_str()).collect();
+    assert_eq!(methods, vec!["setVolume", "getVolume", "performSearch", "playResult"]);
+    assert_eq!(calls[2].arguments, vec![serde_json::json!("mock song")]);
+    assert_eq!(calls[3].arguments[0]["id"], "mock-track-1");
+
+    let history = fs::read_to_string(home.join(".gpmdp_rc_history")).unwrap();
+    assert!(history.ends_with("\nthumbs\nquit\n"), "{}", history);
+    fs::remove_dir_all(&home).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn daemon()
+{
+    let cli = Cli::new("daemon", MOCK_TOKEN);
+    let runtime = std::env::temp_dir().join(format!("gpmdp_rc_test_{}_runtime", std::process::id()));
+    fs::create_dir_all(&runtime).unwrap();
+    let socket = runtime.join("gpmdp_rc.sock");
+    let history = runtime.join("history.ndjson");
+    let mut config = fs::OpenOptions::new().append(true).open(&cli.config).unwrap();
+    writeln!(config, "history: {}", history.display()).unwrap();
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_gpmdp_rc"))
+        .arg("-c").arg(&cli.config)
+        .arg("daemon")
+        .env("XDG_RUNTIME_DIR", &runtime)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    for _ in 0..100 {
+        if socket.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    // commands reach GPMDP through the daemon, not the config's url
+    let nowhere = runtime.join("nowhere.yaml");
+    fs::write(&nowhere, "url: ws://127.0.0.1:1\n").unwrap();
+    let run = |args: &[&str]| -> (i32, String) {
+        let output = Command::new(env!("CARGO_BIN_EXE_gpmdp_rc"))
+            .arg("-c").arg(&nowhere)
+            .args(args)
+            .env("XDG_RUNTIME_DIR", &runtime)
+            .output()
+            .unwrap();
+        return (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap());
+    };
+
+    assert_eq!(run(&["volume", "30"]), (0, "".to_string()));
+    assert_eq!(run(&["--ndjson", "playlists"]),
+               (0, "{\"num\":1,\"name\":\"Mock Playlist\",\"tracks\":3}\n\
+                    {\"num\":2,\"name\":\"Road Trip\",\"tracks\":1}\n".to_string()));
+    assert_eq!(run(&["--json", "results", "9"]),
+               (2, "{\"error\":{\"kind\":\"invalid_argument\",\
+                    \"message\":\"invalid result number 9\",\"exit_code\":2}}\n".to_string()));
+    assert_eq!(cli.last_call().method, "setVolume");
+
+    // commands that aren't run by the daemon use the config's url
+    assert_eq!(run(&["watch"]).0, 4);
+    for name in &["watch", "auth"] {
+        let mut stream = std::os::unix::net::UnixStream::connect(&socket).unwrap();
+        writeln!(stream, "[\"{}\"]", name).unwrap();
+        let mut reply = String::new();
+        BufReader::new(&stream).read_line(&mut reply).unwrap();
+        let reply: serde_json::Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply["error"]["message"], format!("'{}' can't be run by the daemon", name));
+    }
+
+    // the second half of Second Song, from 1:05, is a listen
+    for secs in (70..=165).step_by(5) {
+        cli.server.push("time", serde_json::json!({ "current": secs * 1000, "total": 200000 }));
+    }
+    for _ in 0..100 {
+        if fs::read_to_string(&history).is_ok_and(|s| !s.is_empty()) {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let (code, out) = cli.run(&["history", "scrobbler-log"], "");
+    assert_eq!(code, 0, "{}", out);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", out);
+    assert!(lines[3].starts_with("Mock Artist\tMock Album\tSecond Song\t2\t200\tL\t"), "{}", out);
+    let (code, out) = cli.run(&["history", "listenbrainz"], "");
+    assert_eq!(code, 0, "{}", out);
+    let submission: serde_json::Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(submission["payload"][0]["track_metadata"]["track_name"], "Second Song");
+    assert_eq!(cli.run(&["history"], ""),
+               (2, "ERROR: usage: history scrobbler-log | listenbrainz\n".to_string()));
+
+    // only kept when asked for, --history for a config without it
+    assert_eq!(run(&["history", "listenbrainz"]),
+               (3, "ERROR: config: no history is kept, set 'history' in the config file or \
+                    give --history\n".to_string()));
+    let (code, out) = run(&["--history", history.to_str().unwrap(), "history", "listenbrainz"]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&out).unwrap(), submission);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gpmdp_rc"))
+        .arg("-c").arg(&cli.config)
+        .arg("daemon")
+        .env("XDG_RUNTIME_DIR", &runtime)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(),
+               format!("ERROR: config: a daemon is already listening on {}\n", socket.display()));
+    assert_eq!(cli.run(&["daemon"], ""),
+               (3, "ERROR: config: XDG_RUNTIME_DIR is not set\n".to_string()));
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+    fs::remove_dir_all(&runtime).unwrap();
+}
+
+#[test]
+fn daemon_silent_client()
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let runtime = std::env::temp_dir().join(format!("gpmdp_rc_test_{}_silent", std::process::id()));
+    fs::create_dir_all(&runtime).unwrap();
+    let socket = runtime.join("gpmdp_rc.sock");
+    let config = runtime.join("config.yaml");
+    fs::write(&config, format!("url: {}\ntoken: {}\n", server.url(), MOCK_TOKEN)).unwrap();
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_gpmdp_rc"))
+        .arg("-c").arg(&config)
+        .arg("daemon")
+        .env("XDG_RUNTIME_DIR", &runtime)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut silent = None;
+    for _ in 0..100 {
+        silent = std::os::unix::net::UnixStream::connect(&socket).ok();
+        if silent.is_some() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(silent.is_some(), "the daemon didn't listen on {}", socket.display());
+
+    // a client that never sends its request doesn't keep the daemon up
+    // once GPMDP is gone
+    drop(server);
+    let mut status = None;
+    for _ in 0..500 {
+        status = daemon.try_wait().unwrap();
+        if status.is_some() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    if status.is_none() {
+        let _ = daemon.kill();
+    }
+    assert_eq!(status.and_then(|status| status.code()), Some(4));
+    assert!(!socket.exists());
+    fs::remove_dir_all(&runtime).unwrap();
+}
+
+#[cfg(feature = "mpris")]
+#[test]
+fn mpris()
+{
+    use zbus::blocking::proxy::Builder;
+    use zbus::proxy::CacheProperties;
+    use std::convert::TryFrom;
+    use zbus::blocking::{fdo::PropertiesProxy, Proxy};
+
+    // a private session bus
+    let mut bus = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address=1"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("the mpris test needs dbus-daemon");
+    let mut address = String::new();
+    BufReader::new(bus.stdout.take().unwrap()).read_line(&mut address).unwrap();
+    let address = address.trim().to_string();
+    assert!(!address.is_empty(), "dbus-daemon didn't start");
+
+    let cli = Cli::new("mpris", MOCK_TOKEN);
+    let mut service = Command::new(env!("CARGO_BIN_EXE_gpmdp_rc"))
+        .arg("-c").arg(&cli.config)
+        .arg("mpris")
+        .env("DBUS_SESSION_BUS_ADDRESS", &address)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let conn = zbus::blocking::connection::Builder::address(address.as_str()).unwrap()
+        .build()
+        .unwrap();
+    let player: Proxy = Builder::new(&conn)
+        .destination("org.mpris.MediaPlayer2.gpmdp_rc").unwrap()
+        .path("/org/mpris/MediaPlayer2").unwrap()
+        .interface("org.mpris.MediaPlayer2.Player").unwrap()
+        .cache_properties(CacheProperties::No)
+        .build()
+        .unwrap();
+    for _ in 0..100 {
+        if player.get_property::<String>("PlaybackStatus").is_ok() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    assert_eq!(player.get_property::<String>("PlaybackStatus").unwrap(), "Playing");
+    assert_eq!(player.get_property::<String>("LoopStatus").unwrap(), "None");
+    assert_eq!(player.get_property::<f64>("Volume").unwrap(), 0.5);
+    assert_eq!(player.get_property::<i64>("Position").unwrap(), 65_000_000);
+    let metadata: std::collections::HashMap<String, zbus::zvariant::OwnedValue> =
+        player.get_property("Metadata").unwrap();
+    assert_eq!(String::try_from(metadata["xesam:title"].try_clone().unwrap()).unwrap(),
+               "Second Song");
+    assert_eq!(i64::try_from(metadata["mpris:length"].try_clone().unwrap()).unwrap(),
+               200_000_000);
+
+    player.call_method("PlayPause", &()).unwrap();
+    assert_eq!(cli.last_call().method, "playPause");
+    player.set_property("LoopStatus", "Playlist").unwrap();
+    let call = cli.last_call();
+    assert_eq!((call.method.as_str(), &call.arguments[0]), ("setRepeat", &serde_json::json!("LIST_REPEAT")));
+    assert!(player.set_property("LoopStatus", "Sometimes").is_err());
+    let last_call = || {
+        let call = cli.last_call();
+        return (call.method, call.arguments);
+    };
+    player.set_property("Shuffle", true).unwrap();
+    assert_eq!(last_call(), ("setShuffle".to_string(), vec![serde_json::json!("ALL_SHUFFLE")]));
+
+    player.call_method("Next", &()).unwrap();
+    assert_eq!(cli.last_call().method, "forward");
+    player.call_method("Previous", &()).unwrap();
+    assert_eq!(cli.last_call().method, "rewind");
+
+    // seeking is in microseconds, GPMDP's times in milliseconds, from the
+    // start of the track Previous went back to
+    player.call_method("Seek", &(10_000_000i64)).unwrap();
+    assert_eq!(last_call(), ("setCurrentTime".to_string(), vec![serde_json::json!(10_000)]));
+    let track_id = metadata["mpris:trackid"].try_clone().unwrap();
+    let track_id = zbus::zvariant::OwnedObjectPath::try_from(track_id).unwrap();
+    player.call_method("SetPosition", &(&track_id, 30_000_000i64)).unwrap();
+    assert_eq!(last_call(), ("setCurrentTime".to_string(), vec![serde_json::json!(30_000)]));
+
+    // a position for a track no longer playing is ignored
+    let calls = cli.server.calls().len();
+    let other = "/org/mpris/MediaPlayer2/gpmdp_rc/track/t0";
+    let other = zbus::zvariant::ObjectPath::try_from(other).unwrap();
+    player.call_method("SetPosition", &(&other, 10_000_000i64)).unwrap();
+    assert_eq!(cli.server.calls().len(), calls);
+
+    // channel pushes are signaled
+    let properties = PropertiesProxy::builder(&conn)
+        .destination("org.mpris.MediaPlayer2.gpmdp_rc").unwrap()
+        .path("/org/mpris/MediaPlayer2").unwrap()
+        .build()
+        .unwrap();
+    let mut changes = properties.receive_properties_changed().unwrap();
+    cli.server.push("volume", serde_json::json!(20));
+    let changed = changes.next().unwrap();
+    let args = changed.args().unwrap();
+    assert_eq!(args.interface_name().as_str(), "org.mpris.MediaPlayer2.Player");
+    assert_eq!(f64::try_from(&args.changed_properties()["Volume"]).unwrap(), 0.2);
+
+    let _ = service.kill();
+    let _ = service.wait();
+    let _ = bus.kill();
+    let _ = bus.wait();
+}
@@ -0,0 +1,234 @@
+//! GpmdpClient against the mock GPMDP.
+
+#![allow(clippy::needless_return)]
+
+use std::time::Duration;
+use gpmdp_rc::mock::*;
+use gpmdp_rc::*;
+
+/// Every channel the mock pushes, the ones CHNLS_ALL leaves out included.
+const MOCK_CHNLS: u64 = events::CHNLS_ALL |
+                        events::CHNL_API_VERSION |
+                        events::CHNL_LIBRARY |
+                        events::CHNL_SETTINGS_THEMECOLOR |
+                        events::CHNL_SETTINGS_THEME |
+                        events::CHNL_SETTINGS_THEMETYPE;
+
+fn connect() -> (MockServer, GpmdpClient)
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+    return (server, client);
+}
+
+#[test]
+fn channels_fill_state()
+{
+    let (_server, client) = connect();
+    client.wait_for_channels(events::CHNLS_ALL).unwrap();
+
+    let state = client.state();
+    assert!(state.cur_playing);
+    assert_eq!(state.cur_track.title(), "Second Song");
+    assert_eq!(state.cur_volume, 50);
+    assert_eq!(state.queue_position(), 2);
+    assert_eq!(state.cur_playlists.len(), 2);
+    assert_eq!(state.cur_search.len(), 4);
+    assert_eq!(client.get_playback_state().unwrap(), PlaybackState::Playing);
+}
+
+#[test]
+fn calls_push_changes()
+{
+    let (server, client) = connect();
+    client.wait_for_channels(MOCK_CHNLS).unwrap();
+    let events = client.subscribe();
+
+    client.play_pause().unwrap();
+    assert_eq!(events.recv_timeout(Duration::from_secs(4)).unwrap(),
+               ChannelEvent::PlayState(false));
+    assert_eq!(client.get_playback_state().unwrap(), PlaybackState::Paused);
+
+    client.set_volume(120).unwrap();
+    assert_eq!(client.get_volume().unwrap(), 100);
+
+    server.push("volume", serde_json::json!(7));
+    assert_eq!(events.recv_timeout(Duration::from_secs(4)).unwrap(),
+               ChannelEvent::Volume(100));
+    assert_eq!(events.recv_timeout(Duration::from_secs(4)).unwrap(),
+               ChannelEvent::Volume(7));
+}
+
+#[test]
+fn podcast_asked_once_per_track()
+{
+    let (server, client) = connect();
+    client.wait_for_channels(MOCK_CHNLS).unwrap();
+    let events = client.subscribe();
+    let asked = || server.calls().iter().filter(|call| call.method == "isPodcast").count();
+
+    assert!(!client.is_podcast().unwrap());
+    server.set_response("playback", "isPodcast", serde_json::json!(true));
+    assert!(!client.is_podcast().unwrap());
+    assert_eq!(asked(), 1);
+
+    // the next track is asked about again
+    client.forward().unwrap();
+    while !matches!(events.recv_timeout(Duration::from_secs(4)).unwrap(), ChannelEvent::Track(_)) {}
+    assert!(client.is_podcast().unwrap());
+    assert_eq!(asked(), 2);
+}
+
+#[test]
+fn errors()
+{
+    let (server, client) = connect();
+
+    server.set_response("volume", "getVolume", serde_json::json!("loud"));
+    assert_eq!(client.get_volume().unwrap_err().exit_code(), 7);
+    assert_eq!(client.play_queue_track(0).unwrap_err(),
+               GpmdpError::InvalidArgument("invalid track number 0".to_string()));
+
+    // the mock asks for a code before pushing any channel
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate("bad-token").unwrap();
+    let e = client.wait_for_channels(events::CHNL_TRACK | events::CHNL_QUEUE).unwrap_err();
+    assert!(matches!(e, GpmdpError::Auth(_)), "unexpected error {}", e);
+    assert_eq!(e.exit_code(), 5);
+    assert!(matches!(client.play_pause(), Err(GpmdpError::Server(_))));
+}
+
+#[test]
+fn sparse_models()
+{
+    let mut script = MockScript::default();
+    script.channels.insert("queue".to_string(), serde_json::json!([
+        { "id": "t1", "title": "Untitled", "artist": null, "index": null, "duration": null },
+        {},
+    ]));
+    script.channels.insert("playlists".to_string(), serde_json::json!([
+        { "id": "p1", "name": "Empty", "tracks": null },
+        { "id": "p2" },
+    ]));
+    script.channels.insert("search-results".to_string(), serde_json::json!({
+        "bestMatch": null,
+        "albums": null,
+        "tracks": [{ "title": "Only", "albumArt": null }],
+    }));
+    let server = MockServer::start(script).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+
+    let queue = client.queue_tracks().unwrap();
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue[0].title(), "Untitled");
+    assert_eq!((queue[0].artist.as_deref(), queue[0].index, queue[0].duration), (None, None, None));
+    assert_eq!(queue[1], Track::default());
+
+    let playlists = client.playlists().unwrap();
+    assert_eq!(playlists[0].name(), "Empty");
+    assert!(playlists[0].tracks.is_empty());
+    assert_eq!((playlists[1].name(), playlists[1].tracks.len()), ("", 0));
+
+    let results = client.search("only").unwrap();
+    assert_eq!(results.search_text.as_deref(), Some("only"));
+    assert_eq!(results.best_match, None);
+    assert_eq!((results.artists.len(), results.albums.len()), (0, 0));
+    assert_eq!(results.get(1), Some(SearchResult::Track(Track {
+        title: Some("Only".to_string()),
+        ..Track::default()
+    })));
+
+    // a sparse track is handed back with what GPMDP sent, less its nulls
+    client.play_queue_track(1).unwrap();
+    let call = server.calls().pop().unwrap();
+    assert_eq!(call.method, "playTrack");
+    assert_eq!(call.arguments[0], serde_json::json!({ "id": "t1", "title": "Untitled" }));
+    client.play_queue_track(2).unwrap();
+    assert_eq!(server.calls().pop().unwrap().arguments[0], serde_json::json!({}));
+
+    // and unchanged when there are none
+    let partial = serde_json::json!({ "id": "t9", "title": "Partial", "durationText": "3:20" });
+    let track: Track = serde_json::from_value(partial.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&track).unwrap(), partial);
+    let partial = serde_json::json!({ "name": "Mock Artist", "albums": [
+        { "id": "a1", "tracks": [] },
+    ] });
+    let artist: Artist = serde_json::from_value(partial.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&artist).unwrap(), partial);
+}
+
+#[test]
+fn requests_in_flight()
+{
+    let (_server, client) = connect();
+
+    // sent together, waited for in the other order
+    let requests = vec![
+        client.send_request("volume", "getVolume", "").unwrap(),
+        client.send_request("playback", "getPlaybackState", "").unwrap(),
+        client.send_request("playback", "isPodcast", "").unwrap(),
+        client.send_request("playback", "getShuffle", "").unwrap(),
+    ];
+    let mut ids: Vec<u32> = requests.iter().map(|request| request.request_id()).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), 4);
+    let values: Vec<serde_json::Value> = requests.into_iter().rev()
+        .map(|request| request.wait_value().unwrap())
+        .collect();
+    assert_eq!(values, vec![serde_json::json!("NO_SHUFFLE"), serde_json::json!(false),
+                            serde_json::json!(2), serde_json::json!(50)]);
+
+    // and from threads sharing the client, each getting its own answers
+    let answers = [
+        ("volume", "getVolume", serde_json::json!(50)),
+        ("playback", "isPodcast", serde_json::json!(false)),
+        ("playback", "getShuffle", serde_json::json!("NO_SHUFFLE")),
+        ("playback", "getRepeat", serde_json::json!("NO_REPEAT")),
+    ];
+    std::thread::scope(|scope| {
+        for (namespace, method, answer) in &answers {
+            let client = &client;
+            scope.spawn(move || {
+                for _ in 0..25 {
+                    let value = client.send_request(namespace, method, "").unwrap().wait_value();
+                    assert_eq!(&value.unwrap(), answer);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn malformed_pushes()
+{
+    let mut script = MockScript::default();
+    script.channels.insert("track".to_string(), serde_json::json!("Second Song"));
+    let server = MockServer::start(script).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+
+    // a channel that never decoded fails instead of timing out
+    let e = client.wait_for_channels(events::CHNL_TRACK | events::CHNL_VOLUME).unwrap_err();
+    assert!(matches!(e, GpmdpError::Decode(_)), "unexpected error {}", e);
+    assert_eq!(e.exit_code(), 7);
+    client.wait_for_channels(MOCK_CHNLS & !events::CHNL_TRACK).unwrap();
+
+    // a bad push is no event and leaves the state as it was, until a good one
+    let events = client.subscribe();
+    server.push("volume", serde_json::json!("loud"));
+    server.push("time", serde_json::json!({ "current": "soon", "total": 200000 }));
+    server.push("shuffle", serde_json::json!("ALL_SHUFFLE"));
+    assert_eq!(events.recv_timeout(Duration::from_secs(4)).unwrap(),
+               ChannelEvent::Shuffle("ALL_SHUFFLE".to_string()));
+    assert!(matches!(client.wait_for_channels(events::CHNL_VOLUME), Err(GpmdpError::Decode(_))));
+    assert!(matches!(client.wait_for_channels(events::CHNL_TIME), Err(GpmdpError::Decode(_))));
+    assert_eq!(client.state().cur_volume, 50);
+    client.wait_for_channels(events::CHNL_SHUFFLE | events::CHNL_QUEUE).unwrap();
+
+    server.push("volume", serde_json::json!(30));
+    assert_eq!(events.recv_timeout(Duration::from_secs(4)).unwrap(), ChannelEvent::Volume(30));
+    client.wait_for_channels(events::CHNL_VOLUME).unwrap();
+}
@@ -0,0 +1,538 @@
+//! The command registry, with the built in commands and an added one.
+
+#![allow(clippy::needless_return)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use gpmdp_rc::commands::*;
+use gpmdp_rc::mock::*;
+use gpmdp_rc::*;
+
+struct Title;
+
+impl Command for Title
+{
+    fn name(&self) -> &'static str { "title" }
+
+    fn channels(&self, _args: &serde_json::Value) -> u64
+    {
+        return events::CHNL_TRACK;
+    }
+
+    fn run(&self,
+           client: &GpmdpClient,
+           _registry: &Registry,
+           _args: &serde_json::Value) -> Result<serde_json::Value>
+    {
+        return Ok(client.state().cur_track.title().into());
+    }
+}
+
+fn argv(args: &[&str]) -> Vec<String>
+{
+    return args.iter().map(|arg| arg.to_string()).collect();
+}
+
+#[test]
+fn parse_args()
+{
+    let registry = Registry::default();
+
+    let (command, args) = registry.parse(&argv(&["seek", "-3"])).unwrap();
+    assert_eq!(command.name(), "seek");
+    assert_eq!(args, serde_json::json!(-3000));
+
+    let (_, args) = registry.parse(&argv(&["repeat", "single"])).unwrap();
+    assert_eq!(args, serde_json::json!("SINGLE_REPEAT"));
+
+    for (bad, msg) in &[(&["nope"][..], "unknown command 'nope'"),
+                        (&["volume", "loud"][..], "failed to parse volume level"),
+                        (&["thumbs"][..], "must provide thumbs rating"),
+                        (&["next", "2"][..], "'next' takes no arguments")] {
+        match registry.parse(&argv(bad)) {
+            Err(e) => assert_eq!(e, GpmdpError::InvalidArgument(msg.to_string())),
+            Ok(_) => panic!("{:?} parsed", bad),
+        }
+    }
+}
+
+#[test]
+fn registered_command()
+{
+    let mut registry = Registry::default();
+    let builtins = registry.commands().count();
+    registry.register(Box::new(Title));
+    assert_eq!(registry.commands().count(), builtins + 1);
+    assert!(registry.usage().ends_with("\n  title"));
+
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+
+    let (command, args) = registry.parse(&argv(&["title"])).unwrap();
+    let output = execute(command, &client, &registry, &args).unwrap();
+    assert_eq!(command.render(&output), "Second Song");
+
+    let (command, args) = registry.parse(&argv(&["volume"])).unwrap();
+    let output = execute(command, &client, &registry, &args).unwrap();
+    assert_eq!(command.render(&output), "50");
+}
+
+#[test]
+fn front_ends()
+{
+    let mut registry = Registry::default();
+    registry.register_front_end("count", "[ <word> ]", Box::new(|registry, args| {
+        return match args {
+            [] => Ok(()),
+            _ => Err(GpmdpError::InvalidArgument(format!("{} commands", registry.commands().count()))),
+        };
+    }));
+    // after the commands, even the ones registered later
+    registry.register(Box::new(Title));
+    assert!(registry.usage().ends_with("\n  title\n  count [ <word> ]"), "{}", registry.usage());
+    assert!(registry.get("count").is_none());
+
+    assert_eq!(registry.run_front_end(&argv(&["count"])), Some(Ok(())));
+    assert_eq!(registry.run_front_end(&argv(&["count", "x"])),
+               Some(Err(GpmdpError::InvalidArgument(
+                   format!("{} commands", registry.commands().count())))));
+    assert_eq!(registry.run_front_end(&argv(&["status"])), None);
+    assert_eq!(registry.run_front_end(&[]), None);
+}
+
+#[test]
+fn watch_events()
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+    client.wait_for_channels(events::CHNL_TIME | events::CHNL_VOLUME).unwrap();
+
+    let registry = Registry::default();
+    let (command, args) = registry.parse(&argv(&["watch", "--channels", "time,volume",
+                                                 "--interval", "5"])).unwrap();
+    let (tx, rx) = mpsc::channel();
+    let next = || -> String {
+        let event = rx.recv_timeout(Duration::from_secs(4)).unwrap();
+        return command.render(&event);
+    };
+
+    thread::scope(|scope| {
+        let watcher = scope.spawn(|| {
+            execute_stream(command, &client, &registry, &args, &mut |event| tx.send(event).unwrap())
+        });
+
+        // the state so far, then the changes
+        assert_eq!(next(), "time: 1:05/3:20");
+        assert_eq!(next(), "volume: 50");
+        for (channel, payload) in &[("time", serde_json::json!({ "current": 66000, "total": 200000 })),
+                                    ("time", serde_json::json!({ "current": 70000, "total": 200000 })),
+                                    ("track", serde_json::json!({ "title": "Not Watched" })),
+                                    ("time", serde_json::json!({ "current": 3000, "total": 200000 })),
+                                    ("volume", serde_json::json!(50)),
+                                    ("volume", serde_json::json!(40))] {
+            server.push(channel, payload.clone());
+        }
+        assert_eq!(next(), "time: 1:10/3:20");
+        assert_eq!(next(), "time: 0:03/3:20");
+        assert_eq!(next(), "volume: 40");
+
+        drop(server);
+        match watcher.join().unwrap() {
+            Err(GpmdpError::Connection(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    });
+    assert!(rx.try_recv().is_err());
+}
+
+/*
+ * Lines up to and including the OK or ACK that ends an MPD response.
+ */
+fn response(lines: &mut impl Iterator<Item = String>) -> Vec<String>
+{
+    let mut response = Vec::new();
+    for line in lines {
+        let done = line.starts_with("OK") || line.starts_with("ACK ");
+        response.push(line);
+        if done {
+            break;
+        }
+    }
+    return response;
+}
+
+#[test]
+fn mpd_bridge()
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+
+    let listen = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let registry = Registry::default();
+    let (command, args) = registry.parse(&argv(&["mpd-bridge", "--listen", &listen])).unwrap();
+
+    thread::scope(|scope| {
+        let bridge = scope.spawn(|| execute(command, &client, &registry, &args));
+
+        let stream = (0..100)
+            .find_map(|_| {
+                thread::sleep(Duration::from_millis(20));
+                return TcpStream::connect(&listen).ok();
+            })
+            .unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(4))).unwrap();
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines().map(std::result::Result::unwrap);
+        let mut request = |line: &str| -> Vec<String> {
+            if !line.is_empty() {
+                writeln!(&stream, "{}", line).unwrap();
+            }
+            return response(&mut lines);
+        };
+
+        assert_eq!(request("")[0], "OK MPD 0.23.0");
+        assert_eq!(request("ping"), vec!["OK"]);
+        let status = request("status");
+        for line in &["volume: 50", "repeat: 0", "random: 0", "playlistlength: 3",
+                      "state: play", "song: 1", "time: 65:200", "elapsed: 65.000"] {
+            assert!(status.contains(&line.to_string()), "{:?}", status);
+        }
+        assert_eq!(request("currentsong"),
+                   vec!["file: mock-track-2", "Artist: Mock Artist", "Album: Mock Album",
+                        "AlbumArtist: Mock Artist", "Title: Second Song", "Time: 200",
+                        "duration: 200.000", "Pos: 1", "Id: 2", "OK"]);
+        assert_eq!(request("playlistinfo 2:").iter().filter(|l| l.starts_with("Title: ")).count(), 1);
+        assert_eq!(request("listplaylists")[2], "playlist: Road Trip");
+
+        let found = request("search title \"first\"");
+        assert_eq!(found[0], "file: mock-track-1");
+        assert_eq!(found.last().unwrap(), "OK");
+        assert_eq!(request("search artist mock").iter().filter(|l| l.starts_with("file: ")).count(), 2);
+
+        assert_eq!(request("play 0"), vec!["OK"]);
+        assert_eq!(request("setvol 101"), vec!["ACK [2@0] {setvol} Invalid volume value"]);
+        assert_eq!(request("load \"Nope\""), vec!["ACK [50@0] {load} No such playlist"]);
+        assert_eq!(request("command_list_ok_begin\nrepeat 1\nsingle 1\nrandom 1\ncommand_list_end"),
+                   vec!["list_OK", "list_OK", "list_OK", "OK"]);
+        let calls = server.calls();
+        let last: Vec<(&str, &serde_json::Value)> = calls[calls.len() - 3..].iter()
+            .map(|call| (call.method.as_str(), &call.arguments[0]))
+            .collect();
+        assert_eq!(last, vec![("setRepeat", &serde_json::json!("LIST_REPEAT")),
+                              ("setRepeat", &serde_json::json!("SINGLE_REPEAT")),
+                              ("setShuffle", &serde_json::json!("ALL_SHUFFLE"))]);
+
+        // idle reports what changed since the last one, then waits for pushes
+        assert_eq!(request("idle options"), vec!["changed: options", "OK"]);
+        writeln!(&stream, "idle mixer").unwrap();
+        server.push("volume", serde_json::json!(20));
+        assert_eq!(request("")[..], ["changed: mixer", "OK"]);
+        writeln!(&stream, "idle player").unwrap();
+        assert_eq!(request("noidle"), vec!["OK"]);
+
+        drop(server);
+        match bridge.join().unwrap() {
+            Err(GpmdpError::Connection(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    });
+}
+
+/*
+ * Send an HTTP request and return the status code and body of the answer.
+ */
+fn http(addr: &str, method: &str, path: &str, body: &str) -> (u16, String)
+{
+    return http_headers(addr, &format!("Host: {}\r\n", addr), method, path, body);
+}
+
+/*
+ * Send an HTTP request with 'headers' instead of the Host header.
+ */
+fn http_headers(addr: &str, headers: &str, method: &str, path: &str, body: &str) -> (u16, String)
+{
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "{} {} HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+           method, path, headers, body.len(), body).unwrap();
+    let mut response = String::new();
+    std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    return (head[9..12].parse().unwrap(), body.trim_end().to_string());
+}
+
+#[test]
+fn http_api()
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+
+    let listen = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let registry = Registry::default();
+    let (command, args) = registry.parse(&argv(&["http", "--listen", &listen])).unwrap();
+
+    thread::scope(|scope| {
+        let api = scope.spawn(|| execute(command, &client, &registry, &args));
+        for _ in 0..100 {
+            if TcpStream::connect(&listen).is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let get = |path: &str| -> serde_json::Value {
+            let (status, body) = http(&listen, "GET", path, "");
+            assert_eq!(status, 200, "{}", body);
+            return serde_json::from_str(&body).unwrap();
+        };
+
+        // the same JSON as --json
+        let status = get("/status");
+        assert_eq!(status["title"], "Second Song");
+        assert_eq!(status["volume"], 50);
+        assert_eq!(get("/queue").as_array().unwrap().len(), 3);
+        assert_eq!(get("/playlists/2")[0]["title"], "Third Song");
+        assert_eq!(get("/lyrics"), "la la la");
+        assert_eq!(get("/shuffle"), "off");
+        assert_eq!(get("/search?q=mock+artist")["artists"][0]["name"], "Mock Artist");
+        assert_eq!(server.calls().pop().unwrap().arguments[0], "mock artist");
+
+        assert_eq!(http(&listen, "PUT", "/volume", "30"), (204, "".to_string()));
+        assert_eq!(get("/volume"), 30);
+        assert_eq!(http(&listen, "PUT", "/repeat", "\"single\"").0, 204);
+        assert_eq!(server.calls().pop().unwrap().arguments[0], "SINGLE_REPEAT");
+        assert_eq!(http(&listen, "PUT", "/rating", "up").0, 204);
+        assert_eq!(server.calls().pop().unwrap().method, "toggleThumbsUp");
+
+        assert_eq!(http(&listen, "PUT", "/rating", "9"),
+                   (400, "{\"error\":{\"kind\":\"invalid_argument\",\"message\":\"rating must be 1-5\"}}"
+                         .to_string()));
+        assert_eq!(http(&listen, "POST", "/status", "").0, 405);
+        assert_eq!(http(&listen, "GET", "/nowhere", "").0, 404);
+
+        // web pages on other sites can't drive the player, not even by another name for us
+        let port = listen.rsplit_once(':').unwrap().1;
+        let other_site = format!("Host: {}\r\nOrigin: http://evil.example\r\n", listen);
+        assert_eq!(http_headers(&listen, &other_site, "PUT", "/volume", "0"),
+                   (403, "{\"error\":{\"kind\":\"invalid_argument\",\
+                          \"message\":\"requests from http://evil.example aren't allowed\"}}".to_string()));
+        let rebound = format!("Host: evil.example:{}\r\n", port);
+        assert_eq!(http_headers(&listen, &rebound, "PUT", "/volume", "0"),
+                   (403, format!("{{\"error\":{{\"kind\":\"invalid_argument\",\
+                                  \"message\":\"requests for host evil.example:{} aren't allowed\"}}}}",
+                                 port)));
+        assert_eq!(http_headers(&listen, "", "PUT", "/volume", "0").0, 403);
+        assert_eq!(http_headers(&listen, "Host: 127.0.0.1:1\r\n", "PUT", "/volume", "0").0, 403);
+        assert_eq!(get("/volume"), 30);
+
+        // the request head is bounded
+        let host = format!("Host: {}\r\n", listen);
+        let long = format!("{}X-Long: {}\r\n", host, "a".repeat(8 * 1024));
+        assert_eq!(http_headers(&listen, &long, "GET", "/volume", "").0, 431);
+        let many = format!("{}{}", host, "X-Many: a\r\n".repeat(100));
+        assert_eq!(http_headers(&listen, &many, "GET", "/volume", "").0, 431);
+        let path = format!("/volume?{}", "a".repeat(8 * 1024));
+        assert_eq!(http_headers(&listen, &host, "GET", &path, "").0, 414);
+        for host in &["localhost", "[::1]", "127.0.0.1"] {
+            let host = format!("{}:{}", host, port);
+            let headers = format!("Host: {}\r\nOrigin: http://{}\r\n", host, host);
+            assert_eq!(http_headers(&listen, &headers, "GET", "/volume", ""),
+                       (200, "30".to_string()));
+        }
+
+        // pushes on the watched channels, after the state so far
+        let mut stream = TcpStream::connect(&listen).unwrap();
+        write!(stream, "GET /events?channels=volume HTTP/1.1\r\nHost: {}\r\n\r\n", listen).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(4))).unwrap();
+        let mut lines = BufReader::new(stream).lines().map(std::result::Result::unwrap);
+        assert_eq!(lines.next().unwrap(), "HTTP/1.1 200 OK");
+        let mut event = || -> Vec<String> {
+            return lines.by_ref().take_while(|line| !line.is_empty()).collect();
+        };
+        assert!(event().contains(&"Content-Type: text/event-stream".to_string()));
+        assert_eq!(event(), vec!["event: volume", "data: {\"channel\":\"volume\",\"volume\":30}"]);
+        server.push("volume", serde_json::json!(20));
+        assert_eq!(event(), vec!["event: volume", "data: {\"channel\":\"volume\",\"volume\":20}"]);
+
+        drop(server);
+        match api.join().unwrap() {
+            Err(GpmdpError::Connection(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    });
+}
+
+/*
+ * An OSC message with int, float and string arguments.
+ */
+fn osc_message(address: &str, args: &[serde_json::Value]) -> Vec<u8>
+{
+    let put_string = |packet: &mut Vec<u8>, s: &str| {
+        packet.extend(s.as_bytes());
+        packet.extend(vec![0; 4 - s.len() % 4]);
+    };
+    let mut packet = Vec::new();
+    put_string(&mut packet, address);
+    let tags: String = args.iter()
+        .map(|arg| if arg.is_i64() { 'i' } else if arg.is_f64() { 'f' } else { 's' })
+        .collect();
+    put_string(&mut packet, &format!(",{}", tags));
+    for arg in args {
+        match arg {
+            serde_json::Value::String(s) => put_string(&mut packet, s),
+            arg if arg.is_i64() => packet.extend((arg.as_i64().unwrap() as i32).to_be_bytes()),
+            arg => packet.extend((arg.as_f64().unwrap() as f32).to_be_bytes()),
+        }
+    }
+    return packet;
+}
+
+/*
+ * Take an OSC string off the front of 'rest'.
+ */
+fn osc_string(rest: &mut &[u8]) -> String
+{
+    let end = rest.iter().position(|b| *b == 0).unwrap();
+    let s = String::from_utf8(rest[..end].to_vec()).unwrap();
+    *rest = &rest[(end + 4) / 4 * 4..];
+    return s;
+}
+
+/*
+ * The address and arguments of the next OSC message on 'socket'.
+ */
+fn osc_recv(socket: &UdpSocket) -> (String, Vec<serde_json::Value>)
+{
+    let mut buf = [0; 8192];
+    let len = socket.recv(&mut buf).unwrap();
+    let mut rest = &buf[..len];
+    let address = osc_string(&mut rest);
+    let tags = osc_string(&mut rest);
+    let mut args = Vec::new();
+    for tag in tags.chars().skip(1) {
+        if tag == 's' {
+            args.push(serde_json::json!(osc_string(&mut rest)));
+            continue;
+        }
+        let word = [rest[0], rest[1], rest[2], rest[3]];
+        rest = &rest[4..];
+        args.push(match tag {
+            'i' => serde_json::json!(i32::from_be_bytes(word)),
+            _ => serde_json::json!(f32::from_be_bytes(word)),
+        });
+    }
+    return (address, args);
+}
+
+#[test]
+fn osc()
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+
+    let rig = UdpSocket::bind("127.0.0.1:0").unwrap();
+    rig.set_read_timeout(Some(Duration::from_secs(4))).unwrap();
+    let target = rig.local_addr().unwrap().to_string();
+    let listen = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let registry = Registry::default();
+    let (command, args) = registry.parse(&argv(&["osc", "--listen", &listen, "--target", &target]))
+        .unwrap();
+    // a root prefix is no prefix, one that isn't an address is refused
+    let prefix = |prefix: &str| {
+        return registry.parse(&argv(&["osc", "--prefix", prefix]))
+            .map(|(_, args)| args["prefix"].clone());
+    };
+    assert_eq!(prefix("/").unwrap(), "");
+    assert_eq!(prefix("/stage/").unwrap(), "/stage");
+    assert!(prefix("gpmdp").is_err());
+    let next_message = |address: &str| -> Vec<serde_json::Value> {
+        loop {
+            let (got, args) = osc_recv(&rig);
+            if got == address {
+                return args;
+            }
+        }
+    };
+
+    thread::scope(|scope| {
+        let osc = scope.spawn(|| execute(command, &client, &registry, &args));
+
+        // the state so far
+        assert_eq!(next_message("/gpmdp/track")[0], "Second Song");
+        assert_eq!(next_message("/gpmdp/volume"), vec![serde_json::json!(0.5f32)]);
+
+        rig.send_to(&osc_message("/gpmdp/next", &[]), &listen).unwrap();
+        rig.send_to(&osc_message("/gpmdp/volume", &[serde_json::json!(0.3)]), &listen).unwrap();
+        assert_eq!(next_message("/gpmdp/volume"), vec![serde_json::json!(0.3f32)]);
+        let calls: Vec<_> = server.calls().into_iter().map(|call| (call.method, call.arguments)).collect();
+        assert!(calls.contains(&("forward".to_string(), vec![])), "{:?}", calls);
+        assert_eq!(calls.last().unwrap(), &("setVolume".to_string(), vec![serde_json::json!(30)]));
+
+        // a button let go does nothing
+        rig.send_to(&osc_message("/gpmdp/play", &[serde_json::json!(0)]), &listen).unwrap();
+        rig.send_to(&osc_message("/gpmdp/volume", &[serde_json::json!(40)]), &listen).unwrap();
+        assert_eq!(next_message("/gpmdp/volume"), vec![serde_json::json!(0.4f32)]);
+        assert!(!server.calls().iter().any(|call| call.method == "playPause"));
+
+        // pause pauses, and leaves a paused player alone
+        let play_pauses = || {
+            return server.calls().iter().filter(|call| call.method == "playPause").count();
+        };
+        rig.send_to(&osc_message("/gpmdp/pause", &[serde_json::json!(1)]), &listen).unwrap();
+        assert_eq!(next_message("/gpmdp/playing"), vec![serde_json::json!(0)]);
+        rig.send_to(&osc_message("/gpmdp/pause", &[serde_json::json!(1)]), &listen).unwrap();
+        rig.send_to(&osc_message("/gpmdp/volume", &[serde_json::json!(50)]), &listen).unwrap();
+        assert_eq!(next_message("/gpmdp/volume"), vec![serde_json::json!(0.5f32)]);
+        assert_eq!(play_pauses(), 1);
+
+        rig.send_to(&osc_message("/lights/dim", &[serde_json::json!(1)]), &listen).unwrap();
+        assert_eq!(next_message("/gpmdp/error"), vec![serde_json::json!("unknown address '/lights/dim'")]);
+        rig.send_to(&osc_message("/gpmdp/playlist", &[serde_json::json!("all")]), &listen).unwrap();
+        assert_eq!(next_message("/gpmdp/error"),
+                   vec![serde_json::json!("'playlist' takes a playlist number")]);
+
+        // errors also go back to a sender that isn't a target
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.set_read_timeout(Some(Duration::from_secs(4))).unwrap();
+        let shuffle = osc_message("/gpmdp/shuffle", &[serde_json::json!("on")]);
+        sender.send_to(&shuffle, &listen).unwrap();
+        let error = vec![serde_json::json!("'shuffle' takes a number")];
+        assert_eq!(osc_recv(&sender), ("/gpmdp/error".to_string(), error.clone()));
+        assert_eq!(next_message("/gpmdp/error"), error);
+
+        drop(server);
+        match osc.join().unwrap() {
+            Err(GpmdpError::Connection(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    });
+}
+
+#[test]
+fn osc_ipv6()
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+
+    // listening on every IPv6 address, woken up over ::1 when GPMDP goes away
+    let port = UdpSocket::bind("[::]:0").unwrap().local_addr().unwrap().port();
+    let (done_tx, done) = mpsc::channel();
+    thread::spawn(move || {
+        let registry = Registry::default();
+        let (command, args) = registry.parse(&argv(&["osc", "--listen", &format!("[::]:{}", port)]))
+            .unwrap();
+        let _ = done_tx.send(execute(command, &client, &registry, &args));
+    });
+    thread::sleep(Duration::from_millis(100));
+    drop(server);
+    match done.recv_timeout(Duration::from_secs(4)) {
+        Ok(Err(GpmdpError::Connection(_))) => {}
+        res => panic!("unexpected {:?}", res),
+    }
+}
@@ -0,0 +1,80 @@
+//! `--format` templates.
+
+#![allow(clippy::needless_return)]
+
+use gpmdp_rc::format::Template;
+use gpmdp_rc::*;
+
+/// The keys of the records rendered here, "nope" never being set.
+const FIELDS: [&str; 11] = [
+    "num", "title", "artist", "album", "album_artist", "play_count", "nope", "value",
+    "time_elapsed_fmt", "time_total_fmt", "queue_track",
+];
+
+fn render(template: &str, record: &serde_json::Value) -> String
+{
+    return Template::parse(template, &FIELDS).unwrap().render(record);
+}
+
+#[test]
+fn fields_and_groups()
+{
+    let track = serde_json::json!({
+        "num": 2,
+        "title": "Second Song",
+        "artist": "Mock Artist",
+        "album": "",
+        "album_artist": null,
+        "play_count": 0,
+    });
+
+    assert_eq!(render("%num%: %artist% - %title%", &track), "2: Mock Artist - Second Song");
+    assert_eq!(render("%title%[ (%album%)]", &track), "Second Song");
+    assert_eq!(render("%title%[ (%play_count%)]", &track), "Second Song (0)");
+    assert_eq!(render("[%album_artist%|%artist%] - %title%", &track),
+               "Mock Artist - Second Song");
+    assert_eq!(render("[%album%|%nope%]%title%", &track), "Second Song");
+    assert_eq!(render("%title%[ [%album%]/%num%]", &track), "Second Song /2");
+    assert_eq!(render("<%album%>", &track), "<>");
+    assert_eq!(render("\\[%num%\\]\\t100\\%", &track), "[2]\t100%");
+    assert_eq!(render("%value%", &serde_json::json!(50)), "50");
+}
+
+#[test]
+fn aliases()
+{
+    let status = serde_json::json!({
+        "title": "Second Song",
+        "artist": "Mock Artist",
+        "album_artist": "Mock Artist",
+        "time_elapsed_fmt": "1:05",
+        "time_total_fmt": "3:20",
+        "queue_track": 2,
+    });
+
+    assert_eq!(render("%artist% - %title% [%elapsed%/%total%]", &status),
+               "Mock Artist - Second Song 1:05/3:20");
+    assert_eq!(render("%position%. %albumartist% [%currenttime%|%totaltime%]", &status),
+               "2. Mock Artist 1:05");
+
+    // the first field an alias stands for that the command has
+    let track = Template::parse("%position%: %title%", &["num", "title"]).unwrap();
+    assert_eq!(track.render(&serde_json::json!({ "num": 3, "title": "Third Song" })), "3: Third Song");
+}
+
+#[test]
+fn parse_errors()
+{
+    for (template, msg) in &[("%title", "missing '%'"),
+                             ("[%title%", "missing ']'"),
+                             ("%title%]", "unexpected ']'"),
+                             ("%artist%|%title%", "'|' outside of '[ ]'"),
+                             ("100%%", "empty field name"),
+                             ("\\", "trailing '\\'"),
+                             ("%artist% [%lyrics%]", "unknown field '%lyrics%'"),
+                             ("%elapsed%", "unknown field '%elapsed%'")] {
+        assert_eq!(Template::parse(template, &["artist", "title"]),
+                   Err(GpmdpError::InvalidArgument(
+                       format!("invalid format '{}': {}", template, msg))));
+    }
+}
@@ -0,0 +1,136 @@
+//! The scrobble rules and the history's exports.
+
+#![allow(clippy::needless_return)]
+
+use std::fs;
+use gpmdp_rc::history::*;
+use gpmdp_rc::*;
+
+fn track(title: &str, duration: u64) -> ChannelEvent
+{
+    return ChannelEvent::Track(Track {
+        title: Some(title.to_string()),
+        artist: Some("Mock Artist".to_string()),
+        album: Some("Mock Album".to_string()),
+        index: Some(2),
+        duration: Some(duration),
+        ..Track::default()
+    });
+}
+
+/*
+ * Play from 'from' to 'to' secs of a 'total' secs track, a time push a
+ * second starting at 'now'. Returns the listens made.
+ */
+fn play(scrobbler: &mut Scrobbler, now: u64, from: u64, to: u64, total: u64) -> Vec<(u64, Listen)>
+{
+    return (from..=to)
+        .filter_map(|secs| {
+            let event = ChannelEvent::Time { current: secs * 1000, total: total * 1000 };
+            let at = now + secs - from;
+            return scrobbler.update(&event, at).map(|listen| (at, listen));
+        })
+        .collect();
+}
+
+#[test]
+fn scrobble_rules()
+{
+    let mut scrobbler = Scrobbler::new();
+
+    // half of a 200 sec track
+    assert_eq!(scrobbler.update(&track("Second Song", 200_000), 1000), None);
+    let listens = play(&mut scrobbler, 1000, 0, 150, 200);
+    assert_eq!(listens, vec![(1100, Listen {
+        listened_at: 1000,
+        artist: "Mock Artist".to_string(),
+        title: "Second Song".to_string(),
+        album: "Mock Album".to_string(),
+        track_number: Some(2),
+        duration: 200,
+    })]);
+
+    // played again after a listen, pushing the track again changes nothing
+    assert_eq!(scrobbler.update(&track("Second Song", 200_000), 1150), None);
+    assert_eq!(play(&mut scrobbler, 1200, 0, 100, 200)[0].1.listened_at, 1200);
+
+    // seeking past the middle isn't listening
+    scrobbler.update(&track("Third Song", 200_000), 2000);
+    assert!(play(&mut scrobbler, 2000, 0, 10, 200).is_empty());
+    assert!(play(&mut scrobbler, 2010, 150, 199, 200).is_empty());
+    assert_eq!(play(&mut scrobbler, 2060, 0, 50, 200).len(), 1);
+
+    // 4 minutes of a long track, nothing of a short one
+    scrobbler.update(&track("Long Song", 1_200_000), 3000);
+    assert_eq!(play(&mut scrobbler, 3000, 0, 600, 1200)[0].0, 3240);
+    scrobbler.update(&track("Jingle", 30_000), 4000);
+    assert!(play(&mut scrobbler, 4000, 0, 30, 30).is_empty());
+}
+
+#[test]
+fn exports()
+{
+    let listens = vec![
+        Listen {
+            listened_at: 1697625600,
+            artist: "Mock Artist".to_string(),
+            title: "Second\tSong".to_string(),
+            album: "Mock Album".to_string(),
+            track_number: Some(2),
+            duration: 200,
+        },
+        Listen {
+            listened_at: 1697625800,
+            artist: "Mock Artist".to_string(),
+            title: "Single".to_string(),
+            album: String::new(),
+            track_number: None,
+            duration: 95,
+        },
+    ];
+
+    let log = scrobbler_log(&listens);
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines[..2], ["#AUDIOSCROBBLER/1.1", "#TZ/UTC"]);
+    assert!(lines[2].starts_with("#CLIENT/gpmdp_rc "));
+    assert_eq!(lines[3], "Mock Artist\tMock Album\tSecond Song\t2\t200\tL\t1697625600\t");
+    assert_eq!(lines[4], "Mock Artist\t\tSingle\t\t95\tL\t1697625800\t");
+
+    let submission = listenbrainz(&listens);
+    assert_eq!(submission["listen_type"], "import");
+    let payload = submission["payload"].as_array().unwrap();
+    assert_eq!(payload[0]["listened_at"], 1697625600);
+    assert_eq!(payload[0]["track_metadata"]["track_name"], "Second\tSong");
+    assert_eq!(payload[0]["track_metadata"]["release_name"], "Mock Album");
+    assert_eq!(payload[0]["track_metadata"]["additional_info"]["duration_ms"], 200000);
+    assert_eq!(payload[0]["track_metadata"]["additional_info"]["tracknumber"], 2);
+    assert!(payload[1]["track_metadata"].get("release_name").is_none());
+}
+
+#[test]
+fn log()
+{
+    let dir = std::env::temp_dir().join(format!("gpmdp_rc_test_{}_history", std::process::id()));
+    let file = dir.join("gpmdp_rc/history.ndjson");
+    let file = file.to_str().unwrap();
+    let listen = Listen {
+        listened_at: 1697625600,
+        artist: "Mock Artist".to_string(),
+        title: "Second Song".to_string(),
+        album: "Mock Album".to_string(),
+        track_number: Some(2),
+        duration: 200,
+    };
+
+    // appended to, not replaced
+    Log::open(file).unwrap().append(&listen);
+    Log::open(file).unwrap().append(&listen);
+    assert_eq!(load(file).unwrap(), vec![listen.clone(), listen]);
+
+    fs::write(file, "{\"listened_at\":1}\n").unwrap();
+    match load(file) {
+        Err(GpmdpError::Decode(message)) => assert!(message.starts_with("history line 1: "), "{}", message),
+        res => panic!("unexpected {:?}", res),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
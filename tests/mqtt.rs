@@ -0,0 +1,210 @@
+//! The MQTT bridge, connected to a broker played by the test.
+
+#![cfg(feature = "mqtt")]
+#![allow(clippy::needless_return)]
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use gpmdp_rc::commands::*;
+use gpmdp_rc::mock::*;
+use gpmdp_rc::*;
+
+/*
+ * Just enough of an MQTT 3.1.1 broker for one client: it acks what it's
+ * sent and keeps the last message published on each topic.
+ */
+struct Broker
+{
+    stream: TcpStream,
+    messages: HashMap<String, (String, bool)>,
+    subscriptions: Vec<String>,
+    disconnected: bool,
+}
+
+fn string(body: &[u8]) -> (String, &[u8])
+{
+    let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    return (String::from_utf8(body[2..2 + len].to_vec()).unwrap(), &body[2 + len..]);
+}
+
+impl Broker
+{
+    fn read_packet(&mut self) -> (u8, Vec<u8>)
+    {
+        let mut byte = [0; 1];
+        self.stream.read_exact(&mut byte).unwrap();
+        let header = byte[0];
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            len |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        self.stream.read_exact(&mut body).unwrap();
+        return (header, body);
+    }
+
+    fn write_packet(&mut self, header: u8, body: &[u8])
+    {
+        let mut packet = vec![header];
+        let mut len = body.len();
+        loop {
+            let byte = (len & 0x7f) as u8;
+            len >>= 7;
+            packet.push(if len > 0 { byte | 0x80 } else { byte });
+            if len == 0 {
+                break;
+            }
+        }
+        packet.extend(body);
+        self.stream.write_all(&packet).unwrap();
+    }
+
+    /// Handle packets from the client until 'done' is true.
+    fn until(&mut self, done: impl Fn(&Broker) -> bool)
+    {
+        while !done(self) {
+            let (header, body) = self.read_packet();
+            match header >> 4 {
+                1 => self.write_packet(0x20, &[0, 0]),
+                3 => {
+                    let qos = (header >> 1) & 3;
+                    let (topic, rest) = string(&body);
+                    let (pkid, payload) = if qos > 0 { rest.split_at(2) } else { rest.split_at(0) };
+                    self.messages.insert(topic, (String::from_utf8(payload.to_vec()).unwrap(),
+                                                 header & 1 == 1));
+                    if qos > 0 {
+                        self.write_packet(0x40, pkid);
+                    }
+                }
+                8 => {
+                    let (topic, _) = string(&body[2..]);
+                    self.subscriptions.push(topic);
+                    self.write_packet(0x90, &[body[0], body[1], 1]);
+                }
+                12 => self.write_packet(0xd0, &[]),
+                14 => self.disconnected = true,
+                _ => {}
+            }
+        }
+    }
+
+    fn publish(&mut self, topic: &str, payload: &str)
+    {
+        let mut body = (topic.len() as u16).to_be_bytes().to_vec();
+        body.extend(topic.as_bytes());
+        body.extend(payload.as_bytes());
+        self.write_packet(0x30, &body);
+    }
+
+    fn message(&self, topic: &str) -> Option<&str>
+    {
+        return self.messages.get(topic).map(|(payload, _)| payload.as_str());
+    }
+}
+
+#[test]
+fn bridge()
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let broker_addr = listener.local_addr().unwrap().to_string();
+    let registry = Registry::default();
+    let argv: Vec<String> = ["mqtt", "--broker", &broker_addr, "--prefix", "home/gpmdp",
+                             "--discovery", "homeassistant"]
+        .iter().map(|arg| arg.to_string()).collect();
+    let (command, args) = registry.parse(&argv).unwrap();
+
+    thread::scope(|scope| {
+        let bridge = scope.spawn(|| execute(command, &client, &registry, &args));
+
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(4))).unwrap();
+        let mut broker = Broker { stream, messages: HashMap::new(), subscriptions: Vec::new(),
+                                  disconnected: false };
+
+        // the state so far, retained
+        let channels = ["playState", "track", "time", "volume", "shuffle", "repeat", "rating"];
+        broker.until(|b| channels.iter().all(|c| b.message(&format!("home/gpmdp/{}", c)).is_some()));
+        assert_eq!(broker.subscriptions, vec!["home/gpmdp/set/+"]);
+        assert_eq!(broker.messages["home/gpmdp/availability"], ("online".to_string(), true));
+        assert_eq!(broker.message("home/gpmdp/volume"), Some("{\"channel\":\"volume\",\"volume\":50}"));
+        assert_eq!(broker.message("home/gpmdp/playState"),
+                   Some("{\"channel\":\"playState\",\"playing\":true}"));
+        let track: serde_json::Value = serde_json::from_str(broker.message("home/gpmdp/track").unwrap()).unwrap();
+        assert_eq!(track["title"], "Second Song");
+
+        let config: serde_json::Value =
+            serde_json::from_str(broker.message("homeassistant/number/home_gpmdp/volume/config").unwrap())
+            .unwrap();
+        assert_eq!(config["command_topic"], "home/gpmdp/set/volume");
+        assert_eq!(config["availability_topic"], "home/gpmdp/availability");
+        assert_eq!(config["unique_id"], "home_gpmdp_volume");
+
+        // commands, and the pushes they cause
+        broker.publish("home/gpmdp/set/volume", "30");
+        broker.until(|b| b.message("home/gpmdp/volume") == Some("{\"channel\":\"volume\",\"volume\":30}"));
+        let call = server.calls().pop().unwrap();
+        assert_eq!((call.method.as_str(), &call.arguments[0]), ("setVolume", &serde_json::json!(30)));
+        broker.publish("home/gpmdp/set/repeat", "single");
+        broker.until(|b| b.message("home/gpmdp/repeat") == Some("{\"channel\":\"repeat\",\"repeat\":\"single\"}"));
+
+        // Home Assistant's buttons send PRESS
+        broker.publish("home/gpmdp/set/next", "PRESS");
+        broker.until(|b| b.message("home/gpmdp/track").unwrap_or("").contains("Third Song"));
+        assert_eq!(server.calls().pop().unwrap().method, "forward");
+
+        broker.publish("home/gpmdp/set/search", "mock");
+        broker.until(|b| b.message("home/gpmdp/error").is_some());
+        assert_eq!(broker.messages.remove("home/gpmdp/error").unwrap(),
+                   ("{\"command\":\"search\",\"error\":{\"kind\":\"invalid_argument\",\
+                     \"message\":\"unknown command 'search'\"}}".to_string(), false));
+        broker.publish("home/gpmdp/set/volume", "loud");
+        broker.until(|b| b.message("home/gpmdp/error").is_some());
+        let error = broker.message("home/gpmdp/error").unwrap();
+        assert!(error.contains("volume level"), "{}", error);
+
+        drop(server);
+        broker.until(|b| b.disconnected);
+        assert_eq!(broker.messages["home/gpmdp/availability"], ("offline".to_string(), true));
+        match bridge.join().unwrap() {
+            Err(GpmdpError::Connection(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    });
+}
+
+#[test]
+fn no_broker()
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+
+    // nothing listens on a port just closed
+    let broker_addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let registry = Registry::default();
+    let argv: Vec<String> = ["mqtt", "--broker", &broker_addr].iter().map(|arg| arg.to_string()).collect();
+    let (command, args) = registry.parse(&argv).unwrap();
+    match execute(command, &client, &registry, &args) {
+        Err(GpmdpError::Connection(message)) => {
+            assert!(message.starts_with(&format!("MQTT broker {}: ", broker_addr)), "{}", message)
+        }
+        res => panic!("unexpected {:?}", res),
+    }
+
+    let argv: Vec<String> = ["mqtt", "--broker", "localhost"].iter().map(|arg| arg.to_string()).collect();
+    assert_eq!(registry.parse(&argv).err(),
+               Some(GpmdpError::InvalidArgument("invalid broker 'localhost', expected <host>:<port>"
+                                                .to_string())));
+}
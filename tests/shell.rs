@@ -0,0 +1,24 @@
+//! Splitting shell lines into command words.
+
+#![allow(clippy::needless_return)]
+
+use gpmdp_rc::shell::split_words;
+use gpmdp_rc::*;
+
+#[test]
+fn words()
+{
+    let words = |line: &str| -> Vec<String> { split_words(line).unwrap() };
+
+    assert_eq!(words("  volume   30 "), vec!["volume", "30"]);
+    assert_eq!(words("search \"two words\""), vec!["search", "two words"]);
+    assert_eq!(words("search 'a\\b'c"), vec!["search", "a\\bc"]);
+    assert_eq!(words("search it\\'s \"\""), vec!["search", "it's", ""]);
+    assert_eq!(words("search a\\ b\"c d\""), vec!["search", "a bc d"]);
+    assert!(words(" ").is_empty());
+
+    assert_eq!(split_words("search \"open"),
+               Err(GpmdpError::InvalidArgument("unterminated quote".to_string())));
+    assert_eq!(split_words("seek \\"),
+               Err(GpmdpError::InvalidArgument("trailing '\\'".to_string())));
+}
@@ -0,0 +1,82 @@
+//! Recording a session against the mock GPMDP and replaying it.
+
+#![allow(clippy::needless_return)]
+
+use std::fs;
+use gpmdp_rc::mock::*;
+use gpmdp_rc::transcript::*;
+use gpmdp_rc::*;
+
+/*
+ * Record a session that seeks back 3 secs and reads the volume.
+ */
+fn record(name: &str) -> Vec<Frame>
+{
+    let file = std::env::temp_dir()
+        .join(format!("gpmdp_rc_test_{}_{}.ndjson", std::process::id(), name));
+    let file = file.to_str().unwrap();
+
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect_recorded(server.url(), Recorder::create(file).unwrap())
+        .unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+    client.seek(-3000).unwrap();
+    assert_eq!(client.get_volume().unwrap(), 50);
+    client.close();
+
+    let frames = load(file).unwrap();
+    fs::remove_file(file).unwrap();
+    return frames;
+}
+
+#[test]
+fn record_frames()
+{
+    let frames = record("frames");
+
+    let out: Vec<&serde_json::Value> = frames.iter()
+        .filter(|frame| frame.dir == Direction::Out)
+        .map(|frame| &frame.msg)
+        .collect();
+    assert_eq!(out.len(), 3);
+    assert_eq!(out[0]["arguments"], serde_json::json!(["gpmdp_rc", "<redacted>"]));
+    assert_eq!(out[1]["method"], "setCurrentTime");
+    assert_eq!(out[1]["arguments"], serde_json::json!([62000]));
+    assert_eq!(out[2]["method"], "getVolume");
+
+    assert!(frames.iter().any(|frame| {
+        frame.dir == Direction::In && frame.msg["channel"] == "queue"
+    }));
+    assert!(frames.windows(2).all(|w| w[0].ts <= w[1].ts));
+}
+
+#[test]
+fn replay()
+{
+    let frames = record("replay");
+
+    let client = GpmdpClient::replay(frames.clone());
+    client.authenticate("another-token").unwrap();
+    client.seek(-3000).unwrap();
+    assert_eq!(client.get_volume().unwrap(), 50);
+    assert_eq!(client.state().cur_track.title(), "Second Song");
+    client.close();
+
+    // a different seek than the recorded one
+    let client = GpmdpClient::replay(frames);
+    client.authenticate("").unwrap();
+    match client.seek(-4000) {
+        Err(GpmdpError::Decode(msg)) => assert!(msg.starts_with("replay: expected"), "{}", msg),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn parse_errors()
+{
+    assert_eq!(parse("\n").unwrap(), vec![]);
+    match parse("{\"ts\":1,\"dir\":\"in\",\"msg\":{}}\nnot json\n") {
+        Err(GpmdpError::Decode(msg)) => assert!(msg.starts_with("transcript line 2"), "{}", msg),
+        res => panic!("unexpected {:?}", res),
+    }
+}
@@ -0,0 +1,87 @@
+//! The terminal UI, drawn to a test backend and driven by key events.
+
+#![cfg(feature = "tui")]
+#![allow(clippy::needless_return)]
+
+use ratatui::backend::TestBackend;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::Terminal;
+use gpmdp_rc::mock::*;
+use gpmdp_rc::tui::*;
+use gpmdp_rc::*;
+
+fn key(code: KeyCode) -> KeyEvent
+{
+    return KeyEvent::new(code, KeyModifiers::NONE);
+}
+
+fn screen(terminal: &mut Terminal<TestBackend>, app: &mut App) -> String
+{
+    terminal.draw(|frame| app.draw(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    let width = buffer.area.width as usize;
+    let lines: Vec<String> = buffer.content.chunks(width)
+        .map(|row| row.iter().map(|cell| cell.symbol()).collect())
+        .collect();
+    return lines.join("\n");
+}
+
+#[test]
+fn panes_and_keys()
+{
+    let server = MockServer::start(MockScript::default()).unwrap();
+    let client = GpmdpClient::connect(server.url()).unwrap();
+    client.authenticate(MOCK_TOKEN).unwrap();
+    client.wait_for_channels(events::CHNLS_ALL).unwrap();
+    let methods = || -> Vec<String> {
+        return server.calls().into_iter().map(|call| call.method).collect();
+    };
+
+    let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+    let registry = commands::Registry::default();
+    let mut app = App::new(&client, &registry);
+
+    let text = screen(&mut terminal, &mut app);
+    assert!(text.contains("Playing: Second Song"), "{}", text);
+    assert!(text.contains("1:05 / 3:20"), "{}", text);
+    assert!(text.contains("  2 Mock Artist - Second Song"), "{}", text);
+
+    app.handle_key(key(KeyCode::Char('+')));
+    assert_eq!(methods().last().unwrap(), "increaseVolume");
+
+    // the third track of the queue
+    app.handle_key(key(KeyCode::Down));
+    app.handle_key(key(KeyCode::Down));
+    app.handle_key(key(KeyCode::Down));
+    app.handle_key(key(KeyCode::Enter));
+    let call = server.calls().pop().unwrap();
+    assert_eq!(call.method, "playTrack");
+    assert_eq!(call.arguments[0]["title"], "Third Song");
+
+    app.handle_key(key(KeyCode::Char('2')));
+    assert_eq!(app.pane(), Pane::Playlists);
+    let text = screen(&mut terminal, &mut app);
+    assert!(text.contains("  2 Road Trip [1]"), "{}", text);
+
+    app.handle_key(key(KeyCode::Char('4')));
+    let text = screen(&mut terminal, &mut app);
+    assert!(text.contains("la la la"), "{}", text);
+
+    app.handle_key(key(KeyCode::Char('r')));
+    assert_eq!(methods().last().unwrap(), "setRepeat");
+    assert_eq!(server.calls().pop().unwrap().arguments[0], "LIST_REPEAT");
+
+    app.handle_key(key(KeyCode::Char('/')));
+    for c in "road".chars() {
+        app.handle_key(key(KeyCode::Char(c)));
+    }
+    let text = screen(&mut terminal, &mut app);
+    assert!(text.contains("search: road_"), "{}", text);
+    app.handle_key(key(KeyCode::Enter));
+    assert_eq!(app.pane(), Pane::Search);
+    assert_eq!(methods().last().unwrap(), "performSearch");
+
+    assert!(!app.done());
+    app.handle_key(key(KeyCode::Char('q')));
+    assert!(app.done());
+}